@@ -5,18 +5,55 @@ mod sdl2_input;
 mod sdl2_display;
 mod sdl2_audio;
 
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::env;
 
 use nes_rust::Nes;
-use nes_rust::rom::Rom;
+use nes_rust::region::Region;
+use nes_rust::rewind::{DEFAULT_FRAME_INTERVAL, DEFAULT_MEMORY_BUDGET};
 
 use sdl2_input::Sdl2Input;
 use sdl2_display::Sdl2Display;
 use sdl2_audio::Sdl2Audio;
 
+// Writes battery-backed RAM every 5 seconds in case the process gets killed
+const BATTERY_RAM_SAVE_INTERVAL_FRAMES: u32 = 60 * 5;
+
+fn save_file_path(rom_path: &str) -> PathBuf {
+	Path::new(rom_path).with_extension("sav")
+}
+
+// Writes battery-backed RAM only if it has changed since the last write
+fn write_battery_ram(nes: &Nes, path: &Path, last_written: &mut Vec<u8>) {
+	if let Some(ram) = nes.battery_ram() {
+		if ram == &last_written[..] {
+			return;
+		}
+		match fs::write(path, ram) {
+			Ok(_) => *last_written = ram.to_vec(),
+			Err(e) => eprintln!("Failed to write {}: {}", path.display(), e)
+		}
+	}
+}
+
+// Parses "--region ntsc|pal|dendy" overriding the detected region
+fn parse_region(args: &[String]) -> Result<Option<Region>, String> {
+	let index = match args.iter().position(|arg| arg == "--region") {
+		Some(index) => index,
+		None => return Ok(None)
+	};
+	match args.get(index + 1).map(|arg| arg.to_lowercase()) {
+		Some(ref name) if name == "ntsc" => Ok(Some(Region::Ntsc)),
+		Some(ref name) if name == "pal" => Ok(Some(Region::Pal)),
+		Some(ref name) if name == "dendy" => Ok(Some(Region::Dendy)),
+		_ => Err("--region must be ntsc, pal or dendy".to_string())
+	}
+}
+
 fn main() -> std::io::Result<()> {
 	let args: Vec<String> = env::args().collect();
 
@@ -26,11 +63,16 @@ fn main() -> std::io::Result<()> {
 	}
 
 	let filename = &args[1];
+	let region = match parse_region(&args[2..]) {
+		Ok(region) => region,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
 	let mut file = File::open(filename)?;
 	let mut contents = vec![];
 	file.read_to_end(&mut contents)?;
-	let rom = Rom::new(contents);
-	assert_eq!(rom.valid(), true);
 
 	let sdl = sdl2::init().unwrap();
 	let event_pump = sdl.event_pump().unwrap();
@@ -39,16 +81,45 @@ fn main() -> std::io::Result<()> {
 	let display = Box::new(Sdl2Display::new(sdl));
 	let audio = Box::new(Sdl2Audio::new(audio_subsystem));
 	let mut nes = Nes::new(input, display, audio);
-	nes.set_rom(rom);
+	if let Err(e) = nes.set_rom(contents) {
+		eprintln!("Failed to load {}: {}", filename, e);
+		std::process::exit(1);
+	}
+	if let Some(region) = region {
+		nes.set_region(region);
+	}
+	nes.enable_rewind(DEFAULT_FRAME_INTERVAL, DEFAULT_MEMORY_BUDGET);
+
+	let save_path = save_file_path(filename);
+	let mut last_written = vec![];
+	if nes.battery_ram().is_some() {
+		if let Ok(data) = fs::read(&save_path) {
+			nes.load_battery_ram(&data);
+		}
+		last_written = nes.battery_ram().unwrap().to_vec();
+	}
 
 	nes.bootup();
+	let mut frame_count = 0;
+	let mut last_fault = None;
 	loop {
 		nes.step_frame();
 		if !nes.is_power_on() {
 			break;
 		}
+		if nes.last_fault() != last_fault {
+			last_fault = nes.last_fault();
+			if let Some(fault) = last_fault {
+				eprintln!("{}", fault);
+			}
+		}
+		frame_count += 1;
+		if frame_count % BATTERY_RAM_SAVE_INTERVAL_FRAMES == 0 {
+			write_battery_ram(&nes, &save_path, &mut last_written);
+		}
 		// @TODO: Fix sleep duration time
 		std::thread::sleep(Duration::from_millis(1));
 	}
+	write_battery_ram(&nes, &save_path, &mut last_written);
 	Ok(())
 }
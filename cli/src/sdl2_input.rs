@@ -8,6 +8,7 @@ use nes_rust::button;
 fn keycode_to_button(key: Keycode) -> Option<button::Button> {
 	match key {
 		Keycode::Escape => Some(button::Button::Poweroff),
+		Keycode::Backspace => Some(button::Button::Rewind),
 		// joypad1
 		Keycode::Space => Some(button::Button::Start),
 		Keycode::S => Some(button::Button::Select),
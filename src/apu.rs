@@ -1,5 +1,15 @@
 use register::Register;
 use audio::Audio;
+use state::{StateError, StateReader, StateWriter};
+use region::{NTSC_TIMING, Region, RegionTiming};
+
+// Audio output sample rate in Hz
+pub const SAMPLE_RATE: u64 = 44100;
+
+// The mixer output of a single APU pulse channel at volume 15,
+// 95.88 / (8128 / 15 + 100). Expansion audio channels at their full
+// volume are mixed at about this level.
+pub const PULSE_FULL_VOLUME_LEVEL: f32 = 95.88 / (8128.0 / 15.0 + 100.0);
 
 /*
  * Audio Processing Unit implementation. Consists of
@@ -10,8 +20,15 @@ use audio::Audio;
  * Refer to https://wiki.nesdev.com/w/index.php/APU
  */
 pub struct Apu {
+	timing: RegionTiming,
 	cycle: u32,
+
+	// Frame sequencer. frame_cycle counts CPU cycles from the start
+	// of the sequence and step is the index of the next step.
+	frame_cycle: u32,
 	step: u16,
+	// Writing 0x4017 resets the sequence a few CPU cycles later
+	frame_reset_delay: u8,
 
 	// CPU memory mapped sub units/registers
 
@@ -23,10 +40,11 @@ pub struct Apu {
 	status: Register<u8>, // 0x4015
 	frame: ApuFrameRegister, // 0x4017
 
-	sample_period: u32,
+	// Incremented by SAMPLE_RATE every CPU cycle and a sample
+	// is output when it reaches CPU clock rate
+	sample_counter: u64,
 	frame_irq_active: bool,
 	dmc_irq_active: bool,
-	pub irq_interrupted: bool,
 
 	audio: Box<dyn Audio>
 }
@@ -41,8 +59,11 @@ static LENGTH_TABLE: [u8; 32] = [
 impl Apu {
 	pub fn new(audio: Box<dyn Audio>) -> Self {
 		Apu {
+			timing: NTSC_TIMING,
 			cycle: 0,
+			frame_cycle: 0,
 			step: 0,
+			frame_reset_delay: 0,
 			pulse1: ApuPulse::new(ApuPulseChannel::Channel1),
 			pulse2: ApuPulse::new(ApuPulseChannel::Channel2),
 			triangle: ApuTriangle::new(),
@@ -50,10 +71,9 @@ impl Apu {
 			dmc: ApuDmc::new(),
 			status: Register::<u8>::new(),
 			frame: ApuFrameRegister::new(),
-			sample_period: 1764000 / 44100, // @TODO: Fix me
+			sample_counter: 0,
 			frame_irq_active: false,
 			dmc_irq_active: false,
-			irq_interrupted: false,
 			audio: audio
 		}
 	}
@@ -67,19 +87,32 @@ impl Apu {
 		// @TODO: Implement properly
 	}
 
+	pub fn set_region(&mut self, region: Region) {
+		self.timing = region.timing();
+		// Dendy uses NTSC tables
+		let (noise_timer_table, dmc_timer_table) = match region {
+			Region::Pal => (&PAL_NOISE_TIMER_TABLE, &PAL_DMC_TIMER_TABLE),
+			_ => (&NOISE_TIMER_TABLE, &DMC_TIMER_TABLE)
+		};
+		self.noise.timer_table = noise_timer_table;
+		self.dmc.timer_table = dmc_timer_table;
+	}
+
 	pub fn get_mut_audio(&mut self) -> &mut Box<dyn Audio> {
 		&mut self.audio
 	}
 
-	// Expects being called at CPU clock rate
-	pub fn step(&mut self, dmc_sample_data: u8) {
+	// Expects being called at CPU clock rate.
+	// expansion_output is the cartridge audio mixed into the output.
+	pub fn step(&mut self, expansion_output: f32) {
 		self.cycle += 1;
 
 		// Samping at sample rate timing
-		// @TODO Fix me, more precise timing
 
-		if (self.cycle % self.sample_period) == 0 {
-			self.sample();
+		self.sample_counter += SAMPLE_RATE;
+		if self.sample_counter >= self.timing.cpu_clock_rate() {
+			self.sample_counter -= self.timing.cpu_clock_rate();
+			self.sample(expansion_output);
 		}
 
 		// Timers
@@ -90,88 +123,95 @@ impl Apu {
 			self.pulse1.drive_timer();
 			self.pulse2.drive_timer();
 			self.noise.drive_timer();
-			// @TODO: Add note
-			if self.dmc.drive_timer(dmc_sample_data) {
-				self.dmc_irq_active = true;
-			}
+			self.dmc.drive_timer();
 		}
 
 		self.triangle.drive_timer();
 
-		// 240Hz Frame sequencer
-		// @TODO: Fix me, more precise timing
-
-		if (self.cycle % 7457) == 0 {
-			if self.frame.five_step_mode() {
-				// Five-step sequence
-				//
-				// 0 1 2 3 4    function
-				// -----------  -----------------------------
-				// - - - - -    IRQ (if bit 6 is clear)
-				// l - l - -    Length counter and sweep
-				// e e e e -    Envelope and linear counter
-
-				if self.step < 4 {
-					self.pulse1.drive_envelope();
-					self.pulse2.drive_envelope();
-					self.triangle.drive_linear();
-					self.noise.drive_envelope();
-				}
+		self.drive_frame_sequencer();
+	}
 
-				if self.step == 0 || self.step == 2 {
-					self.pulse1.drive_length();
-					self.pulse1.drive_sweep();
-					self.pulse2.drive_length();
-					self.pulse2.drive_sweep();
-					self.triangle.drive_length();
-					self.noise.drive_length()
-				}
+	// IRQ line shared with mapper. Asserted while frame interrupt flag
+	// or DMC interrupt flag is set and kept until they are acknowledged.
+	pub fn irq_line(&self) -> bool {
+		self.frame_irq_active || self.dmc_irq_active
+	}
 
-				self.step = (self.step + 1) % 5;
-			} else {
-				// Four-step sequence
-				//
-				// 0 1 2 3    function
-				// ---------  -----------------------------
-				// - - - f    IRQ (if bit 6 is clear)
-				// - l - l    Length counter and sweep
-				// e e e e    Envelope and linear counter
-
-				self.pulse1.drive_envelope();
-				self.pulse2.drive_envelope();
-				self.triangle.drive_linear();
-				self.noise.drive_envelope();
-
-				if self.step == 1 || self.step == 3 {
-					self.pulse1.drive_length();
-					self.pulse1.drive_sweep();
-					self.pulse2.drive_length();
-					self.pulse2.drive_sweep();
-					self.triangle.drive_length();
-					self.noise.drive_length();
-				}
+	// 240Hz Frame sequencer
+	// Refer to https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+	fn drive_frame_sequencer(&mut self) {
+		self.frame_cycle += 1;
 
-				if self.step == 3 && !self.frame.irq_disabled() {
-					self.frame_irq_active = true;
-				}
+		let five_step_mode = self.frame.five_step_mode();
+		let step_cycles = match five_step_mode {
+			true => self.timing.five_step_sequence_cycles,
+			false => self.timing.four_step_sequence_cycles
+		};
 
-				// Seems like keep invoking IRQ once frame IRQ flag is on
-				// until IRQ flag is cleared or it's disabled...?
+		if self.frame_cycle == step_cycles[self.step as usize] {
+			// Steps of both sequences. Four-step sequence ends at step 5
+			// (29830) and five-step sequence ends at step 5 (37282).
+			//
+			// 0 1 2 3 4 5    function
+			// -------------  -----------------------------
+			// - - - f f f    IRQ (four-step and if bit 6 is clear)
+			// - l - - l -    Length counter and sweep
+			// e e e - e -    Envelope and linear counter
+
+			if !five_step_mode && self.step >= 3 && !self.frame.irq_disabled() {
+				self.frame_irq_active = true;
+			}
 
-				if self.frame_irq_active && !self.frame.irq_disabled() {
-					self.irq_interrupted = true;
-				}
+			match self.step {
+				0 | 2 => {
+					self.drive_quarter_frame();
+				},
+				1 | 4 => {
+					self.drive_quarter_frame();
+					self.drive_half_frame();
+				},
+				_ => {}
+			};
 
-				self.step = (self.step + 1) % 4;
+			self.step += 1;
+			if self.step == 6 {
+				self.step = 0;
+				self.frame_cycle = 0;
 			}
+		}
 
-			// @TODO: check sending IRQ timing
-			if self.dmc_irq_active {
-				self.irq_interrupted = true;
+		if self.frame_reset_delay > 0 {
+			self.frame_reset_delay -= 1;
+			if self.frame_reset_delay == 0 {
+				self.step = 0;
+				self.frame_cycle = 0;
+				// Five-step mode clocks the units immediately
+				if five_step_mode {
+					self.drive_quarter_frame();
+					self.drive_half_frame();
+				}
 			}
 		}
 	}
 
+	// Envelope and linear counter
+	fn drive_quarter_frame(&mut self) {
+		self.pulse1.drive_envelope();
+		self.pulse2.drive_envelope();
+		self.triangle.drive_linear();
+		self.noise.drive_envelope();
+	}
+
+	// Length counter and sweep
+	fn drive_half_frame(&mut self) {
+		self.pulse1.drive_length();
+		self.pulse1.drive_sweep();
+		self.pulse2.drive_length();
+		self.pulse2.drive_sweep();
+		self.triangle.drive_length();
+		self.noise.drive_length();
+	}
+
 	pub fn load_register(&mut self, address: u16) -> u8 {
 		match address {
 			0x4015 => {
@@ -192,7 +232,7 @@ impl Apu {
 					value |= 0x80;
 				}
 
-				if self.frame_irq_active && !self.frame.irq_disabled() {
+				if self.frame_irq_active {
 					value |= 0x40;
 				}
 
@@ -232,7 +272,14 @@ impl Apu {
 			0x4004..=0x4007 => self.pulse2.store_register(address, value),
 			0x4008..=0x400B => self.triangle.store_register(address, value),
 			0x400C..=0x400F => self.noise.store_register(address, value),
-			0x4010..=0x4013 => self.dmc.store_register(address, value),
+			0x4010..=0x4013 => {
+				self.dmc.store_register(address, value);
+
+				// Clearing IRQ enabled flag clears the DMC interrupt flag
+				if !self.dmc.irq_enabled() {
+					self.dmc_irq_active = false;
+				}
+			},
 			0x4015 => {
 				// Storing status register
 				//
@@ -267,23 +314,74 @@ impl Apu {
 				if self.frame.irq_disabled() {
 					self.frame_irq_active = false;
 				}
+
+				// The sequence is reset 3 CPU cycles after the write
+				// if the write is on an APU cycle, otherwise 4 cycles.
+				self.frame_reset_delay = match (self.cycle % 2) == 0 {
+					true => 3,
+					false => 4
+				};
 			},
 			_ => {}
 		};
 	}
 
-	// See cpu.step() for what the following two methods are for
-	// @TODO: A bit hacky. Simplify.
+	// DMC has no access to CPU memory so CPU checks if DMC needs
+	// the next sample byte, halts and reads it via DMA, and passes it.
 
-	pub fn dmc_needs_cpu_memory_data(&self) -> bool {
-		(self.cycle % 2) == 1 && self.dmc.needs_cpu_memory_data()
+	pub fn dmc_needs_dma(&self) -> bool {
+		self.dmc.needs_dma()
 	}
 
 	pub fn dmc_sample_address(&self) -> u16 {
 		self.dmc.address_counter
 	}
 
-	fn sample(&mut self) {
+	pub fn fill_dmc_sample_buffer(&mut self, sample_data: u8) {
+		if self.dmc.fill_sample_buffer(sample_data) {
+			self.dmc_irq_active = true;
+		}
+	}
+
+	pub fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_u32(self.cycle);
+		writer.write_u64(self.sample_counter);
+		writer.write_u32(self.frame_cycle);
+		writer.write_u16(self.step);
+		writer.write_u8(self.frame_reset_delay);
+		self.pulse1.save_state(writer);
+		self.pulse2.save_state(writer);
+		self.triangle.save_state(writer);
+		self.noise.save_state(writer);
+		self.dmc.save_state(writer);
+		writer.write_register_u8(&self.status);
+		writer.write_register_u8(&self.frame.register);
+		writer.write_bool(self.frame_irq_active);
+		writer.write_bool(self.dmc_irq_active);
+	}
+
+	pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		self.cycle = reader.read_u32()?;
+		self.sample_counter = reader.read_u64()?;
+		self.frame_cycle = reader.read_u32()?;
+		self.step = reader.read_u16()?;
+		if self.step > 5 {
+			return Err(StateError::InvalidData);
+		}
+		self.frame_reset_delay = reader.read_u8()?;
+		self.pulse1.load_state(reader)?;
+		self.pulse2.load_state(reader)?;
+		self.triangle.load_state(reader)?;
+		self.noise.load_state(reader)?;
+		self.dmc.load_state(reader)?;
+		reader.read_register_u8(&mut self.status)?;
+		reader.read_register_u8(&mut self.frame.register)?;
+		self.frame_irq_active = reader.read_bool()?;
+		self.dmc_irq_active = reader.read_bool()?;
+		Ok(())
+	}
+
+	fn sample(&mut self, expansion_output: f32) {
 		// Calculates the audio output within the range of 0.0 to 1.0.
 		// Refer to https://wiki.nesdev.com/w/index.php/APU_Mixer
 
@@ -304,7 +402,7 @@ impl Apu {
 			tnd_out = 159.79 / (1.0 / (triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0) + 100.0);
 		}
 
-		self.audio.push(pulse_out + tnd_out);
+		self.audio.push(pulse_out + tnd_out + expansion_output);
 	}
 }
 
@@ -314,8 +412,9 @@ impl Apu {
  *   - Length counter
  *   - Envelope
  *   - Sweep
+ * MMC5 has two of them without sweep.
  */
-struct ApuPulse {
+pub(crate) struct ApuPulse {
 	channel: ApuPulseChannel,
 	register0: Register<u8>, // 0x4000, 0x4004
 	register1: Register<u8>, // 0x4001, 0x4005
@@ -344,13 +443,15 @@ static DUTY_TABLE: [u8; 32] = [
   1, 0, 0, 1, 1, 1, 1, 1
 ];
 
-enum ApuPulseChannel {
+pub(crate) enum ApuPulseChannel {
 	Channel1,
-	Channel2
+	Channel2,
+	// MMC5 pulse, not silenced by the small timer period
+	Mmc5
 }
 
 impl ApuPulse {
-	fn new(channel: ApuPulseChannel) -> Self {
+	pub(crate) fn new(channel: ApuPulseChannel) -> Self {
 		ApuPulse {
 			channel: channel,
 			register0: Register::<u8>::new(),
@@ -370,7 +471,7 @@ impl ApuPulse {
 		}
 	}
 
-	fn store_register(&mut self, address: u16, value: u8) {
+	pub(crate) fn store_register(&mut self, address: u16, value: u8) {
 		match address & 0x4003 {
 			0x4000 => self.register0.store(value),
 			0x4001 => {
@@ -401,7 +502,7 @@ impl ApuPulse {
 		};
 	}
 
-	fn set_enable(&mut self, enabled: bool) {
+	pub(crate) fn set_enable(&mut self, enabled: bool) {
 		self.enabled = enabled;
 
 		// When the enabled bit is cleared (via $4015), the length counter is forced to 0
@@ -411,7 +512,11 @@ impl ApuPulse {
 		}
 	}
 
-	fn drive_timer(&mut self) {
+	pub(crate) fn is_length_counter_active(&self) -> bool {
+		self.length_counter > 0
+	}
+
+	pub(crate) fn drive_timer(&mut self) {
 		if self.timer_counter > 0 {
 			self.timer_counter -= 1;
 		} else {
@@ -425,13 +530,13 @@ impl ApuPulse {
 		}
 	}
 
-	fn drive_length(&mut self) {
+	pub(crate) fn drive_length(&mut self) {
 		if !self.envelope_loop_enabled() && self.length_counter > 0 {
 			self.length_counter -= 1;
 		}
 	}
 
-	fn drive_envelope(&mut self) {
+	pub(crate) fn drive_envelope(&mut self) {
 		if self.envelope_start_flag {
 			self.envelope_counter = self.envelope_period();
 			self.envelope_decay_level_counter = 0xF;
@@ -467,7 +572,7 @@ impl ApuPulse {
 				// @TODO: Fix me
 				true => match self.channel {
 					ApuPulseChannel::Channel1 => !change,
-					ApuPulseChannel::Channel2 => !change + 1
+					_ => !change + 1
 				},
 				false => change
 			};
@@ -481,10 +586,13 @@ impl ApuPulse {
 		}
 	}
 
-	fn output(&self) -> u8 {
+	pub(crate) fn output(&self) -> u8 {
+		let muted_by_timer = match self.channel {
+			ApuPulseChannel::Mmc5 => false,
+			_ => self.timer_period < 8 || self.timer_period > 0x7FF
+		};
 		if self.length_counter == 0 ||
-			self.timer_period < 8 ||
-			self.timer_period > 0x7FF ||
+			muted_by_timer ||
 			DUTY_TABLE[(self.duty() * 8 + self.timer_sequence) as usize] == 0 {
 			return 0;
 		}
@@ -496,6 +604,44 @@ impl ApuPulse {
 		}
 	}
 
+	pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register0);
+		writer.write_register_u8(&self.register1);
+		writer.write_register_u8(&self.register2);
+		writer.write_register_u8(&self.register3);
+		writer.write_bool(self.enabled);
+		writer.write_u16(self.timer_counter);
+		writer.write_u16(self.timer_period);
+		writer.write_u8(self.timer_sequence);
+		writer.write_bool(self.envelope_start_flag);
+		writer.write_u8(self.envelope_counter);
+		writer.write_u8(self.envelope_decay_level_counter);
+		writer.write_u8(self.length_counter);
+		writer.write_bool(self.sweep_reload_flag);
+		writer.write_u8(self.sweep_counter);
+	}
+
+	pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register0)?;
+		reader.read_register_u8(&mut self.register1)?;
+		reader.read_register_u8(&mut self.register2)?;
+		reader.read_register_u8(&mut self.register3)?;
+		self.enabled = reader.read_bool()?;
+		self.timer_counter = reader.read_u16()?;
+		self.timer_period = reader.read_u16()?;
+		self.timer_sequence = reader.read_u8()?;
+		if self.timer_sequence > 7 {
+			return Err(StateError::InvalidData);
+		}
+		self.envelope_start_flag = reader.read_bool()?;
+		self.envelope_counter = reader.read_u8()?;
+		self.envelope_decay_level_counter = reader.read_u8()?;
+		self.length_counter = reader.read_u8()?;
+		self.sweep_reload_flag = reader.read_bool()?;
+		self.sweep_counter = reader.read_u8()?;
+		Ok(())
+	}
+
 	fn duty(&self) -> u8 {
 		self.register0.load_bits(6, 2)
 	}
@@ -674,6 +820,36 @@ impl ApuTriangle {
 		return SEQUENCE_TABLE[self.timer_sequence as usize] & 0xF;
 	}
 
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register0);
+		writer.write_register_u8(&self.register1);
+		writer.write_register_u8(&self.register2);
+		writer.write_register_u8(&self.register3);
+		writer.write_bool(self.enabled);
+		writer.write_u16(self.timer_counter);
+		writer.write_u8(self.timer_sequence);
+		writer.write_u8(self.length_counter);
+		writer.write_bool(self.linear_reload_flag);
+		writer.write_u8(self.linear_counter);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register0)?;
+		reader.read_register_u8(&mut self.register1)?;
+		reader.read_register_u8(&mut self.register2)?;
+		reader.read_register_u8(&mut self.register3)?;
+		self.enabled = reader.read_bool()?;
+		self.timer_counter = reader.read_u16()?;
+		self.timer_sequence = reader.read_u8()?;
+		if self.timer_sequence > 31 {
+			return Err(StateError::InvalidData);
+		}
+		self.length_counter = reader.read_u8()?;
+		self.linear_reload_flag = reader.read_bool()?;
+		self.linear_counter = reader.read_u8()?;
+		Ok(())
+	}
+
 	fn linear_counter(&self) -> u8 {
 		self.register0.load_bits(0, 7)
 	}
@@ -716,6 +892,7 @@ struct ApuNoise {
 
 	timer_counter: u16,
 	timer_period: u16,
+	timer_table: &'static [u16; 16],
 
 	envelope_start_flag: bool,
 	envelope_counter: u8,
@@ -733,6 +910,13 @@ static NOISE_TIMER_TABLE: [u16; 16] = [
 	0x2FA, 0x3F8, 0x7F2, 0xFE4
 ];
 
+static PAL_NOISE_TIMER_TABLE: [u16; 16] = [
+	0x004, 0x008, 0x00E, 0x01E,
+	0x03C, 0x058, 0x076, 0x094,
+	0x0BC, 0x0EC, 0x162, 0x1D8,
+	0x2C4, 0x3B0, 0x762, 0xEC2
+];
+
 impl ApuNoise {
 	fn new() -> Self {
 		ApuNoise {
@@ -743,6 +927,7 @@ impl ApuNoise {
 			enabled: false,
 			timer_counter: 0,
 			timer_period: 0,
+			timer_table: &NOISE_TIMER_TABLE,
 			envelope_start_flag: false,
 			envelope_counter: 0,
 			envelope_decay_level_counter: 0,
@@ -757,7 +942,7 @@ impl ApuNoise {
 			0x400D => self.register1.store(value),
 			0x400E => {
 				self.register2.store(value);
-				self.timer_period = NOISE_TIMER_TABLE[self.timer_index() as usize];
+				self.timer_period = self.timer_table[self.timer_index() as usize];
 			},
 			0x400F => {
 				self.register3.store(value);
@@ -856,6 +1041,37 @@ impl ApuNoise {
 		self.register0.load_bits(0, 4)
 	}
 
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register0);
+		writer.write_register_u8(&self.register1);
+		writer.write_register_u8(&self.register2);
+		writer.write_register_u8(&self.register3);
+		writer.write_bool(self.enabled);
+		writer.write_u16(self.timer_counter);
+		writer.write_u16(self.timer_period);
+		writer.write_bool(self.envelope_start_flag);
+		writer.write_u8(self.envelope_counter);
+		writer.write_u8(self.envelope_decay_level_counter);
+		writer.write_u8(self.length_counter);
+		writer.write_u16(self.shift_register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register0)?;
+		reader.read_register_u8(&mut self.register1)?;
+		reader.read_register_u8(&mut self.register2)?;
+		reader.read_register_u8(&mut self.register3)?;
+		self.enabled = reader.read_bool()?;
+		self.timer_counter = reader.read_u16()?;
+		self.timer_period = reader.read_u16()?;
+		self.envelope_start_flag = reader.read_bool()?;
+		self.envelope_counter = reader.read_u8()?;
+		self.envelope_decay_level_counter = reader.read_u8()?;
+		self.length_counter = reader.read_u8()?;
+		self.shift_register = reader.read_u16()?;
+		Ok(())
+	}
+
 	fn is_random(&self) -> bool {
 		self.register2.is_bit_set(7)
 	}
@@ -886,6 +1102,7 @@ struct ApuDmc {
 
 	timer_period: u16,
 	timer_counter: u16,
+	timer_table: &'static [u16; 16],
 
 	delta_counter: u8,
 	address_counter: u16,
@@ -907,6 +1124,13 @@ static DMC_TIMER_TABLE: [u16; 16] = [
 	0x06A, 0x054, 0x048, 0x036
 ];
 
+static PAL_DMC_TIMER_TABLE: [u16; 16] = [
+	0x18E, 0x162, 0x13C, 0x12A,
+	0x114, 0x0EC, 0x0D2, 0x0C6,
+	0x0B0, 0x094, 0x084, 0x076,
+	0x062, 0x04E, 0x042, 0x032
+];
+
 impl ApuDmc {
 	fn new() -> Self {
 		ApuDmc {
@@ -917,6 +1141,7 @@ impl ApuDmc {
 			enabled: false,
 			timer_period: 0,
 			timer_counter: 0,
+			timer_table: &DMC_TIMER_TABLE,
 			delta_counter: 0,
 			address_counter: 0,
 			remaining_bytes_counter: 0,
@@ -932,7 +1157,7 @@ impl ApuDmc {
 		match address {
 			0x4010 => {
 				self.register0.store(value);
-				self.timer_period = DMC_TIMER_TABLE[self.timer_index() as usize] >> 1;
+				self.timer_period = self.timer_table[self.timer_index() as usize] >> 1;
 			},
 			0x4011 => {
 				self.register1.store(value);
@@ -971,48 +1196,47 @@ impl ApuDmc {
 		self.remaining_bytes_counter = ((self.sample_length() as u16) << 4) | 1;
 	}
 
-	// See cpu.step() for what this method is for
-	// @TODO: Solution to remove this workaround
-	fn needs_cpu_memory_data(&self) -> bool {
-		self.timer_counter == 0 &&
-			self.remaining_bytes_counter > 0 &&
-			self.sample_buffer_is_empty
+	// The memory reader fills the sample buffer via DMA
+	// as soon as the buffer gets empty
+	fn needs_dma(&self) -> bool {
+		self.remaining_bytes_counter > 0 && self.sample_buffer_is_empty
 	}
 
-	fn drive_timer(&mut self, sample_data: u8) -> bool {
+	// Returns true if DMC IRQ should be asserted
+	fn fill_sample_buffer(&mut self, sample_data: u8) -> bool {
 		let mut irq_active = false;
-		if self.timer_counter > 0 {
-			self.timer_counter -= 1;
-		} else {
-			self.timer_counter = self.timer_period;
+		self.sample_buffer = sample_data;
 
-			// Memory reader
+		// if address exceeds 0xFFFF, it is wrapped around to 0x8000.
+		self.address_counter = match self.address_counter {
+			0xFFFF => 0x8000,
+			_ => self.address_counter + 1
+		};
 
-			if self.remaining_bytes_counter > 0 && self.sample_buffer_is_empty {
-				self.sample_buffer = sample_data;
+		self.sample_buffer_is_empty = false;
 
-				// if address exceeds 0xFFFF, it is wrapped around to 0x8000.
-				self.address_counter = match self.address_counter {
-					0xFFFF => 0x8000,
-					_ => self.address_counter + 1
-				};
+		// If the bytes remaining counter becomes zero
+		//   - the sample is restarted if the loop flag is set
+		//   - otherwise, the interrupt flag is set if IRQ enabled flag is set
 
-				self.sample_buffer_is_empty = false;
+		self.remaining_bytes_counter -= 1;
 
-				// If the bytes remaining counter becomes zero
-				//   - the sample is restarted if the loop flag is set
-				//   - otherwise, the interrupt flag is set if IRQ enabled flag is set
+		if self.remaining_bytes_counter == 0 {
+			if self.is_loop() {
+				self.start();
+			} else if self.irq_enabled() {
+				irq_active = true;
+			}
+		}
 
-				self.remaining_bytes_counter -= 1;
+		irq_active
+	}
 
-				if self.remaining_bytes_counter == 0 {
-					if self.is_loop() {
-						self.start();
-					} else if self.irq_enabled() {
-						irq_active = true;
-					}
-				}
-			}
+	fn drive_timer(&mut self) {
+		if self.timer_counter > 0 {
+			self.timer_counter -= 1;
+		} else {
+			self.timer_counter = self.timer_period;
 
 			// Output unit
 
@@ -1044,8 +1268,6 @@ impl ApuDmc {
 			self.remaining_bits_counter -= 1;
 			self.shift_register = self.shift_register >> 1;
 		}
-
-		irq_active
 	}
 
 	fn output(&self) -> u8 {
@@ -1064,6 +1286,43 @@ impl ApuDmc {
 		self.delta_counter & 0x7F
 	}
 
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register0);
+		writer.write_register_u8(&self.register1);
+		writer.write_register_u8(&self.register2);
+		writer.write_register_u8(&self.register3);
+		writer.write_bool(self.enabled);
+		writer.write_u16(self.timer_period);
+		writer.write_u16(self.timer_counter);
+		writer.write_u8(self.delta_counter);
+		writer.write_u16(self.address_counter);
+		writer.write_u16(self.remaining_bytes_counter);
+		writer.write_u8(self.sample_buffer);
+		writer.write_bool(self.sample_buffer_is_empty);
+		writer.write_u8(self.shift_register);
+		writer.write_u8(self.remaining_bits_counter);
+		writer.write_bool(self.silence_flag);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register0)?;
+		reader.read_register_u8(&mut self.register1)?;
+		reader.read_register_u8(&mut self.register2)?;
+		reader.read_register_u8(&mut self.register3)?;
+		self.enabled = reader.read_bool()?;
+		self.timer_period = reader.read_u16()?;
+		self.timer_counter = reader.read_u16()?;
+		self.delta_counter = reader.read_u8()?;
+		self.address_counter = reader.read_u16()?;
+		self.remaining_bytes_counter = reader.read_u16()?;
+		self.sample_buffer = reader.read_u8()?;
+		self.sample_buffer_is_empty = reader.read_bool()?;
+		self.shift_register = reader.read_u8()?;
+		self.remaining_bits_counter = reader.read_u8()?;
+		self.silence_flag = reader.read_bool()?;
+		Ok(())
+	}
+
 	fn irq_enabled(&self) -> bool {
 		self.register0.is_bit_set(7)
 	}
@@ -1108,7 +1367,52 @@ impl ApuFrameRegister {
 		self.register.is_bit_set(7)
 	}
 
-	fn irq_disabled(&mut self) -> bool {
+	fn irq_disabled(&self) -> bool {
 		self.register.is_bit_set(6)
 	}
 }
+
+#[cfg(test)]
+mod tests_apu {
+	use Nes;
+	use default_input::DefaultInput;
+	use default_audio::DefaultAudio;
+	use default_display::DefaultDisplay;
+	use rom::HEADER_SIZE;
+	use state::StateError;
+
+	fn nes() -> Nes {
+		let mut nes = Nes::new(
+			Box::new(DefaultInput::new()),
+			Box::new(DefaultDisplay::new()),
+			Box::new(DefaultAudio::new())
+		);
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1];
+		rom.resize(HEADER_SIZE + 0x4000, 0);
+		nes.set_rom(rom).unwrap();
+		nes.bootup();
+		nes
+	}
+
+	#[test]
+	fn invalid_state() {
+		let mut nes = nes();
+		let state = nes.save_state();
+
+		// Out of range frame sequencer step and pulse/triangle sequence
+		nes.cpu.get_mut_apu().step = 6;
+		let bad_step = nes.save_state();
+		assert_eq!(Ok(()), nes.load_state(&state));
+		nes.cpu.get_mut_apu().pulse1.timer_sequence = 8;
+		let bad_pulse = nes.save_state();
+		assert_eq!(Ok(()), nes.load_state(&state));
+		nes.cpu.get_mut_apu().triangle.timer_sequence = 32;
+		let bad_triangle = nes.save_state();
+		assert_eq!(Ok(()), nes.load_state(&state));
+
+		assert_eq!(Err(StateError::InvalidData), nes.load_state(&bad_step));
+		assert_eq!(Err(StateError::InvalidData), nes.load_state(&bad_pulse));
+		assert_eq!(Err(StateError::InvalidData), nes.load_state(&bad_triangle));
+		assert_eq!(state, nes.save_state());
+	}
+}
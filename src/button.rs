@@ -1,6 +1,7 @@
 pub enum Button {
 	Poweroff,
 	Reset,
+	Rewind,
 	Select,
 	Start,
 	Joypad1A,
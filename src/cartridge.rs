@@ -0,0 +1,184 @@
+use memory::Memory;
+use state::{StateError, StateReader, StateWriter};
+
+/**
+ * Memories on a cartridge board. Mapper banks them into
+ * CPU and PPU memory address space.
+ *
+ * Addresses passed to the methods are offsets in each memory.
+ * They wrap around by the memory size because boards
+ * ignore the higher address lines the memory doesn't have.
+ */
+pub struct Cartridge {
+	pub prg_rom: Memory,
+	pub chr_rom: Memory,
+	pub chr_ram: Memory,
+	// Volatile work RAM
+	pub prg_ram: Memory,
+	// Battery-backed PRG-RAM for game saves
+	pub battery_ram: Memory,
+	// Extra VRAM for four screen nametables
+	pub nametable_ram: Memory
+}
+
+impl Cartridge {
+	pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize,
+		prg_ram_size: usize, battery_ram_size: usize, nametable_ram_size: usize) -> Self {
+		Cartridge {
+			prg_rom: Memory::new(prg_rom),
+			chr_rom: Memory::new(chr_rom),
+			chr_ram: Memory::new(vec![0; chr_ram_size]),
+			prg_ram: Memory::new(vec![0; prg_ram_size]),
+			battery_ram: Memory::new(vec![0; battery_ram_size]),
+			nametable_ram: Memory::new(vec![0; nametable_ram_size])
+		}
+	}
+
+	pub fn load_prg_rom(&self, address: u32) -> u8 {
+		load_wrapped(&self.prg_rom, address)
+	}
+
+	pub fn has_chr_rom(&self) -> bool {
+		self.chr_rom.capacity() > 0
+	}
+
+	pub fn load_chr_rom(&self, address: u32) -> u8 {
+		load_wrapped(&self.chr_rom, address)
+	}
+
+	pub fn load_chr_ram(&self, address: u32) -> u8 {
+		load_wrapped(&self.chr_ram, address)
+	}
+
+	pub fn store_chr_ram(&mut self, address: u32, value: u8) {
+		store_wrapped(&mut self.chr_ram, address, value);
+	}
+
+	/**
+	 * Pattern table memory of the most of boards. CHR-ROM if exists,
+	 * otherwise CHR-RAM. Boards having both should use
+	 * load_chr_rom() and load_chr_ram() instead.
+	 */
+	pub fn load_chr(&self, address: u32) -> u8 {
+		match self.has_chr_rom() {
+			true => self.load_chr_rom(address),
+			false => self.load_chr_ram(address)
+		}
+	}
+
+	// Writes to CHR-ROM are ignored
+	pub fn store_chr(&mut self, address: u32, value: u8) {
+		if !self.has_chr_rom() {
+			self.store_chr_ram(address, value);
+		}
+	}
+
+	pub fn load_nametable_ram(&self, address: u32) -> u8 {
+		load_wrapped(&self.nametable_ram, address)
+	}
+
+	pub fn store_nametable_ram(&mut self, address: u32, value: u8) {
+		store_wrapped(&mut self.nametable_ram, address, value);
+	}
+
+	// Battery-backed RAM and work RAM in this order
+	pub fn prg_ram_size(&self) -> u32 {
+		self.battery_ram.capacity() + self.prg_ram.capacity()
+	}
+
+	/**
+	 * PRG-RAM space usually mapped at 0x6000 - 0x7FFF.
+	 * Battery-backed RAM comes first and work RAM follows.
+	 * Open bus (0 for now) if no PRG-RAM.
+	 */
+	pub fn load_prg_ram(&self, address: u32) -> u8 {
+		match self.prg_ram_size() {
+			0 => 0,
+			size => {
+				let address = address % size;
+				match address < self.battery_ram.capacity() {
+					true => self.battery_ram.load(address),
+					false => self.prg_ram.load(address - self.battery_ram.capacity())
+				}
+			}
+		}
+	}
+
+	pub fn store_prg_ram(&mut self, address: u32, value: u8) {
+		match self.prg_ram_size() {
+			0 => {},
+			size => {
+				let address = address % size;
+				match address < self.battery_ram.capacity() {
+					true => self.battery_ram.store(address, value),
+					false => {
+						let address = address - self.battery_ram.capacity();
+						self.prg_ram.store(address, value);
+					}
+				}
+			}
+		}
+	}
+
+	// ROMs are not saved because they never change
+	pub fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_memory(&self.chr_ram);
+		writer.write_memory(&self.prg_ram);
+		writer.write_memory(&self.battery_ram);
+		writer.write_memory(&self.nametable_ram);
+	}
+
+	pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_memory(&mut self.chr_ram)?;
+		reader.read_memory(&mut self.prg_ram)?;
+		reader.read_memory(&mut self.battery_ram)?;
+		reader.read_memory(&mut self.nametable_ram)
+	}
+}
+
+fn load_wrapped(memory: &Memory, address: u32) -> u8 {
+	match memory.capacity() {
+		0 => 0,
+		capacity => memory.load(address % capacity)
+	}
+}
+
+fn store_wrapped(memory: &mut Memory, address: u32, value: u8) {
+	match memory.capacity() {
+		0 => {},
+		capacity => memory.store(address % capacity, value)
+	}
+}
+
+#[cfg(test)]
+mod tests_cartridge {
+	use super::*;
+
+	#[test]
+	fn load_chr() {
+		let mut c = Cartridge::new(vec![0; 0x4000], vec![1; 0x2000], 0, 0, 0, 0);
+		c.store_chr(0, 2);
+		assert_eq!(1, c.load_chr(0));
+		assert_eq!(1, c.load_chr(0x2000));
+
+		let mut c2 = Cartridge::new(vec![0; 0x4000], vec![], 0x8000, 0, 0, 0);
+		c2.store_chr(0x6000, 2);
+		assert_eq!(2, c2.load_chr(0x6000));
+		assert_eq!(2, c2.load_chr(0xE000));
+		assert_eq!(0, c2.load_chr(0));
+	}
+
+	#[test]
+	fn prg_ram() {
+		let mut c = Cartridge::new(vec![0; 0x4000], vec![], 0x2000, 0x2000, 0x2000, 0);
+		c.store_prg_ram(0x0000, 1);
+		c.store_prg_ram(0x2000, 2);
+		assert_eq!(1, c.battery_ram.load(0));
+		assert_eq!(2, c.prg_ram.load(0));
+		assert_eq!(1, c.load_prg_ram(0x4000));
+
+		let mut c2 = Cartridge::new(vec![0; 0x4000], vec![], 0x2000, 0, 0, 0);
+		c2.store_prg_ram(0, 1);
+		assert_eq!(0, c2.load_prg_ram(0));
+	}
+}
@@ -9,6 +9,9 @@ use joypad::Joypad;
 use input::Input;
 use display::Display;
 use audio::Audio;
+use state::{StateError, StateReader, StateWriter};
+use event::EmulationEvent;
+use region::{NTSC_TIMING, Region, RegionTiming};
 
 fn to_joypad_button(button: button::Button) -> joypad::Button {
 	match button {
@@ -48,11 +51,48 @@ pub struct Cpu {
 	// CPU inside RAM
 	ram: Memory,
 
-	// manage additional stall cycles eg. DMA or branch success
-	stall_cycles: u16,
+	// Every CPU cycle does one memory access and advances the master
+	// clock, and PPU and APU are clocked along with it.
+	// The ratio comes from the region timing.
+	region: Region,
+	timing: RegionTiming,
+	master_clock: u64,
+	ppu_clock: u64,
+	cycle_count: u64,
+
+	// CPU stops only at instruction boundaries so run_cycles()
+	// carries the excess master clocks over to the next call
+	clock_overshoot: u64,
+
+	// NMI is edge-sensitive. need_nmi is set when PPU NMI line goes high.
+	// IRQ is level-sensitive. run_irq is set while IRQ line is asserted
+	// and interrupt disable flag is clear.
+	// Interrupts are polled at the end of the second-to-last cycle of
+	// instructions so the values of the previous cycle are kept.
+	nmi_line: bool,
+	need_nmi: bool,
+	prev_need_nmi: bool,
+	run_irq: bool,
+	prev_run_irq: bool,
+
+	// DMA halts CPU on its next read cycle
+	dma_halt_needed: bool,
+	oam_dma_running: bool,
+	oam_dma_page: u8,
+	dmc_dma_running: bool,
+	dmc_dummy_read_needed: bool,
+
+	// Set by JAM opcodes. Only reset (or power cycle) resumes the CPU.
+	halted: bool,
+
+	// The latest abnormal event for frontends, cleared by reset
+	last_fault: Option<EmulationEvent>,
 
 	input: Box<dyn Input>,
 
+	// True while rewind button is held
+	rewinding: bool,
+
 	// other devices
 	ppu: Ppu,
 	apu: Apu,
@@ -81,6 +121,7 @@ fn interrupt_handler_address(interrupt_type: Interrupts) -> u16 {
 
 enum InstructionTypes {
 	INV,
+	JAM,
 	ADC,
 	AND,
 	ASL,
@@ -136,12 +177,26 @@ enum InstructionTypes {
 	TSX,
 	TXA,
 	TXS,
-	TYA
+	TYA,
+	// unofficial
+	ALR,
+	ANC,
+	ARR,
+	AXS,
+	DCP,
+	ISC,
+	LAX,
+	RLA,
+	RRA,
+	SAX,
+	SLO,
+	SRE
 }
 
 fn instruction_name(instruction_type: InstructionTypes) -> &'static str {
 	match instruction_type {
 		InstructionTypes::INV => "inv",
+		InstructionTypes::JAM => "jam",
 		InstructionTypes::ADC => "adc",
 		InstructionTypes::AND => "and",
 		InstructionTypes::ASL => "asl",
@@ -197,7 +252,19 @@ fn instruction_name(instruction_type: InstructionTypes) -> &'static str {
 		InstructionTypes::TSX => "tsx",
 		InstructionTypes::TXA => "txa",
 		InstructionTypes::TXS => "txs",
-		InstructionTypes::TYA => "tya"
+		InstructionTypes::TYA => "tya",
+		InstructionTypes::ALR => "alr",
+		InstructionTypes::ANC => "anc",
+		InstructionTypes::ARR => "arr",
+		InstructionTypes::AXS => "axs",
+		InstructionTypes::DCP => "dcp",
+		InstructionTypes::ISC => "isc",
+		InstructionTypes::LAX => "lax",
+		InstructionTypes::RLA => "rla",
+		InstructionTypes::RRA => "rra",
+		InstructionTypes::SAX => "sax",
+		InstructionTypes::SLO => "slo",
+		InstructionTypes::SRE => "sre"
 	}
 }
 
@@ -224,6 +291,29 @@ struct Operation {
 }
 
 // @TODO: Replace with static array?
+// Read instructions with indexed addressing modes take an extra cycle
+// only if page is crossed. Store and read-modify-write instructions
+// always take it.
+fn is_read_instruction(instruction_type: &InstructionTypes) -> bool {
+	match *instruction_type {
+		InstructionTypes::ADC |
+		InstructionTypes::AND |
+		InstructionTypes::BIT |
+		InstructionTypes::CMP |
+		InstructionTypes::CPX |
+		InstructionTypes::CPY |
+		InstructionTypes::EOR |
+		InstructionTypes::LDA |
+		InstructionTypes::LDX |
+		InstructionTypes::LDY |
+		InstructionTypes::ORA |
+		InstructionTypes::SBC |
+		InstructionTypes::LAX |
+		InstructionTypes::NOP => true,
+		_ => false
+	}
+}
+
 fn operation(opc: u8) -> Operation {
 	match opc {
 		0x00 => Operation {
@@ -236,9 +326,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0x02 => invalid
-		// 0x03 => invalid
-		// 0x04 => invalid
+		0x02 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x03 => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
+		0x04 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 3,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x05 => Operation {
 			instruction_type: InstructionTypes::ORA,
 			cycle: 3,
@@ -249,7 +351,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0x07 => invalid
+		0x07 => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 5,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x08 => Operation {
 			instruction_type: InstructionTypes::PHP,
 			cycle: 3,
@@ -265,8 +371,16 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Accumulator
 		},
-		// 0x0B => invalid
-		// 0x0C => invalid
+		0x0B => Operation { // unofficial
+			instruction_type: InstructionTypes::ANC,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
+		0x0C => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0x0D => Operation {
 			instruction_type: InstructionTypes::ORA,
 			cycle: 4,
@@ -277,7 +391,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0x0F => invalid
+		0x0F => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 6,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0x10 => Operation {
 			instruction_type: InstructionTypes::BPL,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -288,9 +406,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0x12 => invalid
-		// 0x13 => invalid
-		// 0x14 => invalid
+		0x12 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x13 => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
+		0x14 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x15 => Operation {
 			instruction_type: InstructionTypes::ORA,
 			cycle: 4,
@@ -301,7 +431,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedZeroPageX
 		},
-		// 0x17 => invalid
+		0x17 => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x18 => Operation {
 			instruction_type: InstructionTypes::CLC,
 			cycle: 2,
@@ -312,9 +446,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0x1A => invalid
-		// 0x1B => invalid
-		// 0x1C => invalid
+		0x1A => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x1B => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
+		0x1C => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x1D => Operation {
 			instruction_type: InstructionTypes::ORA,
 			cycle: 4, // +1 if page crossed
@@ -325,7 +471,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 7,
 			addressing_mode: AddressingModes::IndexedAbsoluteX
 		},
-		// 0x1F => invalid
+		0x1F => Operation { // unofficial
+			instruction_type: InstructionTypes::SLO,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x20 => Operation {
 			instruction_type: InstructionTypes::JSR,
 			cycle: 6,
@@ -336,8 +486,16 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0x22 => invalid
-		// 0x23 => invalid
+		0x22 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x23 => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
 		0x24 => Operation {
 			instruction_type: InstructionTypes::BIT,
 			cycle: 3,
@@ -353,7 +511,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0x27 => invalid
+		0x27 => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 5,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x28 => Operation {
 			instruction_type: InstructionTypes::PLP,
 			cycle: 4,
@@ -369,7 +531,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Accumulator
 		},
-		// 0x2B => invalid
+		0x2B => Operation { // unofficial
+			instruction_type: InstructionTypes::ANC,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0x2C => Operation {
 			instruction_type: InstructionTypes::BIT,
 			cycle: 4,
@@ -385,7 +551,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0x2F => invalid
+		0x2F => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 6,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0x30 => Operation {
 			instruction_type: InstructionTypes::BMI,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -396,9 +566,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0x32 => invalid
-		// 0x33 => invalid
-		// 0x34 => invalid
+		0x32 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x33 => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
+		0x34 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x35 => Operation {
 			instruction_type: InstructionTypes::AND,
 			cycle: 4,
@@ -409,7 +591,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedZeroPageX
 		},
-		// 0x37 => invalid
+		0x37 => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x38 => Operation {
 			instruction_type: InstructionTypes::SEC,
 			cycle: 2,
@@ -420,9 +606,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0x3A => invalid
-		// 0x3B => invalid
-		// 0x3C => invalid
+		0x3A => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x3B => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
+		0x3C => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x3D => Operation {
 			instruction_type: InstructionTypes::AND,
 			cycle: 4, // +1 if page crossed
@@ -433,7 +631,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 7,
 			addressing_mode: AddressingModes::IndexedAbsoluteX
 		},
-		// 0x3F => invalid
+		0x3F => Operation { // unofficial
+			instruction_type: InstructionTypes::RLA,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x40 => Operation {
 			instruction_type: InstructionTypes::RTI,
 			cycle: 6,
@@ -444,9 +646,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0x42 => invalid
-		// 0x43 => invalid
-		// 0x44 => invalid
+		0x42 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x43 => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
+		0x44 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 3,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x45 => Operation {
 			instruction_type: InstructionTypes::EOR,
 			cycle: 3,
@@ -457,7 +671,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0x47 => invalid
+		0x47 => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 5,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x48 => Operation {
 			instruction_type: InstructionTypes::PHA,
 			cycle: 3,
@@ -473,7 +691,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Accumulator
 		},
-		// 0x4B => invalid
+		0x4B => Operation { // unofficial
+			instruction_type: InstructionTypes::ALR,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0x4C => Operation {
 			instruction_type: InstructionTypes::JMP,
 			cycle: 3,
@@ -489,7 +711,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0x4F => invalid
+		0x4F => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 6,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0x50 => Operation {
 			instruction_type: InstructionTypes::BVC,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -500,9 +726,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0x52 => invalid
-		// 0x53 => invalid
-		// 0x54 => invalid
+		0x52 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x53 => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
+		0x54 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x55 => Operation {
 			instruction_type: InstructionTypes::EOR,
 			cycle: 4,
@@ -513,7 +751,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedZeroPageX
 		},
-		// 0x57 => invalid
+		0x57 => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x58 => Operation {
 			instruction_type: InstructionTypes::CLI,
 			cycle: 2,
@@ -524,9 +766,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0x5A => invalid
-		// 0x5B => invalid
-		// 0x5C => invalid
+		0x5A => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x5B => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
+		0x5C => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x5D => Operation {
 			instruction_type: InstructionTypes::EOR,
 			cycle: 4, // +1 if page crossed
@@ -537,7 +791,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 7,
 			addressing_mode: AddressingModes::IndexedAbsoluteX
 		},
-		// 0x5F => invalid
+		0x5F => Operation { // unofficial
+			instruction_type: InstructionTypes::SRE,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x60 => Operation {
 			instruction_type: InstructionTypes::RTS,
 			cycle: 6,
@@ -548,9 +806,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0x62 => invalid
-		// 0x63 => invalid
-		// 0x64 => invalid
+		0x62 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x63 => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
+		0x64 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 3,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x65 => Operation {
 			instruction_type: InstructionTypes::ADC,
 			cycle: 3,
@@ -561,7 +831,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0x67 => invalid
+		0x67 => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 5,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x68 => Operation {
 			instruction_type: InstructionTypes::PLA,
 			cycle: 4,
@@ -577,7 +851,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Accumulator
 		},
-		// 0x6B => invalid
+		0x6B => Operation { // unofficial
+			instruction_type: InstructionTypes::ARR,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0x6C => Operation {
 			instruction_type: InstructionTypes::JMP,
 			cycle: 5,
@@ -593,7 +871,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0x6F => invalid
+		0x6F => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 6,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0x70 => Operation {
 			instruction_type: InstructionTypes::BVS,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -604,9 +886,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // @TODO +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0x72 => invalid
-		// 0x73 => invalid
-		// 0x74 => invalid
+		0x72 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x73 => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
+		0x74 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x75 => Operation {
 			instruction_type: InstructionTypes::ADC,
 			cycle: 4,
@@ -617,7 +911,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedZeroPageX
 		},
-		// 0x77 => invalid
+		0x77 => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0x78 => Operation {
 			instruction_type: InstructionTypes::SEI,
 			cycle: 2,
@@ -628,9 +926,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0x7A => invalid
-		// 0x7B => invalid
-		// 0x7C => invalid
+		0x7A => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0x7B => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
+		0x7C => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0x7D => Operation {
 			instruction_type: InstructionTypes::ADC,
 			cycle: 4, // +1 if page crossed
@@ -641,15 +951,31 @@ fn operation(opc: u8) -> Operation {
 			cycle: 7,
 			addressing_mode: AddressingModes::IndexedAbsoluteX
 		},
-		// 0x7F => invalid
-		// 0x80 => invalid
+		0x7F => Operation { // unofficial
+			instruction_type: InstructionTypes::RRA,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
+		0x80 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0x81 => Operation {
 			instruction_type: InstructionTypes::STA,
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0x82 => invalid
-		// 0x83 => invalid
+		0x82 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
+		0x83 => Operation { // unofficial
+			instruction_type: InstructionTypes::SAX,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
 		0x84 => Operation {
 			instruction_type: InstructionTypes::STY,
 			cycle: 3,
@@ -665,13 +991,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 3,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0x87 => invalid
+		0x87 => Operation { // unofficial
+			instruction_type: InstructionTypes::SAX,
+			cycle: 3,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0x88 => Operation {
 			instruction_type: InstructionTypes::DEY,
 			cycle: 2,
 			addressing_mode: AddressingModes::Implied
 		},
-		// 0x89 => invalid
+		0x89 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0x8A => Operation {
 			instruction_type: InstructionTypes::TXA,
 			cycle: 2,
@@ -693,7 +1027,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0x8F => invalid
+		0x8F => Operation { // unofficial
+			instruction_type: InstructionTypes::SAX,
+			cycle: 4,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0x90 => Operation {
 			instruction_type: InstructionTypes::BCC,
 			cycle: 2, // +1 if branch suceeds, +2 if to a new page
@@ -704,7 +1042,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0x92 => invalid
+		0x92 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
 		// 0x93 => invalid
 		0x94 => Operation {
 			instruction_type: InstructionTypes::STY,
@@ -721,7 +1063,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4,
 			addressing_mode: AddressingModes::IndexedZeroPageY
 		},
-		// 0x97 => invalid
+		0x97 => Operation { // unofficial
+			instruction_type: InstructionTypes::SAX,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageY
+		},
 		0x98 => Operation {
 			instruction_type: InstructionTypes::TYA,
 			cycle: 2,
@@ -761,7 +1107,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Immediate
 		},
-		// 0xA3 => invalid
+		0xA3 => Operation { // unofficial
+			instruction_type: InstructionTypes::LAX,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
 		0xA4 => Operation {
 			instruction_type: InstructionTypes::LDY,
 			cycle: 3,
@@ -777,7 +1127,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 3,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0xA7 => invalid
+		0xA7 => Operation { // unofficial
+			instruction_type: InstructionTypes::LAX,
+			cycle: 3,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0xA8 => Operation {
 			instruction_type: InstructionTypes::TAY,
 			cycle: 2,
@@ -809,7 +1163,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0xAF => invalid
+		0xAF => Operation { // unofficial
+			instruction_type: InstructionTypes::LAX,
+			cycle: 4,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0xB0 => Operation {
 			instruction_type: InstructionTypes::BCS,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -820,8 +1178,16 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0xB2 => invalid
-		// 0xB3 => invalid
+		0xB2 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0xB3 => Operation { // unofficial
+			instruction_type: InstructionTypes::LAX,
+			cycle: 5, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
 		0xB4 => Operation {
 			instruction_type: InstructionTypes::LDY,
 			cycle: 4,
@@ -837,7 +1203,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4,
 			addressing_mode: AddressingModes::IndexedZeroPageY
 		},
-		// 0xB7 => invalid
+		0xB7 => Operation { // unofficial
+			instruction_type: InstructionTypes::LAX,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageY
+		},
 		0xB8 => Operation {
 			instruction_type: InstructionTypes::CLV,
 			cycle: 2,
@@ -869,7 +1239,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0xBF => invalid
+		0xBF => Operation { // unofficial
+			instruction_type: InstructionTypes::LAX,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
 		0xC0 => Operation {
 			instruction_type: InstructionTypes::CPY,
 			cycle: 2,
@@ -880,8 +1254,16 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0xC2 => invalid
-		// 0xC3 => invalid
+		0xC2 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
+		0xC3 => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
 		0xC4 => Operation {
 			instruction_type: InstructionTypes::CPY,
 			cycle: 3,
@@ -897,7 +1279,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0xC7 => invalid
+		0xC7 => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 5,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0xC8 => Operation {
 			instruction_type: InstructionTypes::INY,
 			cycle: 2,
@@ -913,7 +1299,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Implied
 		},
-		// 0xCB => invalid
+		0xCB => Operation { // unofficial
+			instruction_type: InstructionTypes::AXS,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0xCC => Operation {
 			instruction_type: InstructionTypes::CPY,
 			cycle: 4,
@@ -929,7 +1319,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0xCF => invalid
+		0xCF => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 6,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0xD0 => Operation {
 			instruction_type: InstructionTypes::BNE,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -940,9 +1334,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0xD2 => invalid
-		// 0xD3 => invalid
-		// 0xD4 => invalid
+		0xD2 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0xD3 => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
+		0xD4 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0xD5 => Operation {
 			instruction_type: InstructionTypes::CMP,
 			cycle: 4,
@@ -953,7 +1359,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedZeroPageX
 		},
-		// 0xD7 => invalid
+		0xD7 => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0xD8 => Operation {
 			instruction_type: InstructionTypes::CLD,
 			cycle: 2,
@@ -964,10 +1374,22 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0xDA => invalid
-		// 0xDB => invalid
-		// 0xDC => invalid
-		0xDD => Operation {
+		0xDA => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0xDB => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
+		0xDC => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
+		0xDD => Operation {
 			instruction_type: InstructionTypes::CMP,
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteX
@@ -977,7 +1399,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 7,
 			addressing_mode: AddressingModes::IndexedAbsoluteX
 		},
-		// 0xDF => invalid
+		0xDF => Operation { // unofficial
+			instruction_type: InstructionTypes::DCP,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0xE0 => Operation {
 			instruction_type: InstructionTypes::CPX,
 			cycle: 2,
@@ -988,8 +1414,16 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedIndirectX
 		},
-		// 0xE2 => invalid
-		// 0xE3 => invalid
+		0xE2 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
+		0xE3 => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectX
+		},
 		0xE4 => Operation {
 			instruction_type: InstructionTypes::CPX,
 			cycle: 3,
@@ -1005,7 +1439,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5,
 			addressing_mode: AddressingModes::ZeroPage
 		},
-		// 0xE7 => invalid
+		0xE7 => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 5,
+			addressing_mode: AddressingModes::ZeroPage
+		},
 		0xE8 => Operation {
 			instruction_type: InstructionTypes::INX,
 			cycle: 2,
@@ -1021,7 +1459,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 2,
 			addressing_mode: AddressingModes::Implied
 		},
-		// 0xEB => invalid
+		0xEB => Operation { // unofficial
+			instruction_type: InstructionTypes::SBC,
+			cycle: 2,
+			addressing_mode: AddressingModes::Immediate
+		},
 		0xEC => Operation {
 			instruction_type: InstructionTypes::CPX,
 			cycle: 4,
@@ -1037,7 +1479,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::Absolute
 		},
-		// 0xEF => invalid
+		0xEF => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 6,
+			addressing_mode: AddressingModes::Absolute
+		},
 		0xF0 => Operation {
 			instruction_type: InstructionTypes::BEQ,
 			cycle: 2, // +1 if branch succeeds, +2 if to a new page
@@ -1048,9 +1494,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 5, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedIndirectY
 		},
-		// 0xF2 => invalid
-		// 0xF3 => invalid
-		// 0xF4 => invalid
+		0xF2 => Operation { // unofficial
+			instruction_type: InstructionTypes::JAM,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0xF3 => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 8,
+			addressing_mode: AddressingModes::IndexedIndirectY
+		},
+		0xF4 => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0xF5 => Operation {
 			instruction_type: InstructionTypes::SBC,
 			cycle: 4,
@@ -1061,7 +1519,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 6,
 			addressing_mode: AddressingModes::IndexedZeroPageX
 		},
-		// 0xF7 => invalid
+		0xF7 => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 6,
+			addressing_mode: AddressingModes::IndexedZeroPageX
+		},
 		0xF8 => Operation {
 			instruction_type: InstructionTypes::SED,
 			cycle: 2,
@@ -1072,9 +1534,21 @@ fn operation(opc: u8) -> Operation {
 			cycle: 4, // +1 if page crossed
 			addressing_mode: AddressingModes::IndexedAbsoluteY
 		},
-		// 0xFA => invalid
-		// 0xFB => invalid
-		// 0xFC => invalid
+		0xFA => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 2,
+			addressing_mode: AddressingModes::Implied
+		},
+		0xFB => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteY
+		},
+		0xFC => Operation { // unofficial
+			instruction_type: InstructionTypes::NOP,
+			cycle: 4, // +1 if page crossed
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		0xFD => Operation {
 			instruction_type: InstructionTypes::SBC,
 			cycle: 4, // +1 if page crossed
@@ -1085,7 +1559,11 @@ fn operation(opc: u8) -> Operation {
 			cycle: 7,
 			addressing_mode: AddressingModes::IndexedAbsoluteX
 		},
-		// 0xFF => invalid
+		0xFF => Operation { // unofficial
+			instruction_type: InstructionTypes::ISC,
+			cycle: 7,
+			addressing_mode: AddressingModes::IndexedAbsoluteX
+		},
 		_ => Operation {
 			instruction_type: InstructionTypes::INV,
 			cycle: 1,
@@ -1104,9 +1582,27 @@ impl Cpu {
 			x: Register::<u8>::new(),
 			y: Register::<u8>::new(),
 			p: CpuStatusRegister::new(),
-			ram: Memory::new(vec![0; 64 * 1024]), // 64KB
-			stall_cycles: 0,
+			ram: Memory::new(vec![0; 0x800]), // 2KB, mirrored up to 0x1FFF
+			region: Region::Ntsc,
+			timing: NTSC_TIMING,
+			master_clock: 0,
+			ppu_clock: 0,
+			cycle_count: 0,
+			clock_overshoot: 0,
+			nmi_line: false,
+			need_nmi: false,
+			prev_need_nmi: false,
+			run_irq: false,
+			prev_run_irq: false,
+			dma_halt_needed: false,
+			oam_dma_running: false,
+			oam_dma_page: 0,
+			dmc_dma_running: false,
+			dmc_dummy_read_needed: false,
+			halted: false,
+			last_fault: None,
 			input: input,
+			rewinding: false,
 			ppu: Ppu::new(display),
 			apu: Apu::new(audio),
 			joypad1: Joypad::new(),
@@ -1119,11 +1615,33 @@ impl Cpu {
 		self.rom = rom;
 	}
 
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+		self.timing = region.timing();
+		self.ppu.set_region(region);
+		self.apu.set_region(region);
+	}
+
+	pub fn get_region(&self) -> Region {
+		self.region
+	}
+
 	pub fn bootup(&mut self) {
 		self.power_on = true;
+		self.halted = false;
+		self.last_fault = None;
+		self.master_clock = 0;
+		self.ppu_clock = 0;
+		self.cycle_count = 0;
+		self.clock_overshoot = 0;
+		self.nmi_line = false;
+		self.clear_interrupts();
+		self.clear_dma();
 		self.bootup_internal();
 		self.ppu.bootup();
 		self.apu.bootup();
+		self.rom.bootup();
+		self.interrupt(Interrupts::RESET);
 	}
 
 	fn bootup_internal(&mut self) {
@@ -1139,11 +1657,13 @@ impl Cpu {
 
 		self.store(0x4015, 0);
 		self.store(0x4017, 0);
-
-		self.interrupt(Interrupts::RESET);
 	}
 
 	pub fn reset(&mut self) {
+		self.halted = false;
+		self.last_fault = None;
+		self.clear_interrupts();
+		self.clear_dma();
 		self.reset_internal();
 		self.ppu.reset();
 		self.apu.reset();
@@ -1154,11 +1674,37 @@ impl Cpu {
 		self.power_on
 	}
 
+	pub fn is_halted(&self) -> bool {
+		self.halted
+	}
+
+	pub fn last_fault(&self) -> Option<EmulationEvent> {
+		self.last_fault
+	}
+
+	pub fn is_rewinding(&self) -> bool {
+		self.rewinding
+	}
+
 	fn reset_internal(&mut self) {
 		self.sp.sub(3);
 		self.p.set_i();
 	}
 
+	fn clear_interrupts(&mut self) {
+		self.need_nmi = false;
+		self.prev_need_nmi = false;
+		self.run_irq = false;
+		self.prev_run_irq = false;
+	}
+
+	fn clear_dma(&mut self) {
+		self.dma_halt_needed = false;
+		self.oam_dma_running = false;
+		self.dmc_dma_running = false;
+		self.dmc_dummy_read_needed = false;
+	}
+
 	pub fn get_ppu(&self) -> &Ppu {
 		&self.ppu
 	}
@@ -1167,48 +1713,79 @@ impl Cpu {
 		&mut self.apu
 	}
 
+	pub fn get_rom(&self) -> &Rom {
+		&self.rom
+	}
+
+	pub fn get_mut_rom(&mut self) -> &mut Rom {
+		&mut self.rom
+	}
+
 	pub fn get_mut_input(&mut self) -> &mut Box<dyn Input> {
 		&mut self.input
 	}
 
 	//
 
+	// Executes an instruction. PPU and APU are clocked
+	// on every CPU cycle while executing.
 	pub fn step(&mut self) {
-		let stall_cycles = self.step_internal();
-		for _i in 0..stall_cycles * 3 {
-			self.ppu.step(&mut self.rom);
+		self.step_internal();
+	}
+
+	pub fn get_cycle_count(&self) -> u64 {
+		self.cycle_count
+	}
+
+	pub fn get_master_clock(&self) -> u64 {
+		self.master_clock
+	}
+
+	// Runs until `cycles` master clocks pass
+	pub fn run_cycles(&mut self, cycles: u64) {
+		if cycles <= self.clock_overshoot {
+			self.clock_overshoot -= cycles;
+			return;
 		}
-		for _i in 0..stall_cycles {
-			// No reference to CPU from APU so detecting if APU DMC needs
-			// CPU memory data, loading data, and sending to APU if needed
-			// @TODO: Simplify
-			let dmc_sample_data = match self.apu.dmc_needs_cpu_memory_data() {
-				true => {
-					// The CPU is stalled for up to 4 CPU cycles
-					// @TODO: Fix me
-					self.stall_cycles += 4;
-					self.load(self.apu.dmc_sample_address())
-				}
-				false => 0
-			};
-			self.apu.step(dmc_sample_data);
+		let target = self.master_clock + cycles - self.clock_overshoot;
+		while self.master_clock < target {
+			self.step();
 		}
+		self.clock_overshoot = self.master_clock - target;
 	}
 
-	pub fn step_frame(&mut self) {
-		// Input handling should be here? Or under nes.rs?
-		self.handle_inputs();
-		// @TODO: More precise frame update detection?
-		let ppu_frame = self.ppu.frame;
-		loop {
+	// Runs until PPU enters the scanline `line` next time.
+	// Scanlines are counted from the start of PPU so this works
+	// even if a long DMA skips the scanline.
+	pub fn run_until_scanline(&mut self, line: u16) {
+		let scanlines = self.timing.scanlines_per_frame as u64;
+		let line = match (line as u64) < scanlines {
+			true => line as u64,
+			false => scanlines - 1
+		};
+		let position = self.scanline_position();
+		let mut target = position - (position % scanlines) + line;
+		if target <= position {
+			target += scanlines;
+		}
+		while self.scanline_position() < target {
 			self.step();
-			if ppu_frame != self.ppu.frame {
-				break;
-			}
 		}
 	}
 
-	fn handle_inputs(&mut self) {
+	fn scanline_position(&self) -> u64 {
+		(self.ppu.frame as u64) * (self.timing.scanlines_per_frame as u64) +
+			(self.ppu.get_scanline() as u64)
+	}
+
+	// Runs until PPU starts the next frame at scanline 0. CPU stops at
+	// the instruction boundary right after it and the next frame continues
+	// from there so frames don't drift.
+	pub fn step_frame(&mut self) {
+		self.run_until_scanline(0);
+	}
+
+	pub fn handle_inputs(&mut self) {
 		while let Some((button, event)) = self.input.get_input() {
 			match button {
 				button::Button::Poweroff => {
@@ -1217,6 +1794,12 @@ impl Cpu {
 				button::Button::Reset => {
 					self.reset();
 				},
+				button::Button::Rewind => {
+					self.rewinding = match event {
+						button::Event::Press => true,
+						button::Event::Release => false
+					};
+				},
 				button::Button::Select |
 				button::Button::Start |
 				button::Button::Joypad1A |
@@ -1239,31 +1822,57 @@ impl Cpu {
 		}
 	}
 
-	fn step_internal(&mut self) -> u16 {
-		// @TODO: What if both NMI and IRQ happen?
-		if self.ppu.nmi_interrupted {
-			self.ppu.nmi_interrupted = false;
-			self.interrupt(Interrupts::NMI);
-		}
-		if self.ppu.irq_interrupted {
-			self.ppu.irq_interrupted = false;
-			self.interrupt(Interrupts::IRQ);
-		}
-		if self.apu.irq_interrupted {
-			self.apu.irq_interrupted = false;
-			self.interrupt(Interrupts::IRQ);
+	fn step_internal(&mut self) {
+		if self.halted {
+			// Jammed CPU ignores interrupts
+			self.start_cycle(true);
+			self.end_cycle(true);
+			return;
 		}
 
+		let start_cycle_count = self.cycle_count;
+		let address = self.pc.load();
 		let opc = self.fetch();
 		let op = self.decode(opc);
+		match op.instruction_type {
+			InstructionTypes::JAM => {
+				self.last_fault = Some(EmulationEvent::CpuJammed {
+					address: address,
+					opcode: opc
+				});
+			},
+			InstructionTypes::INV => {
+				self.last_fault = Some(EmulationEvent::UnsupportedOpcode {
+					address: address,
+					opcode: opc
+				});
+			},
+			_ => {}
+		};
+		match op.addressing_mode {
+			// The second cycle of instructions without operand
+			// reads the next byte and throws it away
+			AddressingModes::Implied | AddressingModes::Accumulator => {
+				let pc = self.pc.load();
+				self.dummy_read(pc);
+			},
+			_ => {}
+		};
 		self.operate(&op);
-		let stall_cycles = self.stall_cycles;
-		self.stall_cycles = 0;
-		stall_cycles + op.cycle as u16
+		debug_assert!(match op.instruction_type {
+			InstructionTypes::INV => true,
+			_ => self.cycle_count - start_cycle_count >= op.cycle as u64
+		});
+
+		// NMI and IRQ go through the same sequence.
+		// Which vector is used is decided in interrupt().
+		if self.prev_run_irq || self.prev_need_nmi {
+			self.interrupt(Interrupts::IRQ);
+		}
 	}
 
 	fn fetch(&mut self) -> u8 {
-		let opc = self.load(self.pc.load());
+		let opc = self.read(self.pc.load());
 		self.pc.increment();
 		opc
 	}
@@ -1274,20 +1883,28 @@ impl Cpu {
 
 	fn jump_to_interrupt_handler(&mut self, interrupt_type: Interrupts) {
 		let address = interrupt_handler_address(interrupt_type);
-		let value = self.load_2bytes(address);
+		let value = self.read_2bytes(address);
 		self.pc.store(value);
 	}
 
 	fn do_branch(&mut self, op: &Operation, flag: bool) {
 		let result = self.load_with_addressing_mode(&op);
 		if flag {
-			// stall_cycle + 1 if branch succeeds
-			self.stall_cycles += 1;
-			let current_page = self.pc.load() & 0xff00;
+			// A taken branch without page crossing ignores IRQ asserted
+			// during its last cycle so the next instruction is executed
+			// before IRQ
+			if self.run_irq && !self.prev_run_irq {
+				self.run_irq = false;
+			}
+
+			// One more cycle if branch succeeds
+			let current_pc = self.pc.load();
+			self.dummy_read(current_pc);
 			self.pc.add(result);
-			if current_page != (self.pc.load() & 0xff00) {
-				// stall_cycle + 1 if across page
-				self.stall_cycles += 1;
+			if (current_pc & 0xff00) != (self.pc.load() & 0xff00) {
+				// and one more cycle to fix the high byte of pc if across page
+				let address = (current_pc & 0xff00) | (self.pc.load() & 0xff);
+				self.dummy_read(address);
 			}
 		}
 	}
@@ -1296,22 +1913,8 @@ impl Cpu {
 	fn operate(&mut self, op: &Operation) {
 		match op.instruction_type {
 			InstructionTypes::ADC => {
-				let src1 = self.a.load();
-				let src2 = self.load_with_addressing_mode(&op);
-				let c = match self.p.is_c() {
-					true => 1,
-					false => 0
-				} as u16;
-				let result = (src1 as u16).wrapping_add(src2).wrapping_add(c);
-				self.a.store(result as u8);
-				self.update_n(result);
-				self.update_z(result);
-				self.update_c(result);
-				if !(((src1 ^ src2 as u8) & 0x80) != 0) && ((src2 as u8 ^ result as u8) & 0x80) != 0 {
-					self.p.set_v();
-				} else {
-					self.p.clear_v();
-				}
+				let src = self.load_with_addressing_mode(&op);
+				self.do_adc(src);
 			},
 			InstructionTypes::AND => {
 				let src1 = self.a.load();
@@ -1371,6 +1974,8 @@ impl Cpu {
 				self.p.set_a();
 				self.p.set_b();
 				self.interrupt(Interrupts::BRK);
+				// Doesn't take NMI right after BRK even if NMI hijacks BRK
+				self.prev_need_nmi = false;
 			},
 			InstructionTypes::BVC => {
 				let flag = !self.p.is_v();
@@ -1405,14 +2010,7 @@ impl Cpu {
 					}
 				};
 				let src2 = self.load_with_addressing_mode(&op);
-				let result = (src1 as u16).wrapping_sub(src2);
-				self.update_n(result);
-				self.update_z(result);
-				if src1 as u16 >= src2 {
-					self.p.set_c();
-				} else {
-					self.p.clear_c();
-				}
+				self.do_compare(src1, src2);
 			},
 			InstructionTypes::DEC => {
 				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
@@ -1455,8 +2053,13 @@ impl Cpu {
 				self.update_z(result);
 			},
 			InstructionTypes::INV => {
-				// @TODO: Throw?
-				println!("INV operation");
+				// Unstable unofficial opcodes. Reported in step_internal()
+				// and treated as NOP.
+			},
+			InstructionTypes::JAM => {
+				// The real CPU stops fetching instructions until reset.
+				// PPU and APU keep running.
+				self.halted = true;
 			},
 			InstructionTypes::INX | InstructionTypes::INY => {
 				let result = match op.instruction_type {
@@ -1481,13 +2084,17 @@ impl Cpu {
 				let address = self.get_address_with_addressing_mode(op);
 				self.pc.store(address);
 			},
-			// TODO: check the logic.
 			InstructionTypes::JSR => {
-				let address = self.get_address_with_addressing_mode(op);
-				self.pc.decrement();
+				// Pushes the address of the last byte of the instruction
+				// before reading it
+				let address_low = self.read(self.pc.load()) as u16;
+				self.pc.increment();
+				let stack_address = self.get_stack_address();
+				self.dummy_read(stack_address);
 				let value = self.pc.load();
 				self.push_stack_2bytes(value);
-				self.pc.store(address);
+				let address_high = self.read(self.pc.load()) as u16;
+				self.pc.store((address_high << 8) | address_low);
 			},
 			InstructionTypes::LDA | InstructionTypes::LDX | InstructionTypes::LDY => {
 				let result = match op.instruction_type {
@@ -1511,35 +2118,23 @@ impl Cpu {
 				self.update_z(result);
 			},
 			InstructionTypes::LSR => {
-				let result = match op.addressing_mode {
-					AddressingModes::Accumulator => {
-						let src = self.a.load();
-						if (src & 1) == 0 {
-							self.p.clear_c();
-						} else {
-							self.p.set_c();
-						}
-						let result = (src as u16) >> 1;
-						self.a.store(result as u8);
-						result
-					},
+				// Bit 8 of the closure result is the carry out
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(((src as u16) & 1) << 8) | ((src as u16) >> 1)
+				});
+				self.p.clear_n();
+				self.update_z(result);
+				self.update_c(result);
+			},
+			InstructionTypes::NOP => {
+				// Unofficial NOPs read their operand
+				match op.addressing_mode {
+					AddressingModes::Implied => {},
 					_ => {
-						let address = self.get_address_with_addressing_mode(op);
-						let src = self.load(address);
-						if (src & 1) == 0 {
-							self.p.clear_c();
-						} else {
-							self.p.set_c();
-						}
-						let result = (src as u16) >> 1;
-						self.store(address, result as u8);
-						result
+						self.load_with_addressing_mode(&op);
 					}
 				};
-				self.p.clear_n();
-				self.update_z(result);
 			},
-			InstructionTypes::NOP => {},
 			InstructionTypes::ORA => {
 				let src1 = self.a.load();
 				let src2 = self.load_with_addressing_mode(op);
@@ -1559,116 +2154,63 @@ impl Cpu {
 				self.push_stack(value);
 			},
 			InstructionTypes::PLA => {
+				let stack_address = self.get_stack_address();
+				self.dummy_read(stack_address);
 				let result = self.pop_stack() as u16;
 				self.a.store(result as u8);
 				self.update_n(result);
 				self.update_z(result);
 			},
 			InstructionTypes::PLP => {
+				let stack_address = self.get_stack_address();
+				self.dummy_read(stack_address);
 				let value = self.pop_stack();
 				self.p.store(value);
 			},
 			InstructionTypes::ROL => {
-				let result = match op.addressing_mode {
-					AddressingModes::Accumulator => {
-						let src = self.a.load();
-						let c = match self.p.is_c() {
-							true => 1,
-							false => 0
-						} as u16;
-						let result = ((src as u16) << 1) | c;
-						self.a.store(result as u8);
-						result
-					},
-					_ => {
-						let address = self.get_address_with_addressing_mode(op);
-						let src = self.load(address);
-						let c = match self.p.is_c() {
-							true => 1,
-							false => 0
-						} as u16;
-						let result = ((src as u16) << 1) | c;
-						self.store(address, result as u8);
-						result
-					}
-				};
+				let c = match self.p.is_c() {
+					true => 1,
+					false => 0
+				} as u16;
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					((src as u16) << 1) | c
+				});
 				self.update_n(result);
 				self.update_z(result);
 				self.update_c(result);
 			},
 			InstructionTypes::ROR => {
-				let result = match op.addressing_mode {
-					AddressingModes::Accumulator => {
-						let src = self.a.load();
-						let c = match self.p.is_c() {
-							true => 0x80,
-							false => 0
-						} as u16;
-						let result = ((src as u16) >> 1) | c;
-						self.a.store(result as u8);
-						if (src & 1) == 0 {
-							self.p.clear_c();
-						} else {
-							self.p.set_c();
-						}
-						result
-					},
-					_ => {
-						let address = self.get_address_with_addressing_mode(op);
-						let src = self.load(address);
-						let c = match self.p.is_c() {
-							true => 0x80,
-							false => 0
-						} as u16;
-						let result = ((src as u16) >> 1) | c;
-						self.store(address, result as u8);
-						if (src & 1) == 0 {
-							self.p.clear_c();
-						} else {
-							self.p.set_c();
-						}
-						result
-					}
-				};
+				// Bit 8 of the closure result is the carry out
+				let c = match self.p.is_c() {
+					true => 0x80,
+					false => 0
+				} as u16;
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(((src as u16) & 1) << 8) | ((src as u16) >> 1) | c
+				});
 				self.update_n(result);
 				self.update_z(result);
+				self.update_c(result);
 			},
-			// TODO: check logic.
 			InstructionTypes::RTI => {
+				let stack_address = self.get_stack_address();
+				self.dummy_read(stack_address);
 				let value = self.pop_stack();
 				self.p.store(value);
 				let value2 = self.pop_stack_2bytes();
 				self.pc.store(value2);
 			},
-			// TODO: check logic.
 			InstructionTypes::RTS => {
-				let value = self.pop_stack_2bytes().wrapping_add(1);
-				self.pc.store(value);
+				// Pulled address points to the last byte of JSR
+				let stack_address = self.get_stack_address();
+				self.dummy_read(stack_address);
+				let value = self.pop_stack_2bytes();
+				self.dummy_read(value);
+				self.pc.store(value.wrapping_add(1));
 			},
 			InstructionTypes::SBC => {
-				let src1 = self.a.load();
-				let src2 = self.load_with_addressing_mode(&op);
-				let c = match self.p.is_c() {
-					true => 0,
-					false => 1
-				} as u16;
-				let result = (src1 as u16).wrapping_sub(src2).wrapping_sub(c);
-				self.a.store(result as u8);
-				self.update_n(result);
-				self.update_z(result);
-				// TODO: check if this logic is right.
-				if src1 as u16 >= src2.wrapping_add(c) {
-					self.p.set_c();
-				} else {
-					self.p.clear_c();
-				}
-				// TODO: implement right overflow logic.
-				//       this is just a temporal logic.
-				if ((src1 ^ result as u8) & 0x80) != 0 && ((src1 ^ src2 as u8) & 0x80) != 0 {
-					self.p.set_v();
-				} else {
-					self.p.clear_v();
-				}
+				let src = self.load_with_addressing_mode(&op);
+				self.do_sbc(src);
 			},
 			InstructionTypes::SEC => {
 				self.p.set_c();
@@ -1724,10 +2266,194 @@ impl Cpu {
 				self.a.store(result as u8);
 				self.update_n(result);
 				self.update_z(result);
+			},
+			// unofficial
+			InstructionTypes::ALR => {
+				// AND + LSR A
+				let src = self.load_with_addressing_mode(&op);
+				let value = self.a.load() & (src as u8);
+				if (value & 1) == 0 {
+					self.p.clear_c();
+				} else {
+					self.p.set_c();
+				}
+				let result = (value as u16) >> 1;
+				self.a.store(result as u8);
+				self.update_n(result);
+				self.update_z(result);
+			},
+			InstructionTypes::ANC => {
+				// AND and copies N to C
+				let src = self.load_with_addressing_mode(&op);
+				let result = (self.a.load() as u16) & src;
+				self.a.store(result as u8);
+				self.update_n(result);
+				self.update_z(result);
+				if (result & 0x80) == 0 {
+					self.p.clear_c();
+				} else {
+					self.p.set_c();
+				}
+			},
+			InstructionTypes::ARR => {
+				// AND + ROR A. C is bit 6 and V is bit 6 xor bit 5 of the result.
+				let src = self.load_with_addressing_mode(&op);
+				let c = match self.p.is_c() {
+					true => 0x80,
+					false => 0
+				} as u16;
+				let result = (((self.a.load() as u16) & src) >> 1) | c;
+				self.a.store(result as u8);
+				self.update_n(result);
+				self.update_z(result);
+				if (result & 0x40) == 0 {
+					self.p.clear_c();
+				} else {
+					self.p.set_c();
+				}
+				if (((result >> 6) ^ (result >> 5)) & 1) == 0 {
+					self.p.clear_v();
+				} else {
+					self.p.set_v();
+				}
+			},
+			InstructionTypes::AXS => {
+				// X = (A & X) - operand without borrow, flags like CMP
+				let src1 = self.a.load() & self.x.load();
+				let src2 = self.load_with_addressing_mode(&op);
+				self.do_compare(src1, src2);
+				self.x.store(src1.wrapping_sub(src2 as u8));
+			},
+			InstructionTypes::DCP => {
+				// DEC + CMP
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(src as u16).wrapping_sub(1) & 0xFF
+				});
+				let src1 = self.a.load();
+				self.do_compare(src1, result);
+			},
+			InstructionTypes::ISC => {
+				// INC + SBC
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(src as u16).wrapping_add(1) & 0xFF
+				});
+				self.do_sbc(result);
+			},
+			InstructionTypes::LAX => {
+				// LDA + LDX
+				let result = self.load_with_addressing_mode(&op);
+				self.a.store(result as u8);
+				self.x.store(result as u8);
+				self.update_n(result);
+				self.update_z(result);
+			},
+			InstructionTypes::RLA => {
+				// ROL + AND
+				let c = match self.p.is_c() {
+					true => 1,
+					false => 0
+				} as u16;
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					((src as u16) << 1) | c
+				});
+				self.update_c(result);
+				let value = (self.a.load() as u16) & result;
+				self.a.store(value as u8);
+				self.update_n(value);
+				self.update_z(value);
+			},
+			InstructionTypes::RRA => {
+				// ROR + ADC. Bit 8 of the closure result is the carry out of ROR.
+				let c = match self.p.is_c() {
+					true => 0x80,
+					false => 0
+				} as u16;
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(((src as u16) & 1) << 8) | ((src as u16) >> 1) | c
+				});
+				self.update_c(result);
+				self.do_adc(result & 0xFF);
+			},
+			InstructionTypes::SAX => {
+				let value = self.a.load() & self.x.load();
+				self.store_with_addressing_mode(&op, value);
+			},
+			InstructionTypes::SLO => {
+				// ASL + ORA
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(src as u16) << 1
+				});
+				self.update_c(result);
+				let value = (self.a.load() as u16) | (result & 0xFF);
+				self.a.store(value as u8);
+				self.update_n(value);
+				self.update_z(value);
+			},
+			InstructionTypes::SRE => {
+				// LSR + EOR. Bit 8 of the closure result is the carry out of LSR.
+				let result = self.update_memory_with_addressing_mode(op, |src: u8| {
+					(((src as u16) & 1) << 8) | ((src as u16) >> 1)
+				});
+				self.update_c(result);
+				let value = (self.a.load() as u16) ^ (result & 0xFF);
+				self.a.store(value as u8);
+				self.update_n(value);
+				self.update_z(value);
 			}
 		}
 	}
 
+	fn do_adc(&mut self, src2: u16) {
+		let src1 = self.a.load();
+		let c = match self.p.is_c() {
+			true => 1,
+			false => 0
+		} as u16;
+		let result = (src1 as u16).wrapping_add(src2).wrapping_add(c);
+		self.a.store(result as u8);
+		self.update_n(result);
+		self.update_z(result);
+		self.update_c(result);
+		if !(((src1 ^ src2 as u8) & 0x80) != 0) && ((src2 as u8 ^ result as u8) & 0x80) != 0 {
+			self.p.set_v();
+		} else {
+			self.p.clear_v();
+		}
+	}
+
+	fn do_sbc(&mut self, src2: u16) {
+		let src1 = self.a.load();
+		let c = match self.p.is_c() {
+			true => 0,
+			false => 1
+		} as u16;
+		let result = (src1 as u16).wrapping_sub(src2).wrapping_sub(c);
+		self.a.store(result as u8);
+		self.update_n(result);
+		self.update_z(result);
+		if src1 as u16 >= src2.wrapping_add(c) {
+			self.p.set_c();
+		} else {
+			self.p.clear_c();
+		}
+		if ((src1 ^ result as u8) & 0x80) != 0 && ((src1 ^ src2 as u8) & 0x80) != 0 {
+			self.p.set_v();
+		} else {
+			self.p.clear_v();
+		}
+	}
+
+	fn do_compare(&mut self, src1: u8, src2: u16) {
+		let result = (src1 as u16).wrapping_sub(src2);
+		self.update_n(result);
+		self.update_z(result);
+		if src1 as u16 >= src2 {
+			self.p.set_c();
+		} else {
+			self.p.clear_c();
+		}
+	}
+
 	pub fn load(&mut self, address: u16) -> u8 {
 		// 0x0000 - 0x07FF: 2KB internal RAM
 		// 0x0800 - 0x1FFF: Mirrors of 0x0000 - 0x07FF (repeats every 0x800 bytes)
@@ -1740,7 +2466,7 @@ impl Cpu {
 		// 0x2008 - 0x3FFF: Mirrors of 0x2000 - 0x2007 (repeats every 8 bytes)
 
 		if address >= 0x2000 && address < 0x4000 {
-			return self.ppu.load_register(address & 0x2007, &self.rom);
+			return self.ppu.load_register(address & 0x2007, &mut self.rom);
 		}
 
 		if address >= 0x4000 && address < 0x4014 {
@@ -1748,7 +2474,7 @@ impl Cpu {
 		}
 
 		if address == 0x4014 {
-			return self.ppu.load_register(address, &self.rom);
+			return self.ppu.load_register(address, &mut self.rom);
 		}
 
 		if address == 0x4015 {
@@ -1767,15 +2493,7 @@ impl Cpu {
 			return self.apu.load_register(address);
 		}
 
-		if address >= 0x4020 && address < 0x6000 {
-			return self.ram.load(address as u32);
-		}
-
-		if address >= 0x6000 && address < 0x8000 {
-			return self.ram.load(address as u32);
-		}
-
-		if address >= 0x8000 {
+		if address >= 0x4020 {
 			return self.rom.load(address as u32);
 		}
 
@@ -1792,13 +2510,6 @@ impl Cpu {
 		self.ram.load((address & 0xff) as u32) as u16 | ((self.ram.load((address.wrapping_add(1) & 0xff) as u32) as u16) << 8)
 	}
 
-	fn load_2bytes_in_page(&mut self, address: u16) -> u16 {
-		let addr1 = address;
-		let addr2 = (address & 0xff00) | ((address.wrapping_add(1)) & 0xff);
-		let byte_low = self.load(addr1) as u16;
-		let byte_high = self.load(addr2) as u16;
-		(byte_high << 8) | byte_low
-	}
 
 	fn store(&mut self, address: u16, value: u8) {
 		// 0x0000 - 0x07FF: 2KB internal RAM
@@ -1813,28 +2524,23 @@ impl Cpu {
 
 		if address >= 0x2000 && address < 0x4000 {
 			self.ppu.store_register(address & 0x2007, value, &mut self.rom);
+			self.rom.notify_ppu_register_store(address & 0x2007, value);
 		}
 
 		if address >= 0x4000 && address < 0x4014 {
 			self.apu.store_register(address, value);
 		}
 
-		// @TODO: clean up
-
 		if address == 0x4014 {
 			self.ppu.store_register(address, value, &mut self.rom);
 
 			// DMA.
 			// Writing 0xXX will upload 256 bytes of data from CPU page
 			// 0xXX00-0xXXFF to the internal PPU OAM.
-			let offset = (value as u16) << 8;
-			for i in 0..256 {
-				let data = self.load(offset + i);
-				self.ppu.store_register(0x2004, data, &mut self.rom);
-			}
-
-			// @TODO
-			self.stall_cycles += 514;
+			// It starts on the next read cycle. See process_pending_dma().
+			self.oam_dma_page = value;
+			self.oam_dma_running = true;
+			self.dma_halt_needed = true;
 		}
 
 		if address == 0x4015 {
@@ -1851,38 +2557,202 @@ impl Cpu {
 		}
 
 		// cartridge space
+		// 0x4020 - 0x5FFF: Expansion area, mapper registers in some boards
+		// 0x6000 - 0x7FFF: Battery Backed Save or Work RAM in cartridge
+		// 0x8000 - 0xFFFF: ROM
 
-		if address >= 0x4020 && address < 0x6000 {
-			self.ram.store(address as u32, value);
+		if address >= 0x4020 {
+			self.rom.store(address as u32, value);
 		}
+	}
+
+	// Timed memory access. load() and store() above are
+	// raw bus access which don't take cycles.
+
+	fn read(&mut self, address: u16) -> u8 {
+		self.process_pending_dma(address);
+		self.start_cycle(true);
+		let value = self.load(address);
+		self.end_cycle(true);
+		value
+	}
+
+	// Reads whose values are thrown away. They still have
+	// side effects like clearing vblank flag by reading 0x2002.
+	fn dummy_read(&mut self, address: u16) {
+		self.read(address);
+	}
+
+	fn write(&mut self, address: u16, value: u8) {
+		self.start_cycle(false);
+		self.store(address, value);
+		self.end_cycle(false);
+	}
+
+	fn read_2bytes(&mut self, address: u16) -> u16 {
+		let byte_low = self.read(address) as u16;
+		let byte_high = self.read(address.wrapping_add(1)) as u16;
+		(byte_high << 8) | byte_low
+	}
+
+	// The high byte is read from the same page, used for
+	// JMP indirect and zero page pointers
+	fn read_2bytes_in_page(&mut self, address: u16) -> u16 {
+		let addr1 = address;
+		let addr2 = (address & 0xff00) | ((address.wrapping_add(1)) & 0xff);
+		let byte_low = self.read(addr1) as u16;
+		let byte_high = self.read(addr2) as u16;
+		(byte_high << 8) | byte_low
+	}
+
+	// A CPU read happens a master clock before the middle of a cycle
+	// and a write happens a master clock after. Based on Mesen's timing.
+
+	fn start_cycle(&mut self, for_read: bool) {
+		self.master_clock += match for_read {
+			true => self.timing.cpu_start_clock_count - 1,
+			false => self.timing.cpu_start_clock_count + 1
+		};
+		self.cycle_count += 1;
+		let clock = self.master_clock - self.timing.ppu_clock_offset;
+		self.run_ppu(clock);
+	}
 
-		// 0x6000 - 0x7FFF: Battery Backed Save or Work RAM
+	fn end_cycle(&mut self, for_read: bool) {
+		self.master_clock += match for_read {
+			true => self.timing.cpu_end_clock_count + 1,
+			false => self.timing.cpu_end_clock_count - 1
+		};
+		let clock = self.master_clock - self.timing.ppu_clock_offset;
+		self.run_ppu(clock);
+		self.rom.step();
+		self.apu.step(self.rom.audio_output());
+
+		// DMC has no access to CPU memory so CPU reads
+		// sample bytes for DMC with DMA
+		if !self.dmc_dma_running && self.apu.dmc_needs_dma() {
+			self.dmc_dma_running = true;
+			self.dma_halt_needed = true;
+			self.dmc_dummy_read_needed = true;
+		}
 
-		if address >= 0x6000 && address < 0x8000 {
-			self.ram.store(address as u32, value);
+		// Detects the rising edge of NMI line during the second half
+		// of the cycle. NMI is raised from the next cycle.
+		self.prev_need_nmi = self.need_nmi;
+		let nmi_line = self.ppu.nmi_line();
+		if nmi_line && !self.nmi_line {
+			self.need_nmi = true;
 		}
+		self.nmi_line = nmi_line;
 
-		// 0x8000 - 0xFFFF: ROM
+		// IRQ line is shared by APU and mapper.
+		// Each source keeps asserting until it's acknowledged.
+		self.prev_run_irq = self.run_irq;
+		self.run_irq = (self.apu.irq_line() || self.rom.irq_line()) && !self.p.is_i();
+	}
 
-		if address >= 0x8000 {
-			self.rom.store(address as u32, value);
+	// Runs PPU until it catches up the master clock
+	fn run_ppu(&mut self, clock: u64) {
+		while self.ppu_clock + self.timing.ppu_clock_divider <= clock {
+			self.ppu.step(&mut self.rom);
+			self.ppu_clock += self.timing.ppu_clock_divider;
 		}
 	}
 
-	pub fn interrupt(&mut self, interrupt_type: Interrupts) {
-		// @TODO: Optimize
+	fn start_dma_cycle(&mut self) {
+		if self.dma_halt_needed {
+			self.dma_halt_needed = false;
+		} else if self.dmc_dummy_read_needed {
+			self.dmc_dummy_read_needed = false;
+		}
+		self.start_cycle(true);
+	}
 
-		match interrupt_type {
-			Interrupts::IRQ => {
-				if self.p.is_i() {
-					return;
+	// DMA halts CPU on a read cycle. CPU repeats the read while halted.
+	// DMA reads on even (get) cycles and OAM DMA writes on odd (put) cycles.
+	// Refer to https://wiki.nesdev.com/w/index.php/DMA
+	fn process_pending_dma(&mut self, address: u16) {
+		if !self.dma_halt_needed {
+			return;
+		}
+
+		// Halt cycle
+		self.start_cycle(true);
+		self.load(address);
+		self.end_cycle(true);
+		self.dma_halt_needed = false;
+
+		let mut oam_dma_counter = 0 as u16;
+		let mut oam_dma_value = 0;
+		while self.dmc_dma_running || self.oam_dma_running {
+			let get_cycle = (self.cycle_count & 1) == 0;
+			if get_cycle {
+				if self.dmc_dma_running && !self.dma_halt_needed && !self.dmc_dummy_read_needed {
+					// DMC DMA reads after its halt and dummy cycles
+					self.start_dma_cycle();
+					let sample_address = self.apu.dmc_sample_address();
+					let value = self.load(sample_address);
+					self.end_cycle(true);
+					self.dmc_dma_running = false;
+					// DMC may be disabled while waiting
+					if self.apu.dmc_needs_dma() {
+						self.apu.fill_dmc_sample_buffer(value);
+					}
+				} else if self.oam_dma_running {
+					self.start_dma_cycle();
+					let oam_dma_address = ((self.oam_dma_page as u16) << 8) | (oam_dma_counter >> 1);
+					oam_dma_value = self.load(oam_dma_address);
+					self.end_cycle(true);
+					oam_dma_counter += 1;
+				} else {
+					// DMC DMA is waiting for its halt or dummy cycle
+					self.start_dma_cycle();
+					self.load(address);
+					self.end_cycle(true);
 				}
-			},
-			_ => {}
+			} else {
+				if self.oam_dma_running && (oam_dma_counter & 1) == 1 {
+					self.start_dma_cycle();
+					self.ppu.store_register(0x2004, oam_dma_value, &mut self.rom);
+					self.end_cycle(true);
+					oam_dma_counter += 1;
+					if oam_dma_counter == 0x200 {
+						self.oam_dma_running = false;
+					}
+				} else {
+					// Alignment cycle
+					self.start_dma_cycle();
+					self.load(address);
+					self.end_cycle(true);
+				}
+			}
 		}
+	}
 
+	// Interrupt disable flag for IRQ is checked when CPU polls interrupts
+	// in step_internal(), not here.
+	pub fn interrupt(&mut self, interrupt_type: Interrupts) {
 		match interrupt_type {
-			Interrupts::RESET => {},
+			// BRK has already read the padding byte
+			Interrupts::BRK => {},
+			_ => {
+				let pc = self.pc.load();
+				self.dummy_read(pc);
+				self.dummy_read(pc);
+			}
+		};
+
+		let interrupt_type = match interrupt_type {
+			Interrupts::RESET => {
+				// Goes through the same cycles as the other interrupts
+				// but reads the stack instead of writing.
+				// Stack pointer is updated by the callers.
+				for i in 0..3 {
+					let address = 0x100 | (self.sp.load().wrapping_sub(i) as u16);
+					self.dummy_read(address);
+				}
+				Interrupts::RESET
+			},
 			_ => {
 				match interrupt_type {
 					Interrupts::BRK => {},
@@ -1892,9 +2762,21 @@ impl Cpu {
 
 				let value = self.pc.load();
 				self.push_stack_2bytes(value);
+
+				// NMI detected by this point hijacks IRQ and BRK.
+				// The status register is pushed as is but NMI vector is used.
+				let interrupt_type = match self.need_nmi {
+					true => {
+						self.need_nmi = false;
+						Interrupts::NMI
+					},
+					false => interrupt_type
+				};
+
 				let value2 = self.p.load();
 				self.push_stack(value2);
 				self.p.set_i();
+				interrupt_type
 			}
 		};
 
@@ -1908,7 +2790,7 @@ impl Cpu {
 			},
 			_ => {
 				let address = self.get_address_with_addressing_mode(&op);
-				let value = self.load(address) as u16;
+				let value = self.read(address) as u16;
 				match op.addressing_mode {
 					// expects that relative addressing mode is used only for load.
 					AddressingModes::Relative => {
@@ -1932,7 +2814,7 @@ impl Cpu {
 			},
 			_ => {
 				let address = self.get_address_with_addressing_mode(op);
-				self.store(address, value);
+				self.write(address, value);
 			}
 		};
 	}
@@ -1947,9 +2829,11 @@ impl Cpu {
 			},
 			_ => {
 				let address = self.get_address_with_addressing_mode(op);
-				let src = self.load(address);
+				let src = self.read(address);
+				// Writes the original value back while modifying it
+				self.write(address, src);
 				let result = func(src);
-				self.store(address, result as u8);
+				self.write(address, result as u8);
 				result
 			}
 		}
@@ -1963,79 +2847,69 @@ impl Cpu {
 				address
 			},
 			AddressingModes::Absolute | AddressingModes::IndexedAbsoluteX | AddressingModes::IndexedAbsoluteY => {
-				let address = self.load_2bytes(self.pc.load());
+				let address = self.read_2bytes(self.pc.load());
 				self.pc.increment_by_2();
 				let effective_address = address.wrapping_add(match op.addressing_mode {
 					AddressingModes::IndexedAbsoluteX => self.x.load(),
 					AddressingModes::IndexedAbsoluteY => self.y.load(),
 					_ => 0
 				} as u16);
-				match op.instruction_type {
-					InstructionTypes::ADC |
-					InstructionTypes::AND |
-					InstructionTypes::CMP |
-					InstructionTypes::EOR |
-					InstructionTypes::LDA |
-					InstructionTypes::LDY |
-					InstructionTypes::LDX |
-					InstructionTypes::ORA |
-					InstructionTypes::SBC => {
-						// stall_cycles + 1 if page is crossed
-						if (address & 0xff00) != (effective_address & 0xff00) {
-							self.stall_cycles += 1;
+				match op.addressing_mode {
+					AddressingModes::Absolute => {},
+					_ => {
+						// Reads from the address without fixing the high byte first
+						if (address & 0xff00) != (effective_address & 0xff00) ||
+							!is_read_instruction(&op.instruction_type) {
+							self.dummy_read((address & 0xff00) | (effective_address & 0xff));
 						}
-					},
-					_ => {}
+					}
 				};
 				effective_address
 			},
 			AddressingModes::ZeroPage | AddressingModes::IndexedZeroPageX | AddressingModes::IndexedZeroPageY => {
 				let address = self.pc.load();
-				let address2 = self.load(address) as u16;
+				let address2 = self.read(address) as u16;
 				self.pc.increment();
-				address2.wrapping_add(match op.addressing_mode {
-					AddressingModes::IndexedZeroPageX => self.x.load(),
-					AddressingModes::IndexedZeroPageY => self.y.load(),
-					_ => 0
-				} as u16) & 0xFF
+				match op.addressing_mode {
+					AddressingModes::ZeroPage => address2,
+					_ => {
+						// Reads from the base address while adding index
+						self.dummy_read(address2);
+						address2.wrapping_add(match op.addressing_mode {
+							AddressingModes::IndexedZeroPageX => self.x.load(),
+							_ => self.y.load()
+						} as u16) & 0xFF
+					}
+				}
 			},
 			AddressingModes::Indirect => {
 				let address = self.pc.load();
-				let tmp = self.load_2bytes(address);
+				let tmp = self.read_2bytes(address);
 				self.pc.increment_by_2();
-				self.load_2bytes_in_page(tmp)
+				self.read_2bytes_in_page(tmp)
 			},
 			AddressingModes::IndexedIndirectX => {
 				let address = self.pc.load();
-				let tmp = self.load(address);
+				let tmp = self.read(address);
 				self.pc.increment();
-				self.load_2bytes_from_zeropage(((tmp.wrapping_add(self.x.load())) & 0xFF) as u16)
+				// Reads from the pointer while adding X
+				self.dummy_read(tmp as u16);
+				self.read_2bytes_in_page(tmp.wrapping_add(self.x.load()) as u16)
 			},
 			AddressingModes::IndexedIndirectY => {
 				let address = self.pc.load();
-				let tmp = self.load(address);
+				let tmp = self.read(address);
 				self.pc.increment();
-				let address2 = self.load_2bytes_from_zeropage(tmp as u16);
+				let address2 = self.read_2bytes_in_page(tmp as u16);
 				let effective_address = address2.wrapping_add(self.y.load() as u16);
-				match op.instruction_type {
-					InstructionTypes::AND |
-					InstructionTypes::CMP |
-					InstructionTypes::EOR |
-					InstructionTypes::LDA |
-					InstructionTypes::ORA |
-					InstructionTypes::SBC => {
-						// stall_cycles + 1 if page is crossed
-						if (address2 & 0xff00) != (effective_address & 0xff00) {
-							self.stall_cycles += 1;
-						}
-					},
-					_ => {}
-				};
+				if (address2 & 0xff00) != (effective_address & 0xff00) ||
+					!is_read_instruction(&op.instruction_type) {
+					self.dummy_read((address2 & 0xff00) | (effective_address & 0xff));
+				}
 				effective_address
 			},
 			_ => {
-				// @TODO: Throw?
-				println!("Unknown addressing mode.");
+				// Implied and Accumulator have no address
 				0
 			}
 		}
@@ -2071,32 +2945,143 @@ impl Cpu {
 
 	fn push_stack(&mut self, value: u8) {
 		let address = self.get_stack_address();
-		self.store(address, value);
+		self.write(address, value);
 		self.sp.decrement();
 	}
 
 	fn push_stack_2bytes(&mut self, value: u16) {
 		let address = self.get_stack_address();
-		self.store(address, ((value >> 8) & 0xff) as u8);
+		self.write(address, ((value >> 8) & 0xff) as u8);
 		self.sp.decrement();
 		let address2 = self.get_stack_address();
-		self.store(address2, (value & 0xff) as u8);
+		self.write(address2, (value & 0xff) as u8);
 		self.sp.decrement();
 	}
 
 	fn pop_stack(&mut self) -> u8 {
 		self.sp.increment();
-		self.load(self.get_stack_address())
+		self.read(self.get_stack_address())
 	}
 
 	fn pop_stack_2bytes(&mut self) -> u16 {
 		self.sp.increment();
-		let byte_low = self.load(self.get_stack_address()) as u16;
+		let byte_low = self.read(self.get_stack_address()) as u16;
 		self.sp.increment();
-		let byte_high = self.load(self.get_stack_address()) as u16;
+		let byte_high = self.read(self.get_stack_address()) as u16;
 		(byte_high << 8) | byte_low
 	}
 
+	pub fn save_state(&self, writer: &mut StateWriter) {
+		// The region first because the rest is timed with it
+		writer.write_u8(match self.region {
+			Region::Ntsc => 0,
+			Region::Pal => 1,
+			Region::Dendy => 2
+		});
+		writer.write_bool(self.power_on);
+		writer.write_register_u16(&self.pc);
+		writer.write_register_u8(&self.sp);
+		writer.write_register_u8(&self.a);
+		writer.write_register_u8(&self.x);
+		writer.write_register_u8(&self.y);
+		writer.write_register_u8(&self.p.register);
+		writer.write_memory(&self.ram);
+		writer.write_u64(self.master_clock);
+		writer.write_u64(self.ppu_clock);
+		writer.write_u64(self.cycle_count);
+		writer.write_u64(self.clock_overshoot);
+		writer.write_bool(self.nmi_line);
+		writer.write_bool(self.need_nmi);
+		writer.write_bool(self.prev_need_nmi);
+		writer.write_bool(self.run_irq);
+		writer.write_bool(self.prev_run_irq);
+		writer.write_bool(self.dma_halt_needed);
+		writer.write_bool(self.oam_dma_running);
+		writer.write_u8(self.oam_dma_page);
+		writer.write_bool(self.dmc_dma_running);
+		writer.write_bool(self.dmc_dummy_read_needed);
+		writer.write_bool(self.halted);
+		self.save_fault(writer);
+		self.ppu.save_state(writer);
+		self.apu.save_state(writer);
+		self.joypad1.save_state(writer);
+		self.joypad2.save_state(writer);
+		self.rom.save_state(writer);
+	}
+
+	pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		let region = match reader.read_u8()? {
+			0 => Region::Ntsc,
+			1 => Region::Pal,
+			2 => Region::Dendy,
+			_ => return Err(StateError::InvalidData)
+		};
+		self.set_region(region);
+		self.power_on = reader.read_bool()?;
+		reader.read_register_u16(&mut self.pc)?;
+		reader.read_register_u8(&mut self.sp)?;
+		reader.read_register_u8(&mut self.a)?;
+		reader.read_register_u8(&mut self.x)?;
+		reader.read_register_u8(&mut self.y)?;
+		reader.read_register_u8(&mut self.p.register)?;
+		reader.read_memory(&mut self.ram)?;
+		self.master_clock = reader.read_u64()?;
+		self.ppu_clock = reader.read_u64()?;
+		self.cycle_count = reader.read_u64()?;
+		self.clock_overshoot = reader.read_u64()?;
+		self.nmi_line = reader.read_bool()?;
+		self.need_nmi = reader.read_bool()?;
+		self.prev_need_nmi = reader.read_bool()?;
+		self.run_irq = reader.read_bool()?;
+		self.prev_run_irq = reader.read_bool()?;
+		self.dma_halt_needed = reader.read_bool()?;
+		self.oam_dma_running = reader.read_bool()?;
+		self.oam_dma_page = reader.read_u8()?;
+		self.dmc_dma_running = reader.read_bool()?;
+		self.dmc_dummy_read_needed = reader.read_bool()?;
+		self.halted = reader.read_bool()?;
+		self.load_fault(reader)?;
+		self.ppu.load_state(reader)?;
+		self.apu.load_state(reader)?;
+		self.joypad1.load_state(reader)?;
+		self.joypad2.load_state(reader)?;
+		self.rom.load_state(reader)
+	}
+
+	// Fault kind, 0: none, 1: CpuJammed, 2: UnsupportedOpcode,
+	// followed by the address and the opcode
+	fn save_fault(&self, writer: &mut StateWriter) {
+		match self.last_fault {
+			None => writer.write_u8(0),
+			Some(EmulationEvent::CpuJammed { address, opcode }) => {
+				writer.write_u8(1);
+				writer.write_u16(address);
+				writer.write_u8(opcode);
+			},
+			Some(EmulationEvent::UnsupportedOpcode { address, opcode }) => {
+				writer.write_u8(2);
+				writer.write_u16(address);
+				writer.write_u8(opcode);
+			}
+		};
+	}
+
+	fn load_fault(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		self.last_fault = match reader.read_u8()? {
+			0 => None,
+			1 => Some(EmulationEvent::CpuJammed {
+				address: reader.read_u16()?,
+				opcode: reader.read_u8()?
+			}),
+			2 => Some(EmulationEvent::UnsupportedOpcode {
+				address: reader.read_u16()?,
+				opcode: reader.read_u8()?
+			}),
+			_ => return Err(StateError::InvalidData)
+		};
+		Ok(())
+	}
+
 	pub fn dump(&mut self) -> String {
 		let opc = self.load(self.pc.load());
 		let op = self.decode(opc);
@@ -2318,3 +3303,396 @@ impl CpuStatusRegister {
 		&")".to_owned()
 	}
 }
+
+#[cfg(test)]
+mod tests_cpu {
+	use super::*;
+	use default_input::DefaultInput;
+	use default_display::DefaultDisplay;
+	use default_audio::DefaultAudio;
+
+	// Places the program at 0x0200 in internal RAM and runs `steps` instructions
+	fn run(program: &[u8], steps: u32) -> Cpu {
+		let mut cpu = Cpu::new(
+			Box::new(DefaultInput::new()),
+			Box::new(DefaultDisplay::new()),
+			Box::new(DefaultAudio::new())
+		);
+		// NROM with 16KB PRG-ROM filled with 0 except for interrupt vectors.
+		// NMI: 0x0300, RESET: 0x0200, IRQ/BRK: 0x0400
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1];
+		rom.resize(HEADER_SIZE + 0x4000, 0);
+		rom[HEADER_SIZE + 0x3FFB] = 0x03;
+		rom[HEADER_SIZE + 0x3FFD] = 0x02;
+		rom[HEADER_SIZE + 0x3FFF] = 0x04;
+		cpu.set_rom(Rom::new(rom));
+		for (i, value) in program.iter().enumerate() {
+			cpu.ram.store(0x200 + i as u32, *value);
+		}
+		cpu.pc.store(0x200);
+		cpu.sp.store(0xFD);
+		cpu.p.store(0x24);
+		for _ in 0..steps {
+			cpu.step_internal();
+		}
+		cpu
+	}
+
+	#[test]
+	fn lax_and_sax() {
+		// LDA #$F0; LDX #$3C; SAX $10; LAX $10
+		let cpu = run(&[0xA9, 0xF0, 0xA2, 0x3C, 0x87, 0x10, 0xA7, 0x10], 4);
+		assert_eq!(0x30, cpu.ram.load(0x10));
+		assert_eq!(0x30, cpu.a.load());
+		assert_eq!(0x30, cpu.x.load());
+		assert_eq!(0x208, cpu.pc.load());
+	}
+
+	#[test]
+	fn dcp_and_isc() {
+		// LDA #$10; DCP $20 ($11 -> $10); SEC; ISC $21 ($0F -> $10)
+		let mut cpu = run(&[0xA9, 0x10, 0xC7, 0x20, 0x38, 0xE7, 0x21], 0);
+		cpu.ram.store(0x20, 0x11);
+		cpu.ram.store(0x21, 0x0F);
+		for _ in 0..2 {
+			cpu.step_internal();
+		}
+		assert_eq!(0x10, cpu.ram.load(0x20));
+		assert_eq!(true, cpu.p.is_z());
+		assert_eq!(true, cpu.p.is_c());
+		for _ in 0..2 {
+			cpu.step_internal();
+		}
+		assert_eq!(0x10, cpu.ram.load(0x21));
+		assert_eq!(0, cpu.a.load());
+		assert_eq!(true, cpu.p.is_z());
+	}
+
+	#[test]
+	fn slo_and_sre() {
+		// LDA #$01; SLO $30 ($81 -> $02, C=1); SRE $31 ($03 -> $01, C=1)
+		let mut cpu = run(&[0xA9, 0x01, 0x07, 0x30, 0x47, 0x31], 0);
+		cpu.ram.store(0x30, 0x81);
+		cpu.ram.store(0x31, 0x03);
+		for _ in 0..2 {
+			cpu.step_internal();
+		}
+		assert_eq!(0x02, cpu.ram.load(0x30));
+		assert_eq!(0x03, cpu.a.load());
+		assert_eq!(true, cpu.p.is_c());
+		cpu.step_internal();
+		assert_eq!(0x01, cpu.ram.load(0x31));
+		assert_eq!(0x02, cpu.a.load());
+		assert_eq!(true, cpu.p.is_c());
+	}
+
+	#[test]
+	fn immediate_unofficial() {
+		// LDA #$FF; ANC #$80; ARR #$C0; LDX #$0F; AXS #$02
+		let mut cpu = run(&[0xA9, 0xFF, 0x0B, 0x80, 0x6B, 0xC0, 0xA2, 0x0F, 0xCB, 0x02], 3);
+		// ANC: A = $80, C = 1. ARR: A = ($80 & $C0) >> 1 | $80 = $C0
+		assert_eq!(0xC0, cpu.a.load());
+		assert_eq!(true, cpu.p.is_c());
+		assert_eq!(true, cpu.p.is_v());
+		for _ in 0..2 {
+			cpu.step_internal();
+		}
+		// AXS: X = ($C0 & $0F) - 2
+		assert_eq!(0xFE, cpu.x.load());
+		assert_eq!(false, cpu.p.is_c());
+	}
+
+	#[test]
+	fn jam() {
+		// LDA #$01; JAM; LDA #$02
+		let mut cpu = run(&[0xA9, 0x01, 0x02, 0xA9, 0x02], 2);
+		assert_eq!(true, cpu.is_halted());
+		assert_eq!(Some(EmulationEvent::CpuJammed { address: 0x202, opcode: 0x02 }), cpu.last_fault());
+		cpu.step_internal();
+		assert_eq!(0x01, cpu.a.load());
+		assert_eq!(0x203, cpu.pc.load());
+		cpu.reset();
+		assert_eq!(false, cpu.is_halted());
+		assert_eq!(None, cpu.last_fault());
+	}
+
+	#[test]
+	fn jam_state() {
+		// LDA #$01; JAM
+		let cpu = run(&[0xA9, 0x01, 0x02], 2);
+		let mut writer = StateWriter::new();
+		cpu.save_state(&mut writer);
+		let data = writer.into_vec();
+
+		// The state restores the halt and the fault
+		let mut cpu2 = run(&[0xA9, 0x01, 0x02], 0);
+		assert_eq!(Ok(()), cpu2.load_state(&mut StateReader::new(&data)));
+		assert_eq!(true, cpu2.is_halted());
+		assert_eq!(Some(EmulationEvent::CpuJammed { address: 0x202, opcode: 0x02 }), cpu2.last_fault());
+
+		// Running state clears them
+		let cpu3 = run(&[0xA9, 0x01], 1);
+		let mut writer = StateWriter::new();
+		cpu3.save_state(&mut writer);
+		assert_eq!(Ok(()), cpu2.load_state(&mut StateReader::new(&writer.into_vec())));
+		assert_eq!(false, cpu2.is_halted());
+		assert_eq!(None, cpu2.last_fault());
+
+		// Unknown fault kind
+		let mut writer = StateWriter::new();
+		writer.write_u8(3);
+		writer.write_u16(0x202);
+		writer.write_u8(0x02);
+		assert_eq!(Err(StateError::InvalidData), cpu2.load_fault(&mut StateReader::new(&writer.into_vec())));
+	}
+
+	#[test]
+	fn instruction_cycles() {
+		let mut program = vec![
+			0xA2, 0x01, // LDX #$01
+			0xBD, 0xFF, 0x02, // LDA $02FF,X (page crossed)
+			0xBD, 0x00, 0x02, // LDA $0200,X
+			0x9D, 0x00, 0x03, // STA $0300,X
+			0xE6, 0x10, // INC $10
+			0x0A, // ASL A
+			0x48, // PHA
+			0x68, // PLA
+			0x20, 0x20, 0x02, // JSR $0220
+			0x18, // CLC
+			0x90, 0x00 // BCC +0
+		];
+		program.resize(0x20, 0);
+		program.push(0x60); // RTS
+		let mut cpu = run(&program, 0);
+		for cycles in [2, 5, 4, 5, 5, 2, 3, 4, 6, 6, 2, 3].iter() {
+			let cycle_count = cpu.get_cycle_count();
+			cpu.step_internal();
+			assert_eq!(*cycles, cpu.get_cycle_count() - cycle_count);
+		}
+		assert_eq!(0x216, cpu.pc.load());
+	}
+
+	// Asserts IRQ line with DMC IRQ by playing a one byte sample
+	fn assert_dmc_irq(cpu: &mut Cpu) {
+		cpu.store(0x4010, 0x80);
+		cpu.store(0x4013, 0x00);
+		cpu.store(0x4015, 0x10);
+	}
+
+	#[test]
+	fn cli_latency() {
+		// NOP; NOP; CLI; INX; INX
+		let mut cpu = run(&[0xEA, 0xEA, 0x58, 0xE8, 0xE8], 0);
+		assert_dmc_irq(&mut cpu);
+		for _ in 0..3 {
+			cpu.step_internal();
+		}
+		assert_eq!(true, cpu.apu.irq_line());
+		assert_eq!(0x203, cpu.pc.load());
+		// IRQ is taken after the instruction following CLI
+		cpu.step_internal();
+		assert_eq!(0x400, cpu.pc.load());
+		assert_eq!(1, cpu.x.load());
+		assert_eq!(0x02, cpu.ram.load(0x1FD));
+		assert_eq!(0x04, cpu.ram.load(0x1FC));
+	}
+
+	#[test]
+	fn irq_after_sei() {
+		// NOP; CLI; SEI; INX
+		let mut cpu = run(&[0xEA, 0x58, 0x78, 0xE8], 0);
+		assert_dmc_irq(&mut cpu);
+		for _ in 0..2 {
+			cpu.step_internal();
+		}
+		assert_eq!(0x202, cpu.pc.load());
+		// SEI polls IRQ before setting the flag so IRQ is taken
+		// and the pushed status register has the flag set
+		cpu.step_internal();
+		assert_eq!(0x400, cpu.pc.load());
+		assert_eq!(0x03, cpu.ram.load(0x1FC));
+		assert_eq!(0x04, cpu.ram.load(0x1FB) & 0x04);
+		assert_eq!(0, cpu.x.load());
+	}
+
+	#[test]
+	fn nmi_hijacks_brk() {
+		// BRK
+		let mut cpu = run(&[0x00, 0x00], 0);
+		cpu.need_nmi = true;
+		cpu.step_internal();
+		// Pushes the status register with B flag but jumps to NMI handler
+		assert_eq!(0x300, cpu.pc.load());
+		assert_eq!(0x10, cpu.ram.load(0x1FB) & 0x10);
+		assert_eq!(false, cpu.need_nmi);
+	}
+
+	#[test]
+	fn run_until_scanline() {
+		// JMP $0200
+		let mut cpu = run(&[0x4C, 0x00, 0x02], 0);
+		cpu.run_until_scanline(100);
+		assert_eq!(100, cpu.ppu.get_scanline());
+		assert_eq!(0, cpu.ppu.frame);
+		cpu.run_until_scanline(100);
+		assert_eq!(100, cpu.ppu.get_scanline());
+		assert_eq!(1, cpu.ppu.frame);
+		cpu.step_frame();
+		assert_eq!(0, cpu.ppu.get_scanline());
+		assert_eq!(2, cpu.ppu.frame);
+	}
+
+	#[test]
+	fn pal_frame() {
+		// JMP $0200
+		let mut cpu = run(&[0x4C, 0x00, 0x02], 0);
+		cpu.set_region(Region::Pal);
+		// PAL has 312 scanlines
+		cpu.run_until_scanline(300);
+		assert_eq!(300, cpu.ppu.get_scanline());
+		assert_eq!(0, cpu.ppu.frame);
+		cpu.step_frame();
+		let clock = cpu.get_master_clock();
+		cpu.step_frame();
+		// 341 dots * 312 scanlines, a dot is 5 master clocks.
+		// CPU stops at an instruction boundary so +-3 CPU cycles.
+		let frame_clocks = (cpu.get_master_clock() - clock) as i64;
+		assert!((frame_clocks - 341 * 312 * 5).abs() < 3 * 16);
+	}
+
+	#[test]
+	fn region_state() {
+		// JMP $0200
+		let mut cpu = run(&[0x4C, 0x00, 0x02], 0);
+		cpu.set_region(Region::Pal);
+		cpu.run_until_scanline(300);
+		let mut writer = StateWriter::new();
+		cpu.save_state(&mut writer);
+		let data = writer.into_vec();
+
+		// The state restores the region
+		let mut cpu2 = run(&[0x4C, 0x00, 0x02], 0);
+		assert_eq!(Ok(()), cpu2.load_state(&mut StateReader::new(&data)));
+		assert_eq!(Region::Pal, cpu2.get_region());
+		assert_eq!(300, cpu2.ppu.get_scanline());
+
+		let mut broken = data.clone();
+		broken[0] = 3;
+		assert_eq!(Err(StateError::InvalidData), cpu2.load_state(&mut StateReader::new(&broken)));
+	}
+
+	// Sets up MMC3 IRQ firing at the scanline `latch` with rendering
+	fn run_mmc3(ppuctrl: u8, latch: u8) -> Cpu {
+		// JMP $0200
+		let mut cpu = run(&[0x4C, 0x00, 0x02], 0);
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x40];
+		rom.resize(HEADER_SIZE + 0x8000 + 0x2000, 0);
+		cpu.set_rom(Rom::new(rom));
+		// Hides all the sprites below the screen
+		cpu.store(0x2003, 0);
+		for _ in 0..256 {
+			cpu.store(0x2004, 0xFF);
+		}
+		cpu.store(0x2000, ppuctrl);
+		cpu.store(0x2001, 0x18);
+		// Sets up in vblank
+		cpu.run_until_scanline(250);
+		cpu.store(0xC000, latch);
+		cpu.store(0xC001, 0);
+		cpu.store(0xE001, 0);
+		cpu
+	}
+
+	fn assert_mmc3_irq_scanline(ppuctrl: u8, scanline: u16) {
+		let mut cpu = run_mmc3(ppuctrl, 10);
+		cpu.run_until_scanline(scanline);
+		assert_eq!(false, cpu.rom.irq_line());
+		cpu.run_until_scanline(scanline + 1);
+		assert_eq!(true, cpu.rom.irq_line());
+	}
+
+	#[test]
+	fn mmc3_irq_with_sprites_at_1000() {
+		// Clocked at cycle 260 of the pre-render scanline for reload
+		// and reaches 0 on scanline 9
+		assert_mmc3_irq_scanline(0x08, 9);
+	}
+
+	#[test]
+	fn mmc3_irq_with_background_at_1000() {
+		// Clocked at the first background tile fetch and cycle 324
+		// of the pre-render scanline so reaches 0 on scanline 8
+		assert_mmc3_irq_scanline(0x10, 8);
+	}
+
+	#[test]
+	fn mmc3_irq_with_8x16_sprites() {
+		// Empty sprite slots fetch tile 0xFF from 0x1000
+		assert_mmc3_irq_scanline(0x20, 9);
+	}
+
+	#[test]
+	fn mmc3_irq_without_rendering() {
+		let mut cpu = run_mmc3(0x08, 10);
+		cpu.store(0x2001, 0);
+		cpu.step_frame();
+		assert_eq!(false, cpu.rom.irq_line());
+	}
+
+	// Sets up MMC5 scanline IRQ at the scanline `scanline` with rendering
+	fn run_mmc5(scanline: u8) -> Cpu {
+		// JMP $0200
+		let mut cpu = run(&[0x4C, 0x00, 0x02], 0);
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x50];
+		rom.resize(HEADER_SIZE + 0x8000 + 0x2000, 0);
+		cpu.set_rom(Rom::new(rom));
+		cpu.store(0x2001, 0x18);
+		cpu.run_until_scanline(250);
+		cpu.store(0x5203, scanline);
+		cpu.store(0x5204, 0x80);
+		cpu
+	}
+
+	#[test]
+	fn mmc5_irq() {
+		// Fires at the start of scanline 10
+		let mut cpu = run_mmc5(10);
+		cpu.run_until_scanline(9);
+		assert_eq!(false, cpu.rom.irq_line());
+		cpu.run_until_scanline(11);
+		assert_eq!(true, cpu.rom.irq_line());
+		// In-frame flag is set while rendering and reading the status acknowledges IRQ
+		assert_eq!(0xC0, cpu.load(0x5204));
+		assert_eq!(false, cpu.rom.irq_line());
+		cpu.run_until_scanline(241);
+		assert_eq!(0x00, cpu.load(0x5204));
+	}
+
+	#[test]
+	fn mmc5_irq_without_rendering() {
+		let mut cpu = run_mmc5(10);
+		cpu.store(0x2001, 0);
+		cpu.step_frame();
+		assert_eq!(false, cpu.rom.irq_line());
+	}
+
+	#[test]
+	fn run_cycles() {
+		// JMP $0200
+		let mut cpu = run(&[0x4C, 0x00, 0x02], 0);
+		for _ in 0..10 {
+			cpu.run_cycles(100);
+		}
+		// JMP takes 3 CPU cycles so the excess is less than that
+		assert!(cpu.get_master_clock() >= 1000);
+		assert!(cpu.get_master_clock() < 1000 + 3 * 12);
+		assert_eq!(cpu.get_master_clock() / 12, cpu.get_cycle_count());
+	}
+
+	#[test]
+	fn unofficial_nop_skips_operand() {
+		// NOP $1234,X; NOP #$00; NOP zp; NOP
+		let cpu = run(&[0x1C, 0x34, 0x12, 0x80, 0x00, 0x04, 0x00, 0x1A], 4);
+		assert_eq!(0x208, cpu.pc.load());
+	}
+}
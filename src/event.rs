@@ -0,0 +1,25 @@
+use std::fmt;
+
+/**
+ * Something abnormal happened in the emulated machine.
+ * Frontends can show it instead of silently running garbage.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmulationEvent {
+	// The CPU executed a JAM (KIL) opcode and halted until reset
+	CpuJammed { address: u16, opcode: u8 },
+	// The CPU executed an unstable unofficial opcode which isn't
+	// emulated. It's treated as NOP and the execution continues.
+	UnsupportedOpcode { address: u16, opcode: u8 }
+}
+
+impl fmt::Display for EmulationEvent {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			EmulationEvent::CpuJammed { address, opcode } => write!(f,
+				"CPU jammed at ${:04X} (opcode ${:02X})", address, opcode),
+			EmulationEvent::UnsupportedOpcode { address, opcode } => write!(f,
+				"Unsupported opcode ${:02X} at ${:04X}", opcode, address)
+		}
+	}
+}
@@ -1,5 +1,6 @@
 use button;
 use register::Register;
+use state::{StateError, StateReader, StateWriter};
 
 const BUTTON_NUM: u8 = 8;
 
@@ -83,4 +84,23 @@ impl Joypad {
 	pub fn release_button(&mut self, button: Button) {
 		self.buttons[button_index(button)] = false;
 	}
+
+	pub fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
+		writer.write_u8(self.latch);
+		writer.write_u8(self.current_button);
+		for i in 0..BUTTON_NUM as usize {
+			writer.write_bool(self.buttons[i]);
+		}
+	}
+
+	pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		self.latch = reader.read_u8()?;
+		self.current_button = reader.read_u8()?;
+		for i in 0..BUTTON_NUM as usize {
+			self.buttons[i] = reader.read_bool()?;
+		}
+		Ok(())
+	}
 }
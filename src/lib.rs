@@ -3,6 +3,7 @@ pub mod cpu;
 pub mod ppu;
 pub mod apu;
 pub mod rom;
+pub mod cartridge;
 pub mod memory;
 pub mod mapper;
 pub mod button;
@@ -13,13 +14,21 @@ pub mod display;
 pub mod default_input;
 pub mod default_audio;
 pub mod default_display;
+pub mod state;
+pub mod rewind;
+pub mod event;
+pub mod region;
 
 use cpu::Cpu;
-use rom::Rom;
+use rom::{Rom, RomError};
+use region::Region;
 use button::Button;
 use input::Input;
 use display::Display;
 use audio::Audio;
+use state::{StateError, StateReader, StateWriter};
+use rewind::Rewind;
+use event::EmulationEvent;
 
 /// NES emulator.
 ///
@@ -28,7 +37,6 @@ use audio::Audio;
 /// use std::io::Read;
 /// use std::time::Duration;
 /// use nes_rust::Nes;
-/// use nes_rust::rom::Rom;
 /// use nes_rust::default_input::DefaultInput;
 /// use nes_rust::default_audio::DefaultAudio;
 /// use nes_rust::default_display::DefaultDisplay;
@@ -43,8 +51,7 @@ use audio::Audio;
 /// let mut file = File::open(filename)?;
 /// let mut contents = vec![];
 /// file.read_to_end(&mut contents)?;
-/// let rom = Rom::new(contents);
-/// nes.set_rom(rom);
+/// nes.set_rom(contents)?;
 ///
 /// // Go!
 /// nes.bootup();
@@ -59,7 +66,8 @@ use audio::Audio;
 /// }
 /// ```
 pub struct Nes {
-	cpu: Cpu
+	cpu: Cpu,
+	rewind: Option<Rewind>
 }
 
 impl Nes {
@@ -80,21 +88,44 @@ impl Nes {
 				input,
 				display,
 				audio
-			)
+			),
+			rewind: None
 		}
 	}
 
-	/// Sets up NES rom
+	/// Sets up NES rom. Returns an error and keeps the current rom
+	/// if the rom image is broken or its mapper is unsupported.
+	/// The region is detected from NES 2.0 header, NTSC otherwise.
 	///
 	/// # Arguments
-	/// * `rom`
-	pub fn set_rom(&mut self, rom: Rom) {
+	/// * `contents` Rom image binary
+	pub fn set_rom(&mut self, contents: Vec<u8>) -> Result<(), RomError> {
+		let rom = Rom::parse(contents)?;
+		let region = Region::from_header(rom.header());
 		self.cpu.set_rom(rom);
+		self.cpu.set_region(region);
+		self.clear_rewind();
+		Ok(())
+	}
+
+	/// Overrides the console region detected in `set_rom`.
+	/// Call after `set_rom` and before `bootup`.
+	///
+	/// # Arguments
+	/// * `region`
+	pub fn set_region(&mut self, region: Region) {
+		self.cpu.set_region(region);
+	}
+
+	/// Returns the current console region
+	pub fn region(&self) -> Region {
+		self.cpu.get_region()
 	}
 
 	/// Boots up
 	pub fn bootup(&mut self) {
 		self.cpu.bootup();
+		self.clear_rewind();
 	}
 
 	/// Resets
@@ -102,14 +133,71 @@ impl Nes {
 		self.cpu.reset();
 	}
 
-	/// Executes a CPU cycle
+	/// Executes a CPU instruction. PPU and APU run along with every CPU cycle
 	pub fn step(&mut self) {
 		self.cpu.step();
 	}
 
-	/// Executes a PPU (screen refresh) frame
+	/// Runs the emulation for `cycles` master clock cycles.
+	/// The master clock drives CPU, PPU and APU, for example
+	/// 21.477272MHz and CPU runs at 1/12 of it on NTSC.
+	/// CPU stops only at instruction boundaries so the excess
+	/// is carried over to the next call.
+	///
+	/// # Arguments
+	/// * `cycles` Master clock cycles
+	pub fn run_cycles(&mut self, cycles: u64) {
+		self.cpu.handle_inputs();
+		self.cpu.run_cycles(cycles);
+	}
+
+	/// Runs the emulation until PPU enters the scanline `line` next time.
+	/// Scanline 0 is the first visible scanline and the last one is
+	/// the pre-render scanline, 261 on NTSC and 311 on PAL and Dendy.
+	///
+	/// # Arguments
+	/// * `line` Scanline number
+	pub fn run_until_scanline(&mut self, line: u16) {
+		self.cpu.handle_inputs();
+		self.cpu.run_until_scanline(line);
+	}
+
+	/// Returns the master clock cycles since boot up
+	pub fn master_clock(&self) -> u64 {
+		self.cpu.get_master_clock()
+	}
+
+	/// Executes a PPU (screen refresh) frame.
+	/// While [`button::Button::Rewind`](./button/enum.Button.html) is held
+	/// and rewind is enabled, plays backwards instead. Each call goes back
+	/// by one snapshot, `frame_interval` frames of
+	/// [`enable_rewind`](#method.enable_rewind).
 	pub fn step_frame(&mut self) {
+		self.cpu.handle_inputs();
+
+		if self.cpu.is_rewinding() && self.rewind.is_some() {
+			// Restores the previous snapshot and emulates a frame
+			// from it to refresh the screen without recording.
+			// The emulated frame is counted so that the rewind frame
+			// counter keeps up with the machine, then the next rewind
+			// goes back over it and one snapshot interval.
+			let frames = self.rewind.as_ref().unwrap().frame_interval() + 1;
+			self.rewind(frames);
+			self.cpu.step_frame();
+			self.rewind.as_mut().unwrap().countup_frame();
+			return;
+		}
+
 		self.cpu.step_frame();
+
+		let snapshot_needed = match self.rewind {
+			Some(ref mut rewind) => rewind.countup_frame(),
+			None => false
+		};
+		if snapshot_needed {
+			let state = self.save_state();
+			self.rewind.as_mut().unwrap().push(state);
+		}
 	}
 
 	/// Copies RGB pixels of screen to passed pixels.
@@ -150,4 +238,119 @@ impl Nes {
 	pub fn is_power_on(&self) -> bool {
 		self.cpu.is_power_on()
 	}
+
+	/// Returns the latest abnormal event like CPU jam since
+	/// the last reset or boot up, or `None` if nothing happened.
+	/// The CPU stays halted after [`EmulationEvent::CpuJammed`](./event/enum.EmulationEvent.html)
+	/// until [`reset`](#method.reset).
+	pub fn last_fault(&self) -> Option<EmulationEvent> {
+		self.cpu.last_fault()
+	}
+
+	/// Returns battery-backed PRG-RAM contents for persisting
+	/// game saves, or `None` if the cartridge has no battery.
+	pub fn battery_ram(&self) -> Option<&[u8]> {
+		self.cpu.get_rom().battery_ram()
+	}
+
+	/// Restores battery-backed PRG-RAM contents saved with
+	/// [`battery_ram`](#method.battery_ram). Call after `set_rom`
+	/// and before `bootup`. Does nothing if the cartridge has no battery.
+	///
+	/// # Arguments
+	/// * `data`
+	pub fn load_battery_ram(&mut self, data: &[u8]) {
+		self.cpu.get_mut_rom().load_battery_ram(data);
+	}
+
+	/// Sets whether the expansion audio time-multiplexing its channels,
+	/// Namco 163, outputs one channel at a time like the real hardware,
+	/// or mixes them. Multiplexing many channels sounds harsh at the
+	/// usual sample rates. Defaults to `true` and call after `set_rom`.
+	///
+	/// # Arguments
+	/// * `multiplexing`
+	pub fn set_audio_multiplexing(&mut self, multiplexing: bool) {
+		self.cpu.get_mut_rom().set_audio_multiplexing(multiplexing);
+	}
+
+	/// Saves the whole machine state, CPU, RAM, PPU, APU, joypads and
+	/// cartridge PRG-RAM and mapper, into a binary.
+	/// The binary can be restored with [`load_state`](#method.load_state)
+	/// while the same rom is set.
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut writer = StateWriter::new();
+		writer.write_header();
+		self.cpu.save_state(&mut writer);
+		writer.into_vec()
+	}
+
+	/// Enables rewind. A snapshot of the machine is recorded every
+	/// `frame_interval` frames in [`step_frame`](#method.step_frame)
+	/// and the oldest snapshots are discarded when they use more than
+	/// `memory_budget` bytes.
+	///
+	/// # Arguments
+	/// * `frame_interval` Frames between snapshots
+	/// * `memory_budget` Upper limit of memory for snapshots in bytes
+	pub fn enable_rewind(&mut self, frame_interval: u32, memory_budget: usize) {
+		self.rewind = Some(Rewind::new(frame_interval, memory_budget));
+	}
+
+	/// Disables rewind and frees the recorded snapshots
+	pub fn disable_rewind(&mut self) {
+		self.rewind = None;
+	}
+
+	/// Rewinds the machine to the newest snapshot at least `frames` frames
+	/// before, or the oldest snapshot if there aren't enough snapshots.
+	/// The screen is refreshed on the next `step_frame`.
+	/// Returns false if rewind is disabled or no snapshot is recorded yet.
+	///
+	/// # Arguments
+	/// * `frames`
+	pub fn rewind(&mut self, frames: u32) -> bool {
+		let state = match self.rewind {
+			Some(ref mut rewind) => rewind.rewind(frames),
+			None => None
+		};
+		match state {
+			// Snapshots are saved by this build with this rom so never fails
+			Some(state) => self.load_state(&state).is_ok(),
+			None => false
+		}
+	}
+
+	fn clear_rewind(&mut self) {
+		if let Some(ref mut rewind) = self.rewind {
+			rewind.clear();
+		}
+	}
+
+	/// Restores the machine state saved with [`save_state`](#method.save_state).
+	/// If the state can't be restored, for example it is saved by
+	/// an incompatible build or with a different rom, returns an error
+	/// and the machine state is kept unchanged.
+	///
+	/// # Arguments
+	/// * `data` Binary returned by `save_state`
+	pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut reader = StateReader::new(data);
+		reader.read_header()?;
+		let backup = self.save_state();
+		let result = match self.cpu.load_state(&mut reader) {
+			Ok(()) => match reader.is_end() {
+				true => Ok(()),
+				false => Err(StateError::InvalidData)
+			},
+			Err(error) => Err(error)
+		};
+		if result.is_err() {
+			// Restoring the state saved by this build never fails
+			let mut backup_reader = StateReader::new(&backup);
+			backup_reader.read_header().unwrap();
+			self.cpu.load_state(&mut backup_reader).unwrap();
+		}
+		result
+	}
 }
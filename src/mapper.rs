@@ -1,41 +1,197 @@
 pub struct MapperFactory;
-use rom::Mirrorings;
-use rom::RomHeader;
+use apu::{ApuPulse, ApuPulseChannel, PULSE_FULL_VOLUME_LEVEL};
+use cartridge::Cartridge;
+use memory::Memory;
+use rom::{Nametable, NametableMapping, HORIZONTAL_MIRRORING, VERTICAL_MIRRORING,
+	SINGLE_SCREEN_A, SINGLE_SCREEN_B};
+use rom::{RomError, RomHeader};
 use register::Register;
+use state::{StateError, StateReader, StateWriter};
 
 impl MapperFactory {
-	pub fn create(header: &RomHeader) -> Box<dyn Mapper> {
-		match header.mapper_num() {
-			0 => Box::new(NRomMapper::new(header)),
-			1 => Box::new(MMC1Mapper::new(header)),
-			2 => Box::new(UNRomMapper::new(header)),
-			3 => Box::new(CNRomMapper::new()),
-			4 => Box::new(MMC3Mapper::new(header)),
-			_ => panic!("Unsupported mapper {}", header.mapper_num())
-		}
+	pub fn create(header: &RomHeader) -> Result<Box<dyn Mapper>, RomError> {
+		// Submapper 0 is the default variant, also used for iNES 1.0
+		Ok(match (header.mapper_num(), header.submapper_num()) {
+			(0, _) => Box::new(NRomMapper::new(header)),
+			// SEROM, SHROM, SH1ROM: 32KB PRG-ROM without PRG bank switching
+			(1, 5) => Box::new(MMC1Mapper::new_with_fixed_program_bank(header)),
+			(1, _) => Box::new(MMC1Mapper::new(header)),
+			(2, _) => Box::new(UNRomMapper::new(header)),
+			(3, _) => Box::new(CNRomMapper::new(header)),
+			// MMC3A by NEC has the old IRQ behavior
+			(4, 4) => Box::new(MMC3Mapper::new_with_old_irq(header)),
+			(4, _) => Box::new(MMC3Mapper::new(header)),
+			(5, _) => Box::new(MMC5Mapper::new()),
+			(7, _) => Box::new(AxRomMapper::new(header)),
+			(9, _) => Box::new(MMC2Mapper::new(header)),
+			(10, _) => Box::new(MMC2Mapper::new_mmc4(header)),
+			(11, _) => Box::new(ColorDreamsMapper::new()),
+			(19, _) => Box::new(Namco163Mapper::new(header)),
+			(21, _) | (22, _) | (23, _) | (25, _) => Box::new(VRC4Mapper::new(header)),
+			(24, _) => Box::new(VRC6Mapper::new(header)),
+			(26, _) => Box::new(VRC6Mapper::new_vrc6b(header)),
+			(30, _) => Box::new(UNRom512Mapper::new(header)),
+			// Mapper 34 is two different boards. Only NINA-001 has CHR-ROM.
+			(34, 1) => Box::new(Nina001Mapper::new()),
+			(34, 2) => Box::new(BNRomMapper::new()),
+			(34, _) => match header.chr_rom_size() > 0 {
+				true => Box::new(Nina001Mapper::new()),
+				false => Box::new(BNRomMapper::new())
+			},
+			(66, _) => Box::new(GxRomMapper::new()),
+			(69, _) => Box::new(FME7Mapper::new(header)),
+			(119, _) => Box::new(MMC3Mapper::new_with_chr_ram_bank(header)),
+			_ => return Err(RomError::UnsupportedMapper(header.mapper_num()))
+		})
 	}
 }
 
+/**
+ * Mapper is the cartridge board logic between the cartridge memories
+ * and CPU/PPU buses. The methods with the default implementation are
+ * hooks for the boards having more than simple bank switching.
+ */
 pub trait Mapper {
 	// Maps 0x8000 - 0xFFFF to the program rom address
 	fn map(&self, address: u32) -> u32;
 
-	// Maps 0x0000 - 0x1FFF to the character rom address
+	// Maps 0x0000 - 0x1FFF to the CHR-ROM address,
+	// or CHR-RAM address if the cartridge has no CHR-ROM
 	fn map_for_chr_rom(&self, address: u32) -> u32;
 
 	// Writes control register inside in general
 	fn store(&mut self, address: u32, value: u8);
 
-	fn has_mirroring_type(&self) -> bool;
+	/**
+	 * CPU access to 0x4020 - 0xFFFF. By default
+	 * 0x4020 - 0x5FFF: Open bus (0 for now)
+	 * 0x6000 - 0x7FFF: PRG-RAM
+	 * 0x8000 - 0xFFFF: PRG-ROM with map(), writes go to store()
+	 * Override them for the registers in 0x4020 - 0x7FFF, banked PRG-RAM
+	 * or reads with side effects.
+	 */
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x6000..=0x7FFF => cartridge.load_prg_ram(address - 0x6000),
+			0x8000..=0xFFFF => cartridge.load_prg_rom(self.map(address)),
+			_ => 0
+		}
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x6000..=0x7FFF => cartridge.store_prg_ram(address - 0x6000, value),
+			0x8000..=0xFFFF => {
+				// PRG-ROM also drives the data bus and the lower bits win
+				let value = match self.has_bus_conflict() {
+					true => value & cartridge.load_prg_rom(self.map(address)),
+					false => value
+				};
+				self.store(address, value);
+			},
+			_ => {}
+		};
+	}
+
+	// Whether writes to the registers in the PRG-ROM area conflict with
+	// PRG-ROM, for the discrete logic boards without the circuit preventing it
+	fn has_bus_conflict(&self) -> bool {
+		false
+	}
+
+	// PPU access to 0x0000 - 0x1FFF, including the pattern fetches for
+	// rendering. Override them if the board has both CHR-ROM and CHR-RAM
+	// or switches banks by the fetched addresses.
+	fn load_chr(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		cartridge.load_chr(self.map_for_chr_rom(address))
+	}
+
+	fn store_chr(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		cartridge.store_chr(self.map_for_chr_rom(address), value);
+	}
+
+	// Nametable mapping of the four quadrants the mapper controls.
+	// None follows the rom header.
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		None
+	}
+
+	/**
+	 * PPU access to the nametables mapped to other than CIRAM.
+	 * address is the offset in the 1KB nametable. Override them
+	 * for ExRAM and fill mode.
+	 */
+	fn load_nametable(&self, cartridge: &Cartridge, nametable: Nametable, address: u32) -> u8 {
+		match nametable {
+			Nametable::CartridgeRam(page) => cartridge.load_nametable_ram(page as u32 * 0x400 + address),
+			Nametable::ChrRom(bank) => cartridge.load_chr_rom(bank as u32 * 0x400 + address),
+			_ => 0
+		}
+	}
+
+	fn store_nametable(&mut self, cartridge: &mut Cartridge, nametable: Nametable, address: u32, value: u8) {
+		if let Nametable::CartridgeRam(page) = nametable {
+			cartridge.store_nametable_ram(page as u32 * 0x400 + address, value);
+		}
+	}
+
+	// CIRAM page mapped to the pattern table address, for the boards
+	// mapping the console's VRAM into 0x0000 - 0x1FFF like Namco 163.
+	// None for the cartridge's CHR memory.
+	fn pattern_ciram_page(&self, _address: u16) -> Option<u8> {
+		None
+	}
+
+	// Called with every address PPU puts on its address bus, fetches
+	// for rendering and 0x2006/0x2007 access. For the boards watching
+	// the bus like A12 and latches.
+	fn notify_ppu_address(&mut self, _address: u16) {
+	}
+
+	// Called with CPU writes to PPU registers 0x2000 - 0x2007. For the
+	// boards snooping them on CPU bus like MMC5 watching PPUCTRL.
+	fn notify_ppu_register_store(&mut self, _address: u16, _value: u8) {
+	}
+
+	// Called every CPU cycle, for cycle based IRQ counters and so on
+	fn step(&mut self) {
+	}
+
+	// IRQ line shared with APU. Kept asserted until the mapper acknowledges.
+	fn irq_line(&self) -> bool {
+		false
+	}
+
+	// Expansion audio output which is added to the APU output.
+	// 0.0 is silent and 1.0 is as loud as the full APU output.
+	fn audio_output(&self) -> f32 {
+		0.0
+	}
+
+	// For the expansion audio time-multiplexing the channels like
+	// Namco 163. false mixes the channels instead, which is cleaner
+	// than the real hardware at the usual audio sample rates.
+	fn set_audio_multiplexing(&mut self, _multiplexing: bool) {
+	}
+
+	// RAM inside the mapper chip kept by the battery like Namco 163's
+	// internal RAM. Persisted if the cartridge has no battery-backed PRG-RAM.
+	fn battery_backed_chip_ram(&self) -> Option<&Memory> {
+		None
+	}
+
+	fn battery_backed_chip_ram_mut(&mut self) -> Option<&mut Memory> {
+		None
+	}
 
-	fn mirroring_type(&self) -> Mirrorings;
+	// Saves/Loads the internal state like bank registers for save state
+	fn save_state(&self, writer: &mut StateWriter);
 
-	// @TODO: MMC3Mapper specific. Should this method be here?
-	fn drive_irq_counter(&mut self) -> bool;
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
 }
 
 pub struct NRomMapper {
-	program_bank_num: u8
+	program_bank_num: u16
 }
 
 impl NRomMapper {
@@ -75,55 +231,131 @@ impl Mapper for NRomMapper {
 		// throw exception?
 	}
 
-	fn has_mirroring_type(&self) -> bool {
-		false
-	}
-
-	fn mirroring_type(&self) -> Mirrorings {
-		Mirrorings::SingleScreen // dummy
+	fn save_state(&self, _writer: &mut StateWriter) {
+		// No internal state
 	}
 
-	fn drive_irq_counter(&mut self) -> bool {
-		false
+	fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), StateError> {
+		Ok(())
 	}
 }
 
+/**
+ * MMC1 (SxROM). Registers are written serially through bit 0 of five writes.
+ * Boards with large memories reuse the upper bits of the CHR bank registers,
+ * selected by the size of the memories.
+ *   SUROM, SXROM: bit 4 selects 256KB PRG-ROM half of 512KB PRG-ROM
+ *   SOROM: bit 3 selects 8KB PRG-RAM bank of 16KB PRG-RAM
+ *   SXROM: bits 2-3 select 8KB PRG-RAM bank of 32KB PRG-RAM
+ * The CHR bank register driving the CHR address lines at the moment,
+ * 0 or 1 by PPU A12 in 4KB CHR mode, also drives these lines.
+ * The board is detected by the deprecated NES 2.0 submappers
+ * 1 (SUROM), 2 (SOROM) and 4 (SXROM), otherwise by the memory sizes.
+ */
 pub struct MMC1Mapper {
-	program_bank_num: u8,
+	program_bank_num: u16,
+	fixed_program_bank: bool,
+	// 512KB PRG-ROM of SUROM and SXROM
+	has_outer_program_bank: bool,
+	prg_ram_bank_num: u32,
+	// MMC1A, submapper 3, has no PRG-RAM disable bit
+	has_prg_ram_disable: bool,
 	control_register: Register<u8>,
 	chr_bank0_register: Register<u8>,
 	chr_bank1_register: Register<u8>,
 	prg_bank_register: Register<u8>,
 	latch: Register<u8>,
-	register_write_count: u32
+	register_write_count: u32,
+	// MMC1 ignores the write in the next cycle of a write,
+	// the second write of read-modify-write instructions.
+	cycles_since_write: u8,
+	a12: bool
 }
 
 impl MMC1Mapper {
 	fn new(header: &RomHeader) -> Self {
 		let mut control_register = Register::<u8>::new();
 		control_register.store(0x0C);
+		let prg_ram_size = header.prg_ram_size() + header.prg_nvram_size();
 		MMC1Mapper {
 			program_bank_num: header.prg_rom_bank_num(),
+			fixed_program_bank: false,
+			has_outer_program_bank: match header.submapper_num() {
+				1 | 4 => true,
+				_ => header.prg_rom_bank_num() > 16
+			},
+			prg_ram_bank_num: match header.submapper_num() {
+				2 => 2,
+				4 => 4,
+				_ => (prg_ram_size as u32 / 0x2000).max(1)
+			},
+			has_prg_ram_disable: header.submapper_num() != 3,
 			control_register: control_register,
 			chr_bank0_register: Register::<u8>::new(),
 			chr_bank1_register: Register::<u8>::new(),
 			prg_bank_register: Register::<u8>::new(),
 			latch: Register::<u8>::new(),
-			register_write_count: 0
+			register_write_count: 0,
+			cycles_since_write: 2,
+			a12: false
+		}
+	}
+}
+
+impl MMC1Mapper {
+	fn new_with_fixed_program_bank(header: &RomHeader) -> Self {
+		let mut mapper = MMC1Mapper::new(header);
+		mapper.fixed_program_bank = true;
+		mapper
+	}
+
+	// The CHR bank register currently driving the CHR address lines
+	fn current_chr_bank_register(&self) -> u8 {
+		match self.control_register.load_bit(4) == 1 && self.a12 {
+			true => self.chr_bank1_register.load(),
+			false => self.chr_bank0_register.load()
+		}
+	}
+
+	fn outer_program_bank(&self) -> u32 {
+		match self.has_outer_program_bank {
+			true => self.current_chr_bank_register() as u32 & 0x10,
+			false => 0
+		}
+	}
+
+	fn prg_ram_bank(&self) -> u32 {
+		let value = self.current_chr_bank_register() as u32;
+		match self.prg_ram_bank_num {
+			// SXROM
+			4 => (value >> 2) & 3,
+			// SOROM
+			2 => (value >> 3) & 1,
+			_ => 0
 		}
 	}
+
+	// Bit 4 of PRG bank register disables PRG-RAM on MMC1B and later
+	fn prg_ram_enabled(&self) -> bool {
+		!(self.has_prg_ram_disable && self.prg_bank_register.is_bit_set(4))
+	}
 }
 
 impl Mapper for MMC1Mapper {
 	fn map(&self, address: u32) -> u32 {
+		if self.fixed_program_bank {
+			// 16KB PRG-ROM is mirrored like NROM
+			return (address - 0x8000) % (self.program_bank_num as u32 * 0x4000);
+		}
+
 		let bank: u32;
 		let mut offset = address & 0x3FFF;
 		let bank_num = self.prg_bank_register.load() as u32 & 0x0F;
+		let outer_bank = self.outer_program_bank();
 
 		match self.control_register.load_bits(2, 2) {
 			0 | 1 => {
 				// switch 32KB at 0x8000, ignoring low bit of bank number
-				// TODO: Fix me
 				offset = offset | (address & 0x4000);
 				bank = bank_num & 0x0E;
 			},
@@ -137,12 +369,12 @@ impl Mapper for MMC1Mapper {
 			_ /*3*/ => {
 				// fix last bank at 0xC000 and switch 16KB bank at 0x8000
 				bank = match address >= 0xC000 {
-					true => self.program_bank_num as u32 - 1,
+					true => (self.program_bank_num as u32).min(16) - 1,
 					false => bank_num
 				};
 			}
 		};
-		bank * 0x4000 + offset
+		(outer_bank | bank) * 0x4000 + offset
 	}
 
 	fn map_for_chr_rom(&self, address: u32) -> u32 {
@@ -163,6 +395,12 @@ impl Mapper for MMC1Mapper {
 	}
 
 	fn store(&mut self, address: u32, value: u8) {
+		let consecutive = self.cycles_since_write < 2;
+		self.cycles_since_write = 0;
+		if consecutive {
+			return;
+		}
+
 		if (value & 0x80) != 0 {
 			self.register_write_count = 0;
 			self.latch.clear();
@@ -187,25 +425,83 @@ impl Mapper for MMC1Mapper {
 		}
 	}
 
-	fn has_mirroring_type(&self) -> bool {
-		true
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x6000..=0x7FFF => match self.prg_ram_enabled() {
+				true => cartridge.load_prg_ram(self.prg_ram_bank() * 0x2000 + (address - 0x6000)),
+				false => 0
+			},
+			0x8000..=0xFFFF => cartridge.load_prg_rom(self.map(address)),
+			_ => 0
+		}
 	}
 
-	fn mirroring_type(&self) -> Mirrorings {
-		match self.control_register.load_bits(0, 2) {
-			0 | 1 => Mirrorings::SingleScreen,
-			2 => Mirrorings::Vertical,
-			_ /*3*/ => Mirrorings::Horizontal
-		}
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x6000..=0x7FFF => {
+				if self.prg_ram_enabled() {
+					cartridge.store_prg_ram(self.prg_ram_bank() * 0x2000 + (address - 0x6000), value);
+				}
+			},
+			0x8000..=0xFFFF => self.store(address, value),
+			_ => {}
+		};
 	}
 
-	fn drive_irq_counter(&mut self) -> bool {
-		false
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		Some(match self.control_register.load_bits(0, 2) {
+			0 => SINGLE_SCREEN_A,
+			1 => SINGLE_SCREEN_B,
+			2 => VERTICAL_MIRRORING,
+			_ /*3*/ => HORIZONTAL_MIRRORING
+		})
+	}
+
+	fn notify_ppu_address(&mut self, address: u16) {
+		self.a12 = (address & 0x1000) != 0;
+	}
+
+	fn step(&mut self) {
+		self.cycles_since_write = self.cycles_since_write.saturating_add(1);
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.control_register);
+		writer.write_register_u8(&self.chr_bank0_register);
+		writer.write_register_u8(&self.chr_bank1_register);
+		writer.write_register_u8(&self.prg_bank_register);
+		writer.write_register_u8(&self.latch);
+		writer.write_u32(self.register_write_count);
+		writer.write_u8(self.cycles_since_write);
+		writer.write_bool(self.a12);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.control_register)?;
+		reader.read_register_u8(&mut self.chr_bank0_register)?;
+		reader.read_register_u8(&mut self.chr_bank1_register)?;
+		reader.read_register_u8(&mut self.prg_bank_register)?;
+		reader.read_register_u8(&mut self.latch)?;
+		self.register_write_count = reader.read_u32()?;
+		self.cycles_since_write = reader.read_u8()?;
+		self.a12 = reader.read_bool()?;
+		Ok(())
 	}
 }
 
+/**
+ * NES 2.0 submapper of the discrete logic boards, UxROM (2),
+ * CNROM (3) and AxROM (7), telling whether the board has bus conflicts.
+ * 1 is without bus conflicts and 2 is with. 0, unspecified,
+ * runs without them.
+ */
+fn has_bus_conflict_submapper(header: &RomHeader) -> bool {
+	header.submapper_num() == 2
+}
+
 struct UNRomMapper {
-	program_bank_num: u8,
+	program_bank_num: u16,
+	bus_conflict: bool,
 	register: Register<u8>
 }
 
@@ -213,6 +509,7 @@ impl UNRomMapper {
 	fn new(header: &RomHeader) -> Self {
 		UNRomMapper {
 			program_bank_num: header.prg_rom_bank_num(),
+			bus_conflict: has_bus_conflict_submapper(header),
 			register: Register::<u8>::new()
 		}
 	}
@@ -221,9 +518,9 @@ impl UNRomMapper {
 impl Mapper for UNRomMapper {
 	fn map(&self, address: u32) -> u32 {
 		let bank = match address < 0xC000 {
-			true => self.register.load(),
-			false => self.program_bank_num - 1
-		} as u32;
+			true => self.register.load() as u32,
+			false => self.program_bank_num as u32 - 1
+		};
 		let offset = address & 0x3FFF;
 		0x4000 * bank + offset
 	}
@@ -236,26 +533,91 @@ impl Mapper for UNRomMapper {
 		self.register.store(value & 0xF);
 	}
 
-	fn has_mirroring_type(&self) -> bool {
-		false
+	fn has_bus_conflict(&self) -> bool {
+		self.bus_conflict
 	}
 
-	fn mirroring_type(&self) -> Mirrorings {
-		Mirrorings::SingleScreen // dummy
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
 	}
 
-	fn drive_irq_counter(&mut self) -> bool {
-		false
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
+	}
+}
+
+/**
+ * UNROM 512, a homebrew board with 32KB CHR-RAM.
+ * The register at 0x8000 - 0xFFFF is
+ *   bit 0-4: 16KB PRG-ROM bank at 0x8000, the last bank is fixed at 0xC000
+ *   bit 5-6: 8KB CHR-RAM bank
+ *   bit 7: One screen mirroring select
+ */
+struct UNRom512Mapper {
+	program_bank_num: u16,
+	single_screen: bool,
+	register: Register<u8>
+}
+
+impl UNRom512Mapper {
+	fn new(header: &RomHeader) -> Self {
+		UNRom512Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			// Four screen bit without vertical mirroring bit
+			single_screen: header.four_screen_mirroring() && header.mirroring_bit() == 0,
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for UNRom512Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address < 0xC000 {
+			true => self.register.load_bits(0, 5) as u32,
+			false => self.program_bank_num as u32 - 1
+		};
+		0x4000 * bank + (address & 0x3FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		0x2000 * self.register.load_bits(5, 2) as u32 + (address & 0x1FFF)
+	}
+
+	// @TODO: Flash memory writes of the self-flashable boards
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		match self.single_screen {
+			true => Some(match self.register.is_bit_set(7) {
+				true => SINGLE_SCREEN_B,
+				false => SINGLE_SCREEN_A
+			}),
+			false => None
+		}
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
 	}
 }
 
 struct CNRomMapper {
+	bus_conflict: bool,
 	register: Register<u8>
 }
 
 impl CNRomMapper {
-	fn new() -> Self {
+	fn new(header: &RomHeader) -> Self {
 		CNRomMapper {
+			bus_conflict: has_bus_conflict_submapper(header),
 			register: Register::<u8>::new()
 		}
 	}
@@ -274,229 +636,4134 @@ impl Mapper for CNRomMapper {
 		self.register.store(value & 0xF);
 	}
 
-	fn has_mirroring_type(&self) -> bool {
-		false
+	fn has_bus_conflict(&self) -> bool {
+		self.bus_conflict
 	}
 
-	fn mirroring_type(&self) -> Mirrorings {
-		Mirrorings::SingleScreen // dummy
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
 	}
 
-	fn drive_irq_counter(&mut self) -> bool {
-		false
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
 	}
 }
 
-struct MMC3Mapper {
-	program_bank_num: u8,
-	character_bank_num: u8,
-	register0: Register<u8>,
-	register1: Register<u8>,
-	register2: Register<u8>,
-	register3: Register<u8>,
-	register4: Register<u8>,
-	register5: Register<u8>,
-	register6: Register<u8>,
-	register7: Register<u8>,
-	program_register0: Register<u8>,
-	program_register1: Register<u8>,
-	character_register0: Register<u8>,
-	character_register1: Register<u8>,
-	character_register2: Register<u8>,
-	character_register3: Register<u8>,
-	character_register4: Register<u8>,
-	character_register5: Register<u8>,
-	irq_counter: u8,
-	irq_counter_reload: bool,
-	irq_enabled: bool
+/**
+ * AxROM (AMROM, ANROM, AOROM). The register at 0x8000 - 0xFFFF is
+ *   bit 0-3: 32KB PRG-ROM bank
+ *   bit 4: One screen mirroring select
+ * CHR is 8KB CHR-RAM. AMROM and AOROM have bus conflicts but ANROM
+ * doesn't. iNES can't tell them apart, so bus conflicts are emulated
+ * only with NES 2.0 submapper 2. Games for the boards with conflicts
+ * avoid them and run fine without, while emulating conflicts breaks
+ * ANROM games.
+ */
+struct AxRomMapper {
+	bus_conflict: bool,
+	register: Register<u8>
 }
 
-impl MMC3Mapper {
+impl AxRomMapper {
 	fn new(header: &RomHeader) -> Self {
-		MMC3Mapper {
-			program_bank_num: header.prg_rom_bank_num(),
-			character_bank_num: header.chr_rom_bank_num(),
-			register0: Register::<u8>::new(),
-			register1: Register::<u8>::new(),
-			register2: Register::<u8>::new(),
-			register3: Register::<u8>::new(),
-			register4: Register::<u8>::new(),
-			register5: Register::<u8>::new(),
-			register6: Register::<u8>::new(),
-			register7: Register::<u8>::new(),
-			program_register0: Register::<u8>::new(),
-			program_register1: Register::<u8>::new(),
-			character_register0: Register::<u8>::new(),
-			character_register1: Register::<u8>::new(),
-			character_register2: Register::<u8>::new(),
-			character_register3: Register::<u8>::new(),
-			character_register4: Register::<u8>::new(),
-			character_register5: Register::<u8>::new(),
-			irq_counter: 0,
-			irq_counter_reload: false,
-			irq_enabled: true
+		AxRomMapper {
+			bus_conflict: has_bus_conflict_submapper(header),
+			register: Register::<u8>::new()
 		}
 	}
 }
 
-impl Mapper for MMC3Mapper {
+impl Mapper for AxRomMapper {
 	fn map(&self, address: u32) -> u32 {
-		let bank = match address {
-			0x8000..=0x9FFF => match self.register0.is_bit_set(6) {
-				true => self.program_bank_num * 2 - 2,
-				false => self.program_register0.load()
-			},
-			0xA000..=0xBFFF => self.program_register1.load(),
-			0xC000..=0xDFFF => match self.register0.is_bit_set(6) {
-				true => self.program_register0.load(),
-				false => self.program_bank_num * 2 - 2
-			},
-			_ => self.program_bank_num * 2 - 1
-		};
-		// I couldn't in the spec but it seems that
-		// we need to wrap 2k bank with 4k program_bank_num
-		((bank as u32) % ((self.program_bank_num as u32) * 2)) * 0x2000 + (address & 0x1FFF)
+		0x8000 * self.register.load_bits(0, 4) as u32 + (address & 0x7FFF)
 	}
 
 	fn map_for_chr_rom(&self, address: u32) -> u32 {
-		let bank = match self.register0.is_bit_set(7) {
-			true => match address & 0x1FFF {
-				0x0000..=0x03FF => self.character_register2.load(),
-				0x0400..=0x07FF => self.character_register3.load(),
-				0x0800..=0x0BFF => self.character_register4.load(),
-				0x0C00..=0x0FFF => self.character_register5.load(),
-				0x1000..=0x13FF => self.character_register0.load() & 0xFE,
-				0x1400..=0x17FF => self.character_register0.load() | 1,
-				0x1800..=0x1BFF => self.character_register1.load() & 0xFE,
-				_ => self.character_register1.load() | 1
-			},
-			false => match address & 0x1FFF {
-				0x0000..=0x03FF => self.character_register0.load() & 0xFE,
-				0x0400..=0x07FF => self.character_register0.load() | 1,
-				0x0800..=0x0BFF => self.character_register1.load() & 0xFE,
-				0x0C00..=0x0FFF => self.character_register1.load() | 1,
-				0x1000..=0x13FF => self.character_register2.load(),
-				0x1400..=0x17FF => self.character_register3.load(),
-				0x1800..=0x1BFF => self.character_register4.load(),
-				_ => self.character_register5.load()
-			}
-		};
-		// I couldn't in the spec but it seems that
-		// we need to wrap 0.4k bank with 4k character_bank_num
-		((bank as u32) % ((self.character_bank_num as u32) * 8)) * 0x400 + (address & 0x3FF)
+		address
 	}
 
-	fn store(&mut self, address: u32, value: u8) {
-		match address {
-			0x8000..=0x9FFF => match (address & 1) == 0 {
-				true => self.register0.store(value),
-				false => {
-					self.register1.store(value);
-					match self.register0.load_bits(0, 3) {
-						0 => self.character_register0.store(value & 0xFE),
-						1 => self.character_register1.store(value & 0xFE),
-						2 => self.character_register2.store(value),
-						3 => self.character_register3.store(value),
-						4 => self.character_register4.store(value),
-						5 => self.character_register5.store(value),
-						6 => self.program_register0.store(value & 0x3F),
-						_ => self.program_register1.store(value & 0x3F)
-					};
-				}
-			},
-			0xA000..=0xBFFF => match (address & 1) == 0 {
-				true => self.register2.store(value),
-				false => self.register3.store(value)
-			},
-			0xC000..=0xDFFF => {
-				match (address & 1) == 0 {
-					true => self.register4.store(value),
-					false => self.register5.store(value)
-				};
-				self.irq_counter_reload = true;
-			},
-			_ => match (address & 1) == 0 {
-				true => {
-					self.register6.store(value);
-					self.irq_enabled = false;
-				},
-				false => {
-					self.register7.store(value);
-					self.irq_enabled = true;
-				}
-			}
-		};
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
 	}
 
-	fn has_mirroring_type(&self) -> bool {
-		true
+	fn has_bus_conflict(&self) -> bool {
+		self.bus_conflict
 	}
 
-	fn mirroring_type(&self) -> Mirrorings {
-		match self.register2.is_bit_set(0) {
-			true => Mirrorings::Horizontal,
-			false => Mirrorings::Vertical
-		}
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		Some(match self.register.is_bit_set(4) {
+			true => SINGLE_SCREEN_B,
+			false => SINGLE_SCREEN_A
+		})
 	}
 
-	fn drive_irq_counter(&mut self) -> bool {
-		match self.irq_counter_reload {
-			true => {
-				self.irq_counter = self.register4.load();
-				self.irq_counter_reload = false;
-				false
-			},
-			false => match self.irq_enabled {
-				true => match self.irq_counter > 0 {
-					true => {
-						self.irq_counter -= 1;
-						match self.irq_counter == 0 {
-							true => {
-								self.irq_counter_reload = true;
-								true
-							}
-							false => false
-						}
-					},
-					false => false
-				},
-				false => false
-			}
-		}
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
 	}
 }
 
-#[cfg(test)]
-mod tests_nrom_mapper {
-	use super::*;
+/**
+ * BNROM, mapper 34 submapper 2. The register at 0x8000 - 0xFFFF selects
+ * 32KB PRG-ROM bank. CHR is 8KB CHR-RAM. Has bus conflicts.
+ */
+struct BNRomMapper {
+	register: Register<u8>
+}
+
+impl BNRomMapper {
+	fn new() -> Self {
+		BNRomMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for BNRomMapper {
+	fn map(&self, address: u32) -> u32 {
+		0x8000 * self.register.load() as u32 + (address & 0x7FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_bus_conflict(&self) -> bool {
+		true
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
+	}
+}
+
+/**
+ * NINA-001, mapper 34 submapper 1. The registers overlap 8KB PRG-RAM
+ * and writes go to both.
+ *   0x7FFD: 32KB PRG-ROM bank
+ *   0x7FFE: 4KB CHR-ROM bank at 0x0000
+ *   0x7FFF: 4KB CHR-ROM bank at 0x1000
+ */
+struct Nina001Mapper {
+	program_register: Register<u8>,
+	character_register0: Register<u8>,
+	character_register1: Register<u8>
+}
+
+impl Nina001Mapper {
+	fn new() -> Self {
+		Nina001Mapper {
+			program_register: Register::<u8>::new(),
+			character_register0: Register::<u8>::new(),
+			character_register1: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for Nina001Mapper {
+	fn map(&self, address: u32) -> u32 {
+		0x8000 * self.program_register.load_bit(0) as u32 + (address & 0x7FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let bank = match address < 0x1000 {
+			true => self.character_register0.load(),
+			false => self.character_register1.load()
+		} as u32 & 0xF;
+		0x1000 * bank + (address & 0x0FFF)
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0x7FFD => self.program_register.store(value),
+			0x7FFE => self.character_register0.store(value),
+			0x7FFF => self.character_register1.store(value),
+			_ => {}
+		};
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		if let 0x6000..=0x7FFF = address {
+			cartridge.store_prg_ram(address - 0x6000, value);
+			self.store(address, value);
+		}
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.program_register);
+		writer.write_register_u8(&self.character_register0);
+		writer.write_register_u8(&self.character_register1);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.program_register)?;
+		reader.read_register_u8(&mut self.character_register0)?;
+		reader.read_register_u8(&mut self.character_register1)?;
+		Ok(())
+	}
+}
+
+/**
+ * GxROM and MHROM, mapper 66. The register at 0x8000 - 0xFFFF is
+ *   bit 0-1: 8KB CHR-ROM bank
+ *   bit 4-5: 32KB PRG-ROM bank
+ * Has bus conflicts.
+ */
+struct GxRomMapper {
+	register: Register<u8>
+}
+
+impl GxRomMapper {
+	fn new() -> Self {
+		GxRomMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for GxRomMapper {
+	fn map(&self, address: u32) -> u32 {
+		0x8000 * self.register.load_bits(4, 2) as u32 + (address & 0x7FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		0x2000 * self.register.load_bits(0, 2) as u32 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_bus_conflict(&self) -> bool {
+		true
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
+	}
+}
+
+/**
+ * Color Dreams, mapper 11. The register at 0x8000 - 0xFFFF is
+ *   bit 0-1: 32KB PRG-ROM bank
+ *   bit 4-7: 8KB CHR-ROM bank
+ * Has bus conflicts.
+ */
+struct ColorDreamsMapper {
+	register: Register<u8>
+}
+
+impl ColorDreamsMapper {
+	fn new() -> Self {
+		ColorDreamsMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for ColorDreamsMapper {
+	fn map(&self, address: u32) -> u32 {
+		0x8000 * self.register.load_bits(0, 2) as u32 + (address & 0x7FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		0x2000 * self.register.load_bits(4, 4) as u32 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_bus_conflict(&self) -> bool {
+		true
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register)?;
+		Ok(())
+	}
+}
+
+/**
+ * MMC2 (PxROM) and MMC4 (FxROM). Two latches switch 4KB CHR-ROM banks
+ * when PPU fetches tile 0xFD or 0xFE from the specific addresses,
+ * after the fetch.
+ *   0x0FD8 (0x0FD8 - 0x0FDF on MMC4): latch 0 = 0xFD
+ *   0x0FE8 (0x0FE8 - 0x0FEF on MMC4): latch 0 = 0xFE
+ *   0x1FD8 - 0x1FDF: latch 1 = 0xFD
+ *   0x1FE8 - 0x1FEF: latch 1 = 0xFE
+ * The registers are
+ *   0xA000: PRG-ROM bank at 0x8000, 8KB on MMC2 and 16KB on MMC4
+ *   0xB000/0xC000: 4KB CHR-ROM bank at 0x0000 for latch 0 0xFD/0xFE
+ *   0xD000/0xE000: 4KB CHR-ROM bank at 0x1000 for latch 1 0xFD/0xFE
+ *   0xF000: Mirroring, 0: vertical, 1: horizontal
+ * The rest of PRG-ROM is fixed to the last banks.
+ */
+struct MMC2Mapper {
+	program_bank_num: u16,
+	// MMC4 has 16KB PRG-ROM bank and the wider latch 0 address ranges
+	mmc4: bool,
+	program_register: Register<u8>,
+	character_registers: [Register<u8>; 4],
+	mirroring_register: Register<u8>,
+	latch0: u8,
+	latch1: u8
+}
+
+impl MMC2Mapper {
+	fn new(header: &RomHeader) -> Self {
+		MMC2Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			mmc4: false,
+			program_register: Register::<u8>::new(),
+			character_registers: [
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new()
+			],
+			mirroring_register: Register::<u8>::new(),
+			latch0: 0xFE,
+			latch1: 0xFE
+		}
+	}
+
+	fn new_mmc4(header: &RomHeader) -> Self {
+		let mut mapper = MMC2Mapper::new(header);
+		mapper.mmc4 = true;
+		mapper
+	}
+
+	fn update_latches(&mut self, address: u32) {
+		match address {
+			0x0FD8 => self.latch0 = 0xFD,
+			0x0FE8 => self.latch0 = 0xFE,
+			0x0FD9..=0x0FDF if self.mmc4 => self.latch0 = 0xFD,
+			0x0FE9..=0x0FEF if self.mmc4 => self.latch0 = 0xFE,
+			0x1FD8..=0x1FDF => self.latch1 = 0xFD,
+			0x1FE8..=0x1FEF => self.latch1 = 0xFE,
+			_ => {}
+		};
+	}
+}
+
+impl Mapper for MMC2Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let last_bank = self.program_bank_num as u32 * 0x4000;
+		match self.mmc4 {
+			true => match address < 0xC000 {
+				true => self.program_register.load_bits(0, 4) as u32 * 0x4000 + (address & 0x3FFF),
+				false => last_bank - 0x4000 + (address & 0x3FFF)
+			},
+			false => match address < 0xA000 {
+				true => self.program_register.load_bits(0, 4) as u32 * 0x2000 + (address & 0x1FFF),
+				// The last three 8KB banks
+				false => last_bank - 0x8000 + (address - 0x8000)
+			}
+		}
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let index = match address < 0x1000 {
+			true => match self.latch0 {
+				0xFD => 0,
+				_ => 1
+			},
+			false => match self.latch1 {
+				0xFD => 2,
+				_ => 3
+			}
+		};
+		self.character_registers[index].load_bits(0, 5) as u32 * 0x1000 + (address & 0x0FFF)
+	}
+
+	fn load_chr(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		let value = cartridge.load_chr(self.map_for_chr_rom(address));
+		self.update_latches(address);
+		value
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address & 0xF000 {
+			0xA000 => self.program_register.store(value),
+			0xB000 => self.character_registers[0].store(value),
+			0xC000 => self.character_registers[1].store(value),
+			0xD000 => self.character_registers[2].store(value),
+			0xE000 => self.character_registers[3].store(value),
+			0xF000 => self.mirroring_register.store(value),
+			_ => {}
+		};
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		Some(match self.mirroring_register.is_bit_set(0) {
+			true => HORIZONTAL_MIRRORING,
+			false => VERTICAL_MIRRORING
+		})
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.program_register);
+		for register in self.character_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		writer.write_register_u8(&self.mirroring_register);
+		writer.write_u8(self.latch0);
+		writer.write_u8(self.latch1);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.program_register)?;
+		for register in self.character_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		reader.read_register_u8(&mut self.mirroring_register)?;
+		self.latch0 = reader.read_u8()?;
+		self.latch1 = reader.read_u8()?;
+		Ok(())
+	}
+}
+
+struct MMC3Mapper {
+	program_bank_num: u16,
+	// TQROM selects CHR-RAM with bit 6 of the CHR bank number
+	has_chr_ram_bank: bool,
+	four_screen: bool,
+	register0: Register<u8>,
+	register1: Register<u8>,
+	register2: Register<u8>,
+	register3: Register<u8>,
+	register4: Register<u8>,
+	register5: Register<u8>,
+	register6: Register<u8>,
+	register7: Register<u8>,
+	program_register0: Register<u8>,
+	program_register1: Register<u8>,
+	character_register0: Register<u8>,
+	character_register1: Register<u8>,
+	character_register2: Register<u8>,
+	character_register3: Register<u8>,
+	character_register4: Register<u8>,
+	character_register5: Register<u8>,
+	irq_counter: u8,
+	irq_counter_reload: bool,
+	irq_enabled: bool,
+	irq_active: bool,
+	// MMC3A doesn't fire IRQ when the counter is naturally reloaded with 0
+	old_irq: bool,
+	// PPU A12 on the last PPU address and M2 (CPU) cycles since A12 falls
+	a12: bool,
+	a12_low_cycles: u8
+}
+
+impl MMC3Mapper {
+	fn new(header: &RomHeader) -> Self {
+		MMC3Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			has_chr_ram_bank: false,
+			four_screen: header.four_screen_mirroring(),
+			register0: Register::<u8>::new(),
+			register1: Register::<u8>::new(),
+			register2: Register::<u8>::new(),
+			register3: Register::<u8>::new(),
+			register4: Register::<u8>::new(),
+			register5: Register::<u8>::new(),
+			register6: Register::<u8>::new(),
+			register7: Register::<u8>::new(),
+			program_register0: Register::<u8>::new(),
+			program_register1: Register::<u8>::new(),
+			character_register0: Register::<u8>::new(),
+			character_register1: Register::<u8>::new(),
+			character_register2: Register::<u8>::new(),
+			character_register3: Register::<u8>::new(),
+			character_register4: Register::<u8>::new(),
+			character_register5: Register::<u8>::new(),
+			irq_counter: 0,
+			irq_counter_reload: false,
+			irq_enabled: false,
+			irq_active: false,
+			old_irq: false,
+			a12: false,
+			a12_low_cycles: 0
+		}
+	}
+
+	fn new_with_old_irq(header: &RomHeader) -> Self {
+		let mut mapper = MMC3Mapper::new(header);
+		mapper.old_irq = true;
+		mapper
+	}
+
+	/**
+	 * Clocked on the rising edges of PPU A12. If the counter is 0 or
+	 * reload is requested the counter is reloaded with the latch,
+	 * otherwise decremented. IRQ fires when the counter becomes 0.
+	 * Refer to https://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics
+	 */
+	fn clock_irq_counter(&mut self) {
+		let counter = self.irq_counter;
+		match self.irq_counter == 0 || self.irq_counter_reload {
+			true => self.irq_counter = self.register4.load(),
+			false => self.irq_counter -= 1
+		};
+		let fires = match self.old_irq {
+			true => (counter > 0 || self.irq_counter_reload) && self.irq_counter == 0,
+			false => self.irq_counter == 0
+		};
+		if fires && self.irq_enabled {
+			self.irq_active = true;
+		}
+		self.irq_counter_reload = false;
+	}
+
+	fn new_with_chr_ram_bank(header: &RomHeader) -> Self {
+		let mut mapper = MMC3Mapper::new(header);
+		mapper.has_chr_ram_bank = true;
+		mapper
+	}
+
+	// CHR bank number in 1KB unit
+	fn character_bank(&self, address: u32) -> u8 {
+		match self.register0.is_bit_set(7) {
+			true => match address & 0x1FFF {
+				0x0000..=0x03FF => self.character_register2.load(),
+				0x0400..=0x07FF => self.character_register3.load(),
+				0x0800..=0x0BFF => self.character_register4.load(),
+				0x0C00..=0x0FFF => self.character_register5.load(),
+				0x1000..=0x13FF => self.character_register0.load() & 0xFE,
+				0x1400..=0x17FF => self.character_register0.load() | 1,
+				0x1800..=0x1BFF => self.character_register1.load() & 0xFE,
+				_ => self.character_register1.load() | 1
+			},
+			false => match address & 0x1FFF {
+				0x0000..=0x03FF => self.character_register0.load() & 0xFE,
+				0x0400..=0x07FF => self.character_register0.load() | 1,
+				0x0800..=0x0BFF => self.character_register1.load() & 0xFE,
+				0x0C00..=0x0FFF => self.character_register1.load() | 1,
+				0x1000..=0x13FF => self.character_register2.load(),
+				0x1400..=0x17FF => self.character_register3.load(),
+				0x1800..=0x1BFF => self.character_register4.load(),
+				_ => self.character_register5.load()
+			}
+		}
+	}
+
+	fn selects_chr_ram(&self, address: u32) -> bool {
+		self.has_chr_ram_bank && (self.character_bank(address) & 0x40) != 0
+	}
+}
+
+impl Mapper for MMC3Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address {
+			0x8000..=0x9FFF => match self.register0.is_bit_set(6) {
+				true => self.program_bank_num * 2 - 2,
+				false => self.program_register0.load() as u16
+			},
+			0xA000..=0xBFFF => self.program_register1.load() as u16,
+			0xC000..=0xDFFF => match self.register0.is_bit_set(6) {
+				true => self.program_register0.load() as u16,
+				false => self.program_bank_num * 2 - 2
+			},
+			_ => self.program_bank_num * 2 - 1
+		};
+		// I couldn't in the spec but it seems that
+		// we need to wrap 2k bank with 4k program_bank_num
+		((bank as u32) % ((self.program_bank_num as u32) * 2)) * 0x2000 + (address & 0x1FFF)
+	}
+
+	// Banks larger than CHR memory wrap around in Cartridge
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		(self.character_bank(address) as u32) * 0x400 + (address & 0x3FF)
+	}
+
+	fn load_chr(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match self.selects_chr_ram(address) {
+			true => cartridge.load_chr_ram(self.map_for_chr_rom(address)),
+			false => cartridge.load_chr(self.map_for_chr_rom(address))
+		}
+	}
+
+	fn store_chr(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match self.selects_chr_ram(address) {
+			true => cartridge.store_chr_ram(self.map_for_chr_rom(address), value),
+			false => cartridge.store_chr(self.map_for_chr_rom(address), value)
+		};
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0x8000..=0x9FFF => match (address & 1) == 0 {
+				true => self.register0.store(value),
+				false => {
+					self.register1.store(value);
+					match self.register0.load_bits(0, 3) {
+						0 => self.character_register0.store(value & 0xFE),
+						1 => self.character_register1.store(value & 0xFE),
+						2 => self.character_register2.store(value),
+						3 => self.character_register3.store(value),
+						4 => self.character_register4.store(value),
+						5 => self.character_register5.store(value),
+						6 => self.program_register0.store(value & 0x3F),
+						_ => self.program_register1.store(value & 0x3F)
+					};
+				}
+			},
+			0xA000..=0xBFFF => match (address & 1) == 0 {
+				true => self.register2.store(value),
+				false => self.register3.store(value)
+			},
+			0xC000..=0xDFFF => match (address & 1) == 0 {
+				// IRQ latch
+				true => self.register4.store(value),
+				// IRQ reload, the counter is reloaded on the next clock
+				false => {
+					self.register5.store(value);
+					self.irq_counter = 0;
+					self.irq_counter_reload = true;
+				}
+			},
+			_ => match (address & 1) == 0 {
+				true => {
+					// Disabling IRQ also acknowledges the pending IRQ
+					self.register6.store(value);
+					self.irq_enabled = false;
+					self.irq_active = false;
+				},
+				false => {
+					self.register7.store(value);
+					self.irq_enabled = true;
+				}
+			}
+		};
+	}
+
+	// Four screen boards like TR1ROM ignore the mirroring register
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		match self.four_screen {
+			true => None,
+			false => Some(match self.register2.is_bit_set(0) {
+				true => HORIZONTAL_MIRRORING,
+				false => VERTICAL_MIRRORING
+			})
+		}
+	}
+
+	// A12 rises after it stays low for some M2 cycles clocks the IRQ counter.
+	// The rises between the background tile fetches and after the garbage
+	// nametable fetches at cycle 337-340, 9 PPU cycles, are filtered out.
+	fn notify_ppu_address(&mut self, address: u16) {
+		let a12 = (address & 0x1000) != 0;
+		if a12 && !self.a12 && self.a12_low_cycles > 3 {
+			self.clock_irq_counter();
+		}
+		if !a12 && self.a12 {
+			self.a12_low_cycles = 0;
+		}
+		self.a12 = a12;
+	}
+
+	fn step(&mut self) {
+		if !self.a12 && self.a12_low_cycles < 0xFF {
+			self.a12_low_cycles += 1;
+		}
+	}
+
+	fn irq_line(&self) -> bool {
+		self.irq_active
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register0);
+		writer.write_register_u8(&self.register1);
+		writer.write_register_u8(&self.register2);
+		writer.write_register_u8(&self.register3);
+		writer.write_register_u8(&self.register4);
+		writer.write_register_u8(&self.register5);
+		writer.write_register_u8(&self.register6);
+		writer.write_register_u8(&self.register7);
+		writer.write_register_u8(&self.program_register0);
+		writer.write_register_u8(&self.program_register1);
+		writer.write_register_u8(&self.character_register0);
+		writer.write_register_u8(&self.character_register1);
+		writer.write_register_u8(&self.character_register2);
+		writer.write_register_u8(&self.character_register3);
+		writer.write_register_u8(&self.character_register4);
+		writer.write_register_u8(&self.character_register5);
+		writer.write_u8(self.irq_counter);
+		writer.write_bool(self.irq_counter_reload);
+		writer.write_bool(self.irq_enabled);
+		writer.write_bool(self.irq_active);
+		writer.write_bool(self.a12);
+		writer.write_u8(self.a12_low_cycles);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register0)?;
+		reader.read_register_u8(&mut self.register1)?;
+		reader.read_register_u8(&mut self.register2)?;
+		reader.read_register_u8(&mut self.register3)?;
+		reader.read_register_u8(&mut self.register4)?;
+		reader.read_register_u8(&mut self.register5)?;
+		reader.read_register_u8(&mut self.register6)?;
+		reader.read_register_u8(&mut self.register7)?;
+		reader.read_register_u8(&mut self.program_register0)?;
+		reader.read_register_u8(&mut self.program_register1)?;
+		reader.read_register_u8(&mut self.character_register0)?;
+		reader.read_register_u8(&mut self.character_register1)?;
+		reader.read_register_u8(&mut self.character_register2)?;
+		reader.read_register_u8(&mut self.character_register3)?;
+		reader.read_register_u8(&mut self.character_register4)?;
+		reader.read_register_u8(&mut self.character_register5)?;
+		self.irq_counter = reader.read_u8()?;
+		self.irq_counter_reload = reader.read_bool()?;
+		self.irq_enabled = reader.read_bool()?;
+		self.irq_active = reader.read_bool()?;
+		self.a12 = reader.read_bool()?;
+		self.a12_low_cycles = reader.read_u8()?;
+		Ok(())
+	}
+}
+
+/**
+ * MMC5 (ExROM). Refer to https://wiki.nesdev.com/w/index.php/MMC5
+ *   0x5000 - 0x5015: Expansion audio, two pulse channels and PCM
+ *   0x5100: PRG mode, 0: 32KB, 1: 16KB x 2, 2: 16KB + 8KB x 2, 3: 8KB x 4
+ *   0x5101: CHR mode, 0: 8KB, 1: 4KB, 2: 2KB, 3: 1KB banks
+ *   0x5102, 0x5103: PRG-RAM write protect, writable with 2 and 1
+ *   0x5104: ExRAM mode
+ *     0: Nametable, 1: Extended attribute, 2: CPU RAM, 3: CPU ROM
+ *   0x5105: Nametable mapping, 2 bits for each quadrant
+ *     0: CIRAM page 0, 1: CIRAM page 1, 2: ExRAM, 3: Fill mode
+ *   0x5106, 0x5107: Fill mode tile and attribute
+ *   0x5113: 8KB PRG-RAM bank at 0x6000
+ *   0x5114 - 0x5117: PRG banks, bit 7 selects ROM(1) or RAM(0)
+ *   0x5120 - 0x5127: CHR banks for sprites
+ *   0x5128 - 0x512B: CHR banks for background in 8x16 sprite mode
+ *   0x5130: Upper CHR bank bits
+ *   0x5200 - 0x5202: Vertical split control, scroll and CHR bank
+ *   0x5203, 0x5204: Scanline IRQ compare value, and enable/status
+ *   0x5205, 0x5206: 8x8 bits multiplier
+ *   0x5C00 - 0x5FFF: 1KB ExRAM
+ *
+ * MMC5 has no PPU scanline signal so it detects scanlines by watching
+ * PPU bus. Three reads of the same nametable address in a row, the two
+ * dummy fetches at dot 337 and 339 and the first fetch at dot 1 of the
+ * next line, start a scanline. PPU makes 170 fetches in a scanline so
+ * MMC5 knows which tile or sprite PPU is fetching by counting them.
+ *   0 - 127: Background tiles 2 - 33 of the scanline
+ *   128 - 159: Sprites for the next scanline
+ *   160 - 167: Background tiles 0 - 1 of the next scanline
+ *   168 - 169: Dummy nametable fetches
+ * The scanline counter stops when PPU makes no fetches for three CPU cycles.
+ */
+struct MMC5Mapper {
+	prg_mode: Register<u8>,
+	chr_mode: Register<u8>,
+	prg_ram_protect1: Register<u8>,
+	prg_ram_protect2: Register<u8>,
+	exram_mode: Register<u8>,
+	nametable_register: Register<u8>,
+	fill_tile: Register<u8>,
+	fill_attribute: Register<u8>,
+	// 0x5113 - 0x5117
+	prg_registers: [Register<u8>; 5],
+	// CHR bank registers with the upper bits at the time of writes
+	sprite_chr_banks: [u16; 8],
+	background_chr_banks: [u16; 4],
+	chr_upper_bits: Register<u8>,
+	// The CHR bank set last written is used while PPU isn't rendering
+	background_chr_banks_written: bool,
+	split_control: Register<u8>,
+	split_scroll: Register<u8>,
+	split_bank: Register<u8>,
+	irq_scanline: Register<u8>,
+	irq_enabled: bool,
+	irq_pending: bool,
+	multiplicand: Register<u8>,
+	multiplier: Register<u8>,
+	exram: Memory,
+
+	// PPU status snooped from PPUCTRL
+	sprite_8x16: bool,
+
+	// Scanline detection and fetch counting
+	last_ppu_address: u16,
+	same_address_count: u8,
+	ppu_idle_cycles: u8,
+	fetch_count: u8,
+	in_frame: bool,
+	scanline: u8,
+
+	// Background tile being fetched
+	split_tile: bool,
+	split_column: u8,
+	split_y: u8,
+	extended_attribute: u8,
+
+	audio: MMC5Audio
+}
+
+impl MMC5Mapper {
+	fn new() -> Self {
+		let mut prg_mode = Register::<u8>::new();
+		prg_mode.store(3);
+		let mut prg_registers = [
+			Register::<u8>::new(),
+			Register::<u8>::new(),
+			Register::<u8>::new(),
+			Register::<u8>::new(),
+			Register::<u8>::new()
+		];
+		for register in prg_registers.iter_mut().skip(1) {
+			register.store(0xFF);
+		}
+		MMC5Mapper {
+			prg_mode: prg_mode,
+			chr_mode: Register::<u8>::new(),
+			prg_ram_protect1: Register::<u8>::new(),
+			prg_ram_protect2: Register::<u8>::new(),
+			exram_mode: Register::<u8>::new(),
+			nametable_register: Register::<u8>::new(),
+			fill_tile: Register::<u8>::new(),
+			fill_attribute: Register::<u8>::new(),
+			prg_registers: prg_registers,
+			sprite_chr_banks: [0; 8],
+			background_chr_banks: [0; 4],
+			chr_upper_bits: Register::<u8>::new(),
+			background_chr_banks_written: false,
+			split_control: Register::<u8>::new(),
+			split_scroll: Register::<u8>::new(),
+			split_bank: Register::<u8>::new(),
+			irq_scanline: Register::<u8>::new(),
+			irq_enabled: false,
+			irq_pending: false,
+			multiplicand: Register::<u8>::new(),
+			multiplier: Register::<u8>::new(),
+			exram: Memory::new(vec![0; 0x400]),
+			sprite_8x16: false,
+			last_ppu_address: 0,
+			same_address_count: 0,
+			ppu_idle_cycles: 0,
+			fetch_count: 0,
+			in_frame: false,
+			scanline: 0,
+			split_tile: false,
+			split_column: 0,
+			split_y: 0,
+			extended_attribute: 0,
+			audio: MMC5Audio::new()
+		}
+	}
+
+	// Returns whether PRG-ROM is selected and the address in PRG-ROM or PRG-RAM
+	// for 0x8000 - 0xFFFF. 0x5117 always selects PRG-ROM.
+	fn map_prg(&self, address: u32) -> (bool, u32) {
+		let (index, size) = match (self.prg_mode.load_bits(0, 2), address) {
+			(0, _) => (4, 0x8000),
+			(1, 0x8000..=0xBFFF) => (2, 0x4000),
+			(1, _) => (4, 0x4000),
+			(2, 0x8000..=0xBFFF) => (2, 0x4000),
+			(2, 0xC000..=0xDFFF) => (3, 0x2000),
+			(2, _) => (4, 0x2000),
+			_ => (((address - 0x8000) / 0x2000) as usize + 1, 0x2000)
+		};
+		let value = self.prg_registers[index].load() as u32;
+		let bank = (value & 0x7F) & !(size / 0x2000 - 1);
+		(index == 4 || (value & 0x80) != 0, bank * 0x2000 + (address & (size - 1)))
+	}
+
+	fn prg_ram_writable(&self) -> bool {
+		self.prg_ram_protect1.load_bits(0, 2) == 2 && self.prg_ram_protect2.load_bits(0, 2) == 1
+	}
+
+	fn store_chr_bank(&mut self, address: u32, value: u8) {
+		let bank = ((self.chr_upper_bits.load_bits(0, 2) as u16) << 8) | value as u16;
+		match address {
+			0x5120..=0x5127 => {
+				self.sprite_chr_banks[(address - 0x5120) as usize] = bank;
+				self.background_chr_banks_written = false;
+			},
+			_ /* 0x5128..=0x512B */ => {
+				self.background_chr_banks[(address - 0x5128) as usize] = bank;
+				self.background_chr_banks_written = true;
+			}
+		};
+	}
+
+	fn detect_scanline(&mut self) {
+		match self.in_frame {
+			false => {
+				self.in_frame = true;
+				self.scanline = 0;
+			},
+			true => {
+				self.scanline = self.scanline.wrapping_add(1);
+				if self.scanline == self.irq_scanline.load() {
+					self.irq_pending = true;
+				}
+			}
+		};
+	}
+
+	// Column of the background tile PPU is fetching, or None
+	fn background_column(&self) -> Option<u8> {
+		match self.in_frame {
+			true => match self.fetch_count {
+				0..=127 => Some(self.fetch_count / 4 + 2),
+				160..=167 => Some((self.fetch_count - 160) / 4),
+				_ => None
+			},
+			false => None
+		}
+	}
+
+	fn fetching_sprites(&self) -> bool {
+		self.in_frame && self.fetch_count >= 128 && self.fetch_count < 160
+	}
+
+	fn is_split_column(&self, column: u8) -> bool {
+		if !self.split_control.is_bit_set(7) || self.exram_mode.load_bits(0, 2) > 1 {
+			return false;
+		}
+		let threshold = self.split_control.load_bits(0, 5);
+		match self.split_control.is_bit_set(6) {
+			true => column >= threshold,
+			false => column < threshold
+		}
+	}
+
+	// Split screen and extended attribute replace the nametable
+	// and attribute fetches for background tiles
+	fn overrides_nametable(&self) -> bool {
+		match self.background_column() {
+			Some(_) => match self.fetch_count % 4 {
+				0 => self.split_tile,
+				1 => self.split_tile || self.exram_mode.load_bits(0, 2) == 1,
+				_ => false
+			},
+			None => false
+		}
+	}
+
+	fn load_overridden_nametable(&self) -> u8 {
+		if self.fetch_count % 4 == 0 {
+			let address = (self.split_y as u32 / 8) * 32 + (self.split_column as u32 & 0x1F);
+			return self.exram.load(address);
+		}
+		let palette = match self.split_tile {
+			true => {
+				let column = self.split_column as u32 & 0x1F;
+				let address = 0x3C0 + (self.split_y as u32 / 32) * 8 + column / 4;
+				let shift = ((self.split_y / 16) & 1) * 4 + ((column as u8 / 2) & 1) * 2;
+				(self.exram.load(address) >> shift) & 3
+			},
+			false => self.extended_attribute >> 6
+		};
+		// Same palette for all the four tiles
+		palette * 0x55
+	}
+}
+
+impl Mapper for MMC5Mapper {
+	fn map(&self, address: u32) -> u32 {
+		self.map_prg(address).1
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		// The background set is used only in 8x16 sprite mode
+		let background = match (self.sprite_8x16, self.in_frame) {
+			(true, true) => !self.fetching_sprites(),
+			(true, false) => self.background_chr_banks_written,
+			(false, _) => false
+		};
+		let (size, index) = match self.chr_mode.load_bits(0, 2) {
+			0 => (0x2000, 7),
+			1 => (0x1000, (address / 0x1000) * 4 + 3),
+			2 => (0x800, (address / 0x800) * 2 + 1),
+			_ => (0x400, address / 0x400)
+		};
+		let bank = match background {
+			true => self.background_chr_banks[(index & 3) as usize],
+			false => self.sprite_chr_banks[index as usize]
+		} as u32;
+		bank * size + (address & (size - 1))
+	}
+
+	fn load_chr(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		let background_pattern = self.background_column().is_some() && self.fetch_count % 4 >= 2;
+		if background_pattern && self.split_tile {
+			// Fine Y of the split region replaces the PPU's
+			let offset = (address & 0xFF8) | (self.split_y as u32 & 7);
+			return cartridge.load_chr(self.split_bank.load() as u32 * 0x1000 + offset);
+		}
+		if background_pattern && self.exram_mode.load_bits(0, 2) == 1 {
+			let bank = (self.extended_attribute as u32 & 0x3F) |
+				((self.chr_upper_bits.load_bits(0, 2) as u32) << 6);
+			return cartridge.load_chr(bank * 0x1000 + (address & 0xFFF));
+		}
+		cartridge.load_chr(self.map_for_chr_rom(address))
+	}
+
+	fn store(&mut self, _address: u32, _value: u8) {
+		// Registers are in 0x5000 - 0x5FFF, see store_prg()
+	}
+
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x5010 | 0x5015 => self.audio.load_register(address),
+			0x5204 => {
+				let mut value = 0;
+				if self.irq_pending {
+					value |= 0x80;
+				}
+				if self.in_frame {
+					value |= 0x40;
+				}
+				// Reading the status acknowledges IRQ
+				self.irq_pending = false;
+				value
+			},
+			0x5205 => (self.multiplicand.load() as u16 * self.multiplier.load() as u16) as u8,
+			0x5206 => ((self.multiplicand.load() as u16 * self.multiplier.load() as u16) >> 8) as u8,
+			0x5C00..=0x5FFF => match self.exram_mode.load_bits(0, 2) >= 2 {
+				true => self.exram.load(address - 0x5C00),
+				false => 0
+			},
+			0x6000..=0x7FFF => {
+				let bank = self.prg_registers[0].load_bits(0, 4) as u32;
+				cartridge.load_prg_ram(bank * 0x2000 + (address - 0x6000))
+			},
+			0x8000..=0xFFFF => {
+				// PPU is in vblank when CPU reads NMI vector
+				if address == 0xFFFA || address == 0xFFFB {
+					self.in_frame = false;
+				}
+				let value = match self.map_prg(address) {
+					(true, address) => cartridge.load_prg_rom(address),
+					(false, address) => cartridge.load_prg_ram(address)
+				};
+				if address < 0xC000 {
+					self.audio.notify_prg_load(value);
+				}
+				value
+			},
+			_ => 0
+		}
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x5000..=0x5015 => self.audio.store_register(address, value),
+			0x5100 => self.prg_mode.store(value),
+			0x5101 => self.chr_mode.store(value),
+			0x5102 => self.prg_ram_protect1.store(value),
+			0x5103 => self.prg_ram_protect2.store(value),
+			0x5104 => self.exram_mode.store(value),
+			0x5105 => self.nametable_register.store(value),
+			0x5106 => self.fill_tile.store(value),
+			0x5107 => self.fill_attribute.store(value),
+			0x5113..=0x5117 => self.prg_registers[(address - 0x5113) as usize].store(value),
+			0x5120..=0x512B => self.store_chr_bank(address, value),
+			0x5130 => self.chr_upper_bits.store(value),
+			0x5200 => self.split_control.store(value),
+			0x5201 => self.split_scroll.store(value),
+			0x5202 => self.split_bank.store(value),
+			0x5203 => self.irq_scanline.store(value),
+			0x5204 => self.irq_enabled = (value & 0x80) != 0,
+			0x5205 => self.multiplicand.store(value),
+			0x5206 => self.multiplier.store(value),
+			0x5C00..=0x5FFF => match self.exram_mode.load_bits(0, 2) {
+				// Nametable modes accept writes only while rendering
+				0 | 1 => self.exram.store(address - 0x5C00, match self.in_frame {
+					true => value,
+					false => 0
+				}),
+				2 => self.exram.store(address - 0x5C00, value),
+				_ => {}
+			},
+			0x6000..=0x7FFF => {
+				if self.prg_ram_writable() {
+					let bank = self.prg_registers[0].load_bits(0, 4) as u32;
+					cartridge.store_prg_ram(bank * 0x2000 + (address - 0x6000), value);
+				}
+			},
+			0x8000..=0xFFFF => {
+				if let (false, address) = self.map_prg(address) {
+					if self.prg_ram_writable() {
+						cartridge.store_prg_ram(address, value);
+					}
+				}
+			},
+			_ => {}
+		};
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		if self.overrides_nametable() {
+			return Some([Nametable::ExRam; 4]);
+		}
+		let mut mapping = [Nametable::Ciram(0); 4];
+		for (i, nametable) in mapping.iter_mut().enumerate() {
+			*nametable = match self.nametable_register.load_bits(i as u8 * 2, 2) {
+				0 => Nametable::Ciram(0),
+				1 => Nametable::Ciram(1),
+				2 => Nametable::ExRam,
+				_ => Nametable::Fill
+			};
+		}
+		Some(mapping)
+	}
+
+	fn load_nametable(&self, _cartridge: &Cartridge, nametable: Nametable, address: u32) -> u8 {
+		if self.overrides_nametable() {
+			return self.load_overridden_nametable();
+		}
+		match nametable {
+			Nametable::ExRam => match self.exram_mode.load_bits(0, 2) <= 1 {
+				true => self.exram.load(address),
+				false => 0
+			},
+			Nametable::Fill => match address < 0x3C0 {
+				true => self.fill_tile.load(),
+				false => self.fill_attribute.load_bits(0, 2) * 0x55
+			},
+			_ => 0
+		}
+	}
+
+	fn store_nametable(&mut self, _cartridge: &mut Cartridge, nametable: Nametable, address: u32, value: u8) {
+		if nametable == Nametable::ExRam && self.exram_mode.load_bits(0, 2) <= 1 {
+			self.exram.store(address, value);
+		}
+	}
+
+	fn notify_ppu_address(&mut self, address: u16) {
+		self.ppu_idle_cycles = 0;
+		match address >= 0x2000 && address < 0x3000 && address == self.last_ppu_address {
+			true => self.same_address_count = self.same_address_count.saturating_add(1),
+			false => self.same_address_count = 0
+		};
+		self.last_ppu_address = address;
+
+		match self.same_address_count == 2 {
+			true => {
+				self.detect_scanline();
+				self.fetch_count = 0;
+			},
+			false => self.fetch_count = self.fetch_count.saturating_add(1)
+		};
+
+		// Nametable fetch of a background tile
+		if let Some(column) = self.background_column() {
+			if self.fetch_count % 4 == 0 {
+				let line = match self.fetch_count < 128 {
+					true => self.scanline as u16,
+					false => self.scanline as u16 + 1
+				};
+				self.split_tile = self.is_split_column(column);
+				self.split_column = column;
+				self.split_y = ((self.split_scroll.load() as u16 + line) % 240) as u8;
+				self.extended_attribute = self.exram.load(address as u32 & 0x3FF);
+			}
+		}
+	}
+
+	fn notify_ppu_register_store(&mut self, address: u16, value: u8) {
+		if address == 0x2000 {
+			self.sprite_8x16 = (value & 0x20) != 0;
+		}
+	}
+
+	fn step(&mut self) {
+		if self.ppu_idle_cycles < 3 {
+			self.ppu_idle_cycles += 1;
+			if self.ppu_idle_cycles == 3 {
+				self.in_frame = false;
+			}
+		}
+		self.audio.step();
+	}
+
+	fn irq_line(&self) -> bool {
+		(self.irq_enabled && self.irq_pending) || self.audio.irq_line()
+	}
+
+	fn audio_output(&self) -> f32 {
+		self.audio.output()
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.prg_mode);
+		writer.write_register_u8(&self.chr_mode);
+		writer.write_register_u8(&self.prg_ram_protect1);
+		writer.write_register_u8(&self.prg_ram_protect2);
+		writer.write_register_u8(&self.exram_mode);
+		writer.write_register_u8(&self.nametable_register);
+		writer.write_register_u8(&self.fill_tile);
+		writer.write_register_u8(&self.fill_attribute);
+		for register in self.prg_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		for bank in self.sprite_chr_banks.iter() {
+			writer.write_u16(*bank);
+		}
+		for bank in self.background_chr_banks.iter() {
+			writer.write_u16(*bank);
+		}
+		writer.write_register_u8(&self.chr_upper_bits);
+		writer.write_bool(self.background_chr_banks_written);
+		writer.write_register_u8(&self.split_control);
+		writer.write_register_u8(&self.split_scroll);
+		writer.write_register_u8(&self.split_bank);
+		writer.write_register_u8(&self.irq_scanline);
+		writer.write_bool(self.irq_enabled);
+		writer.write_bool(self.irq_pending);
+		writer.write_register_u8(&self.multiplicand);
+		writer.write_register_u8(&self.multiplier);
+		writer.write_memory(&self.exram);
+		writer.write_bool(self.sprite_8x16);
+		writer.write_u16(self.last_ppu_address);
+		writer.write_u8(self.same_address_count);
+		writer.write_u8(self.ppu_idle_cycles);
+		writer.write_u8(self.fetch_count);
+		writer.write_bool(self.in_frame);
+		writer.write_u8(self.scanline);
+		writer.write_bool(self.split_tile);
+		writer.write_u8(self.split_column);
+		writer.write_u8(self.split_y);
+		writer.write_u8(self.extended_attribute);
+		self.audio.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.prg_mode)?;
+		reader.read_register_u8(&mut self.chr_mode)?;
+		reader.read_register_u8(&mut self.prg_ram_protect1)?;
+		reader.read_register_u8(&mut self.prg_ram_protect2)?;
+		reader.read_register_u8(&mut self.exram_mode)?;
+		reader.read_register_u8(&mut self.nametable_register)?;
+		reader.read_register_u8(&mut self.fill_tile)?;
+		reader.read_register_u8(&mut self.fill_attribute)?;
+		for register in self.prg_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		for bank in self.sprite_chr_banks.iter_mut() {
+			*bank = reader.read_u16()?;
+		}
+		for bank in self.background_chr_banks.iter_mut() {
+			*bank = reader.read_u16()?;
+		}
+		reader.read_register_u8(&mut self.chr_upper_bits)?;
+		self.background_chr_banks_written = reader.read_bool()?;
+		reader.read_register_u8(&mut self.split_control)?;
+		reader.read_register_u8(&mut self.split_scroll)?;
+		reader.read_register_u8(&mut self.split_bank)?;
+		reader.read_register_u8(&mut self.irq_scanline)?;
+		self.irq_enabled = reader.read_bool()?;
+		self.irq_pending = reader.read_bool()?;
+		reader.read_register_u8(&mut self.multiplicand)?;
+		reader.read_register_u8(&mut self.multiplier)?;
+		reader.read_memory(&mut self.exram)?;
+		self.sprite_8x16 = reader.read_bool()?;
+		self.last_ppu_address = reader.read_u16()?;
+		self.same_address_count = reader.read_u8()?;
+		self.ppu_idle_cycles = reader.read_u8()?;
+		self.fetch_count = reader.read_u8()?;
+		self.in_frame = reader.read_bool()?;
+		self.scanline = reader.read_u8()?;
+		self.split_tile = reader.read_bool()?;
+		self.split_column = reader.read_u8()?;
+		self.split_y = reader.read_u8()?;
+		self.extended_attribute = reader.read_u8()?;
+		self.audio.load_state(reader)
+	}
+}
+
+/**
+ * MMC5 expansion audio. Two pulse channels same as APU's without sweep,
+ * and 8-bit PCM channel.
+ *   0x5000 - 0x5003: Pulse 1
+ *   0x5004 - 0x5007: Pulse 2
+ *   0x5010: PCM mode, bit 0: read mode, bit 7: IRQ enable
+ *   0x5011: PCM raw data in write mode
+ *   0x5015: Pulse enable and length counter status
+ * In read mode, PCM takes the bytes CPU reads from 0x8000 - 0xBFFF
+ * and 0 triggers IRQ.
+ */
+struct MMC5Audio {
+	pulse1: ApuPulse,
+	pulse2: ApuPulse,
+	pcm_mode: Register<u8>,
+	pcm: u8,
+	pcm_irq_active: bool,
+	cycle: u32
+}
+
+// MMC5 clocks the envelope and length counter at fixed 240Hz
+// while APU's frame sequencer is configurable
+static MMC5_FRAME_CYCLES: u32 = 7457;
+
+impl MMC5Audio {
+	fn new() -> Self {
+		MMC5Audio {
+			pulse1: ApuPulse::new(ApuPulseChannel::Mmc5),
+			pulse2: ApuPulse::new(ApuPulseChannel::Mmc5),
+			pcm_mode: Register::<u8>::new(),
+			pcm: 0,
+			pcm_irq_active: false,
+			cycle: 0
+		}
+	}
+
+	fn load_register(&mut self, address: u32) -> u8 {
+		match address {
+			0x5010 => {
+				let value = match self.pcm_irq_active {
+					true => 0x80,
+					false => 0
+				};
+				self.pcm_irq_active = false;
+				value
+			},
+			_ /* 0x5015 */ => {
+				let mut value = 0;
+				if self.pulse1.is_length_counter_active() {
+					value |= 0x01;
+				}
+				if self.pulse2.is_length_counter_active() {
+					value |= 0x02;
+				}
+				value
+			}
+		}
+	}
+
+	fn store_register(&mut self, address: u32, value: u8) {
+		match address {
+			0x5000..=0x5003 => self.pulse1.store_register(address as u16, value),
+			0x5004..=0x5007 => self.pulse2.store_register(address as u16, value),
+			0x5010 => self.pcm_mode.store(value),
+			0x5011 => {
+				// Writes of 0 are ignored
+				if !self.pcm_mode.is_bit_set(0) && value != 0 {
+					self.pcm = value;
+				}
+			},
+			0x5015 => {
+				self.pulse1.set_enable((value & 0x1) == 0x1);
+				self.pulse2.set_enable((value & 0x2) == 0x2);
+			},
+			_ => {}
+		};
+	}
+
+	fn notify_prg_load(&mut self, value: u8) {
+		if !self.pcm_mode.is_bit_set(0) {
+			return;
+		}
+		match value {
+			0 => self.pcm_irq_active = true,
+			_ => self.pcm = value
+		};
+	}
+
+	fn step(&mut self) {
+		self.cycle += 1;
+		if (self.cycle % 2) == 0 {
+			self.pulse1.drive_timer();
+			self.pulse2.drive_timer();
+		}
+		if self.cycle >= MMC5_FRAME_CYCLES {
+			self.cycle = 0;
+			self.pulse1.drive_envelope();
+			self.pulse1.drive_length();
+			self.pulse2.drive_envelope();
+			self.pulse2.drive_length();
+		}
+	}
+
+	fn irq_line(&self) -> bool {
+		self.pcm_mode.is_bit_set(7) && self.pcm_irq_active
+	}
+
+	// Pulses are mixed like APU pulses and PCM is as loud as DMC
+	fn output(&self) -> f32 {
+		let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+		let pcm = self.pcm as f32 / 2.0;
+
+		let mut output = 0.0;
+		if pulse != 0.0 {
+			output += 95.88 / ((8128.0 / pulse) + 100.0);
+		}
+		if pcm != 0.0 {
+			output += 159.79 / (1.0 / (pcm / 22638.0) + 100.0);
+		}
+		output
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		self.pulse1.save_state(writer);
+		self.pulse2.save_state(writer);
+		writer.write_register_u8(&self.pcm_mode);
+		writer.write_u8(self.pcm);
+		writer.write_bool(self.pcm_irq_active);
+		writer.write_u32(self.cycle);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		self.pulse1.load_state(reader)?;
+		self.pulse2.load_state(reader)?;
+		reader.read_register_u8(&mut self.pcm_mode)?;
+		self.pcm = reader.read_u8()?;
+		self.pcm_irq_active = reader.read_bool()?;
+		self.cycle = reader.read_u32()?;
+		Ok(())
+	}
+}
+
+/**
+ * IRQ counter shared by Konami VRC4, VRC6 and VRC7.
+ * 8-bit counter counts up from the latch and fires IRQ on overflow.
+ * In scanline mode, a prescaler divides CPU cycles by 341/3, about
+ * a scanline. In cycle mode, the counter is clocked every CPU cycle.
+ * The control register is
+ *   bit 0: Enable after acknowledgement
+ *   bit 1: Enable
+ *   bit 2: Mode, 0: scanline, 1: cycle
+ */
+struct VrcIrq {
+	latch: Register<u8>,
+	control: Register<u8>,
+	counter: u8,
+	prescaler: i16,
+	pending: bool
+}
+
+impl VrcIrq {
+	fn new() -> Self {
+		VrcIrq {
+			latch: Register::<u8>::new(),
+			control: Register::<u8>::new(),
+			counter: 0,
+			prescaler: 341,
+			pending: false
+		}
+	}
+
+	fn store_latch(&mut self, value: u8) {
+		self.latch.store(value);
+	}
+
+	// VRC4 writes the latch in two 4-bit registers
+	fn store_latch_low(&mut self, value: u8) {
+		self.latch.store_bits(0, 4, value);
+	}
+
+	fn store_latch_high(&mut self, value: u8) {
+		self.latch.store_bits(4, 4, value);
+	}
+
+	fn store_control(&mut self, value: u8) {
+		self.control.store(value);
+		self.pending = false;
+		if self.control.is_bit_set(1) {
+			self.counter = self.latch.load();
+			self.prescaler = 341;
+		}
+	}
+
+	fn acknowledge(&mut self) {
+		self.pending = false;
+		let enable_after_acknowledgement = self.control.load_bit(0);
+		self.control.store_bit(1, enable_after_acknowledgement);
+	}
+
+	fn step(&mut self) {
+		if !self.control.is_bit_set(1) {
+			return;
+		}
+		if self.control.is_bit_set(2) {
+			self.clock();
+			return;
+		}
+		self.prescaler -= 3;
+		if self.prescaler <= 0 {
+			self.prescaler += 341;
+			self.clock();
+		}
+	}
+
+	fn clock(&mut self) {
+		match self.counter {
+			0xFF => {
+				self.counter = self.latch.load();
+				self.pending = true;
+			},
+			_ => self.counter += 1
+		};
+	}
+
+	fn irq_line(&self) -> bool {
+		self.pending
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.latch);
+		writer.write_register_u8(&self.control);
+		writer.write_u8(self.counter);
+		writer.write_u16(self.prescaler as u16);
+		writer.write_bool(self.pending);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.latch)?;
+		reader.read_register_u8(&mut self.control)?;
+		self.counter = reader.read_u8()?;
+		self.prescaler = reader.read_u16()? as i16;
+		self.pending = reader.read_bool()?;
+		Ok(())
+	}
+}
+
+/**
+ * Konami VRC6. VRC6a (mapper 24) and VRC6b (mapper 26) are the same
+ * chip except that A0 and A1 are swapped on VRC6b boards.
+ * The registers, in VRC6a address, are
+ *   0x8000 - 0x8003: 16KB PRG-ROM bank at 0x8000
+ *   0x9000 - 0x9003: Pulse 1 and frequency control
+ *   0xA000 - 0xA002: Pulse 2
+ *   0xB000 - 0xB002: Sawtooth
+ *   0xB003: bit 0-1: CHR banking mode, bit 2-3: mirroring,
+ *           bit 5: CHR A10 from PPU A10, bit 7: PRG-RAM enable
+ *   0xC000 - 0xC003: 8KB PRG-ROM bank at 0xC000
+ *   0xD000 - 0xE003: 1KB CHR-ROM bank registers R0 - R7
+ *   0xF000: IRQ latch, 0xF001: IRQ control, 0xF002: IRQ acknowledgement
+ * 0xE000 - 0xFFFF is fixed to the last 8KB bank.
+ * Nametables from CHR-ROM, bit 4 of 0xB003, are not supported
+ * because no games use them.
+ */
+struct VRC6Mapper {
+	program_bank_num: u16,
+	// VRC6b swaps A0 and A1
+	swaps_address_lines: bool,
+	program_register0: Register<u8>,
+	program_register1: Register<u8>,
+	banking_register: Register<u8>,
+	character_registers: [Register<u8>; 8],
+	irq: VrcIrq,
+	audio: VRC6Audio
+}
+
+impl VRC6Mapper {
+	fn new(header: &RomHeader) -> Self {
+		VRC6Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			swaps_address_lines: false,
+			program_register0: Register::<u8>::new(),
+			program_register1: Register::<u8>::new(),
+			banking_register: Register::<u8>::new(),
+			character_registers: [
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new()
+			],
+			irq: VrcIrq::new(),
+			audio: VRC6Audio::new()
+		}
+	}
+
+	fn new_vrc6b(header: &RomHeader) -> Self {
+		let mut mapper = VRC6Mapper::new(header);
+		mapper.swaps_address_lines = true;
+		mapper
+	}
+
+	// Converts to VRC6a register address
+	fn register_address(&self, address: u32) -> u32 {
+		match self.swaps_address_lines {
+			true => (address & 0xF000) | ((address & 1) << 1) | ((address & 2) >> 1),
+			false => address & 0xF003
+		}
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.banking_register.is_bit_set(7)
+	}
+
+	// Returns 1KB CHR bank
+	fn chr_bank(&self, address: u32) -> u32 {
+		let slot = (address >> 10) as usize;
+		let a10 = (address >> 10) & 1;
+		// In 2KB banks, CHR A10 comes from PPU A10 or bit 0 of the register
+		let bank_2kb = |register: &Register<u8>| {
+			let bank = register.load() as u32;
+			match self.banking_register.is_bit_set(5) {
+				true => (bank & !1) | a10,
+				false => bank
+			}
+		};
+		match self.banking_register.load_bits(0, 2) {
+			0 => self.character_registers[slot].load() as u32,
+			1 => bank_2kb(&self.character_registers[slot / 2]),
+			_ => match address < 0x1000 {
+				true => self.character_registers[slot].load() as u32,
+				false => bank_2kb(&self.character_registers[4 + (slot - 4) / 2])
+			}
+		}
+	}
+}
+
+impl Mapper for VRC6Mapper {
+	fn map(&self, address: u32) -> u32 {
+		match address {
+			0x8000..=0xBFFF => self.program_register0.load_bits(0, 4) as u32 * 0x4000 + (address & 0x3FFF),
+			0xC000..=0xDFFF => self.program_register1.load_bits(0, 5) as u32 * 0x2000 + (address & 0x1FFF),
+			_ => self.program_bank_num as u32 * 0x4000 - 0x2000 + (address & 0x1FFF)
+		}
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.chr_bank(address) * 0x400 + (address & 0x3FF)
+	}
+
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x6000..=0x7FFF => match self.prg_ram_enabled() {
+				true => cartridge.load_prg_ram(address - 0x6000),
+				false => 0
+			},
+			0x8000..=0xFFFF => cartridge.load_prg_rom(self.map(address)),
+			_ => 0
+		}
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x6000..=0x7FFF => {
+				if self.prg_ram_enabled() {
+					cartridge.store_prg_ram(address - 0x6000, value);
+				}
+			},
+			0x8000..=0xFFFF => self.store(address, value),
+			_ => {}
+		};
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		let address = self.register_address(address);
+		match address {
+			0x8000..=0x8003 => self.program_register0.store(value),
+			0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => self.audio.store_register(address, value),
+			0xB003 => self.banking_register.store(value),
+			0xC000..=0xC003 => self.program_register1.store(value),
+			0xD000..=0xD003 => self.character_registers[(address & 3) as usize].store(value),
+			0xE000..=0xE003 => self.character_registers[4 + (address & 3) as usize].store(value),
+			0xF000 => self.irq.store_latch(value),
+			0xF001 => self.irq.store_control(value),
+			0xF002 => self.irq.acknowledge(),
+			_ => {}
+		};
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		Some(match self.banking_register.load_bits(2, 2) {
+			0 => VERTICAL_MIRRORING,
+			1 => HORIZONTAL_MIRRORING,
+			2 => SINGLE_SCREEN_A,
+			_ => SINGLE_SCREEN_B
+		})
+	}
+
+	fn step(&mut self) {
+		self.irq.step();
+		self.audio.step();
+	}
+
+	fn irq_line(&self) -> bool {
+		self.irq.irq_line()
+	}
+
+	fn audio_output(&self) -> f32 {
+		self.audio.output()
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.program_register0);
+		writer.write_register_u8(&self.program_register1);
+		writer.write_register_u8(&self.banking_register);
+		for register in self.character_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		self.irq.save_state(writer);
+		self.audio.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.program_register0)?;
+		reader.read_register_u8(&mut self.program_register1)?;
+		reader.read_register_u8(&mut self.banking_register)?;
+		for register in self.character_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		self.irq.load_state(reader)?;
+		self.audio.load_state(reader)
+	}
+}
+
+/**
+ * VRC6 expansion audio, two pulse channels and a sawtooth channel.
+ * Their timers are clocked every CPU cycle.
+ *   0x9000 - 0x9002: Pulse 1
+ *   0x9003: bit 0: halt, bit 1: period >> 4, bit 2: period >> 8
+ *   0xA000 - 0xA002: Pulse 2
+ *   0xB000 - 0xB002: Sawtooth
+ */
+struct VRC6Audio {
+	pulse1: VRC6Pulse,
+	pulse2: VRC6Pulse,
+	sawtooth: VRC6Sawtooth,
+	frequency_register: Register<u8>
+}
+
+impl VRC6Audio {
+	fn new() -> Self {
+		VRC6Audio {
+			pulse1: VRC6Pulse::new(),
+			pulse2: VRC6Pulse::new(),
+			sawtooth: VRC6Sawtooth::new(),
+			frequency_register: Register::<u8>::new()
+		}
+	}
+
+	fn store_register(&mut self, address: u32, value: u8) {
+		let index = (address & 3) as usize;
+		match address {
+			0x9003 => self.frequency_register.store(value),
+			0x9000..=0x9002 => self.pulse1.store_register(index, value),
+			0xA000..=0xA002 => self.pulse2.store_register(index, value),
+			0xB000..=0xB002 => self.sawtooth.store_register(index, value),
+			_ => {}
+		};
+	}
+
+	fn period_shift(&self) -> u8 {
+		match self.frequency_register.load_bits(1, 2) {
+			0 => 0,
+			1 => 4,
+			_ => 8
+		}
+	}
+
+	fn step(&mut self) {
+		if self.frequency_register.is_bit_set(0) {
+			return;
+		}
+		let shift = self.period_shift();
+		self.pulse1.drive_timer(shift);
+		self.pulse2.drive_timer(shift);
+		self.sawtooth.drive_timer(shift);
+	}
+
+	// 15 is as loud as APU pulse at volume 15
+	fn output(&self) -> f32 {
+		let output = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+		output as f32 * PULSE_FULL_VOLUME_LEVEL / 15.0
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		self.pulse1.save_state(writer);
+		self.pulse2.save_state(writer);
+		self.sawtooth.save_state(writer);
+		writer.write_register_u8(&self.frequency_register);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		self.pulse1.load_state(reader)?;
+		self.pulse2.load_state(reader)?;
+		self.sawtooth.load_state(reader)?;
+		reader.read_register_u8(&mut self.frequency_register)
+	}
+}
+
+// Period in the registers 1 and 2, and enable bit 7 of the register 2
+fn vrc6_period(registers: &[Register<u8>; 3]) -> u16 {
+	((registers[2].load_bits(0, 4) as u16) << 8) | registers[1].load() as u16
+}
+
+/**
+ * VRC6 pulse. 16 steps duty cycle without sweep, length counter
+ * and envelope.
+ *   register 0: bit 0-3: volume, bit 4-6: duty, bit 7: constant output
+ *   register 1: period low
+ *   register 2: bit 0-3: period high, bit 7: enable
+ */
+struct VRC6Pulse {
+	registers: [Register<u8>; 3],
+	timer: u16,
+	step: u8
+}
+
+impl VRC6Pulse {
+	fn new() -> Self {
+		VRC6Pulse {
+			registers: [Register::<u8>::new(), Register::<u8>::new(), Register::<u8>::new()],
+			timer: 0,
+			step: 15
+		}
+	}
+
+	fn store_register(&mut self, index: usize, value: u8) {
+		self.registers[index].store(value);
+		// Disabling resets the duty cycle
+		if index == 2 && !self.registers[2].is_bit_set(7) {
+			self.step = 15;
+		}
+	}
+
+	fn drive_timer(&mut self, shift: u8) {
+		if !self.registers[2].is_bit_set(7) {
+			return;
+		}
+		match self.timer {
+			0 => {
+				self.timer = vrc6_period(&self.registers) >> shift;
+				self.step = self.step.wrapping_sub(1) & 0xF;
+			},
+			_ => self.timer -= 1
+		};
+	}
+
+	fn output(&self) -> u8 {
+		if !self.registers[2].is_bit_set(7) {
+			return 0;
+		}
+		let volume = self.registers[0].load_bits(0, 4);
+		match self.registers[0].is_bit_set(7) || self.step <= self.registers[0].load_bits(4, 3) {
+			true => volume,
+			false => 0
+		}
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		for register in self.registers.iter() {
+			writer.write_register_u8(register);
+		}
+		writer.write_u16(self.timer);
+		writer.write_u8(self.step);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		for register in self.registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		self.timer = reader.read_u16()?;
+		self.step = reader.read_u8()?;
+		if self.step > 15 {
+			return Err(StateError::InvalidData);
+		}
+		Ok(())
+	}
+}
+
+/**
+ * VRC6 sawtooth. The accumulator adds the rate every other timer
+ * clock and is reset at the 7th addition, 14 timer clocks.
+ * The output is the upper 5 bits of the accumulator.
+ *   register 0: bit 0-5: accumulator rate
+ *   register 1: period low
+ *   register 2: bit 0-3: period high, bit 7: enable
+ */
+struct VRC6Sawtooth {
+	registers: [Register<u8>; 3],
+	timer: u16,
+	step: u8,
+	accumulator: u8
+}
+
+impl VRC6Sawtooth {
+	fn new() -> Self {
+		VRC6Sawtooth {
+			registers: [Register::<u8>::new(), Register::<u8>::new(), Register::<u8>::new()],
+			timer: 0,
+			step: 0,
+			accumulator: 0
+		}
+	}
+
+	fn store_register(&mut self, index: usize, value: u8) {
+		self.registers[index].store(value);
+		if index == 2 && !self.registers[2].is_bit_set(7) {
+			self.step = 0;
+			self.accumulator = 0;
+		}
+	}
+
+	fn drive_timer(&mut self, shift: u8) {
+		if !self.registers[2].is_bit_set(7) {
+			return;
+		}
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = vrc6_period(&self.registers) >> shift;
+		self.step += 1;
+		if self.step == 14 {
+			self.step = 0;
+			self.accumulator = 0;
+		} else if (self.step % 2) == 0 {
+			self.accumulator = self.accumulator.wrapping_add(self.registers[0].load_bits(0, 6));
+		}
+	}
+
+	fn output(&self) -> u8 {
+		match self.registers[2].is_bit_set(7) {
+			true => self.accumulator >> 3,
+			false => 0
+		}
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		for register in self.registers.iter() {
+			writer.write_register_u8(register);
+		}
+		writer.write_u16(self.timer);
+		writer.write_u8(self.step);
+		writer.write_u8(self.accumulator);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		for register in self.registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		self.timer = reader.read_u16()?;
+		self.step = reader.read_u8()?;
+		if self.step > 13 {
+			return Err(StateError::InvalidData);
+		}
+		self.accumulator = reader.read_u8()?;
+		Ok(())
+	}
+}
+
+/**
+ * Konami VRC2 and VRC4. The variants are wired to the different CPU
+ * address lines selecting the registers in each 0x1000 range.
+ *   mapper 21: VRC4a (A1, A2), VRC4c (A6, A7)
+ *   mapper 22: VRC2a (A1, A0)
+ *   mapper 23: VRC4f (A0, A1), VRC4e (A2, A3), VRC2b (A0, A1)
+ *   mapper 25: VRC4b (A1, A0), VRC4d (A3, A2), VRC2c (A1, A0)
+ * NES 2.0 submapper selects the variant. Otherwise, the both pairs of
+ * the lines are OR'ed, which works for the most of games because they
+ * leave the other lines zero, and VRC4 is assumed for 23 and 25.
+ * The registers are
+ *   0x8000 - 0x8003: 8KB PRG-ROM bank at 0x8000, or 0xC000 in swap mode
+ *   0x9000 - 0x9001: Mirroring, 1 bit on VRC2 and 2 bits on VRC4
+ *   0x9002 - 0x9003: VRC4 bit 1: PRG swap mode
+ *   0xA000 - 0xA003: 8KB PRG-ROM bank at 0xA000
+ *   0xB000 - 0xE003: Lower and upper 4 bits of 1KB CHR-ROM banks
+ *   0xF000 - 0xF003: VRC4 IRQ latch lower/upper 4 bits, control, acknowledgement
+ * VRC2a ignores bit 0 of the CHR banks. The rest of PRG-ROM is fixed
+ * to the second last and last banks. VRC2 boards without PRG-RAM have
+ * a 1-bit latch at 0x6000 - 0x6FFF for the microwire EEPROM interface
+ * which games use as a copy protection check.
+ */
+struct VRC4Mapper {
+	program_bank_num: u16,
+	vrc2: bool,
+	// Address lines selecting bit 0 and 1 of the register index
+	register_lines: [u32; 2],
+	chr_bank_shift: u8,
+	program_register0: Register<u8>,
+	program_register1: Register<u8>,
+	mirroring_register: Register<u8>,
+	swap_register: Register<u8>,
+	character_registers: [Register<u16>; 8],
+	microwire_latch: Register<u8>,
+	irq: VrcIrq
+}
+
+impl VRC4Mapper {
+	fn new(header: &RomHeader) -> Self {
+		let (vrc2, register_lines) = match (header.mapper_num(), header.submapper_num()) {
+			(21, 1) => (false, [0x02, 0x04]),
+			(21, 2) => (false, [0x40, 0x80]),
+			(21, _) => (false, [0x42, 0x84]),
+			(22, _) => (true, [0x02, 0x01]),
+			(23, 1) => (false, [0x01, 0x02]),
+			(23, 2) => (false, [0x04, 0x08]),
+			(23, 3) => (true, [0x01, 0x02]),
+			(23, _) => (false, [0x05, 0x0A]),
+			(25, 1) => (false, [0x02, 0x01]),
+			(25, 2) => (false, [0x08, 0x04]),
+			(25, 3) => (true, [0x02, 0x01]),
+			(_, _) => (false, [0x0A, 0x05])
+		};
+		VRC4Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			vrc2: vrc2,
+			register_lines: register_lines,
+			chr_bank_shift: match header.mapper_num() {
+				22 => 1,
+				_ => 0
+			},
+			program_register0: Register::<u8>::new(),
+			program_register1: Register::<u8>::new(),
+			mirroring_register: Register::<u8>::new(),
+			swap_register: Register::<u8>::new(),
+			character_registers: [
+				Register::<u16>::new(),
+				Register::<u16>::new(),
+				Register::<u16>::new(),
+				Register::<u16>::new(),
+				Register::<u16>::new(),
+				Register::<u16>::new(),
+				Register::<u16>::new(),
+				Register::<u16>::new()
+			],
+			microwire_latch: Register::<u8>::new(),
+			irq: VrcIrq::new()
+		}
+	}
+
+	// Converts to 0xX000 - 0xX003 register address
+	fn register_address(&self, address: u32) -> u32 {
+		let mut index = 0;
+		if (address & self.register_lines[0]) != 0 {
+			index |= 1;
+		}
+		if (address & self.register_lines[1]) != 0 {
+			index |= 2;
+		}
+		(address & 0xF000) | index
+	}
+
+	fn store_chr_bank(&mut self, address: u32, value: u8) {
+		// Two registers for each bank, lower and upper 4 bits
+		let index = (((address - 0xB000) >> 12) * 2 + ((address & 2) >> 1)) as usize;
+		let register = &mut self.character_registers[index];
+		match address & 1 {
+			0 => register.store_bits(0, 4, value as u16),
+			_ => match self.vrc2 {
+				true => register.store_bits(4, 4, value as u16),
+				false => register.store_bits(4, 5, value as u16)
+			}
+		};
+	}
+
+	// Whether VRC2 board has the microwire latch instead of PRG-RAM
+	fn has_microwire_latch(&self, cartridge: &Cartridge) -> bool {
+		self.vrc2 && cartridge.prg_ram_size() == 0
+	}
+}
+
+impl Mapper for VRC4Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let bank_num = self.program_bank_num as u32 * 2;
+		let swap_mode = !self.vrc2 && self.swap_register.is_bit_set(1);
+		let bank = match address {
+			0x8000..=0x9FFF => match swap_mode {
+				true => bank_num - 2,
+				false => self.program_register0.load_bits(0, 5) as u32
+			},
+			0xA000..=0xBFFF => self.program_register1.load_bits(0, 5) as u32,
+			0xC000..=0xDFFF => match swap_mode {
+				true => self.program_register0.load_bits(0, 5) as u32,
+				false => bank_num - 2
+			},
+			_ => bank_num - 1
+		};
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let register = &self.character_registers[(address >> 10) as usize];
+		((register.load() >> self.chr_bank_shift) as u32) * 0x400 + (address & 0x3FF)
+	}
+
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x6000..=0x6FFF if self.has_microwire_latch(cartridge) => self.microwire_latch.load_bits(0, 1),
+			0x6000..=0x7FFF => cartridge.load_prg_ram(address - 0x6000),
+			0x8000..=0xFFFF => cartridge.load_prg_rom(self.map(address)),
+			_ => 0
+		}
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x6000..=0x6FFF if self.has_microwire_latch(cartridge) => self.microwire_latch.store(value),
+			0x6000..=0x7FFF => cartridge.store_prg_ram(address - 0x6000, value),
+			0x8000..=0xFFFF => self.store(address, value),
+			_ => {}
+		};
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		let address = self.register_address(address);
+		match address {
+			0x8000..=0x8003 => self.program_register0.store(value),
+			0x9000..=0x9001 => self.mirroring_register.store(value),
+			0x9002..=0x9003 => self.swap_register.store(value),
+			0xA000..=0xA003 => self.program_register1.store(value),
+			0xB000..=0xE003 => self.store_chr_bank(address, value),
+			_ if self.vrc2 => {},
+			0xF000 => self.irq.store_latch_low(value),
+			0xF001 => self.irq.store_latch_high(value),
+			0xF002 => self.irq.store_control(value),
+			0xF003 => self.irq.acknowledge(),
+			_ => {}
+		};
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		let mirroring = match self.vrc2 {
+			true => self.mirroring_register.load_bits(0, 1),
+			false => self.mirroring_register.load_bits(0, 2)
+		};
+		Some(match mirroring {
+			0 => VERTICAL_MIRRORING,
+			1 => HORIZONTAL_MIRRORING,
+			2 => SINGLE_SCREEN_A,
+			_ => SINGLE_SCREEN_B
+		})
+	}
+
+	fn step(&mut self) {
+		if !self.vrc2 {
+			self.irq.step();
+		}
+	}
+
+	fn irq_line(&self) -> bool {
+		self.irq.irq_line()
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.program_register0);
+		writer.write_register_u8(&self.program_register1);
+		writer.write_register_u8(&self.mirroring_register);
+		writer.write_register_u8(&self.swap_register);
+		for register in self.character_registers.iter() {
+			writer.write_register_u16(register);
+		}
+		writer.write_register_u8(&self.microwire_latch);
+		self.irq.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.program_register0)?;
+		reader.read_register_u8(&mut self.program_register1)?;
+		reader.read_register_u8(&mut self.mirroring_register)?;
+		reader.read_register_u8(&mut self.swap_register)?;
+		for register in self.character_registers.iter_mut() {
+			reader.read_register_u16(register)?;
+		}
+		reader.read_register_u8(&mut self.microwire_latch)?;
+		self.irq.load_state(reader)
+	}
+}
+
+/**
+ * Sunsoft FME-7 and 5B, FME-7 with the audio. Commands are written
+ * to 0x8000 - 0x9FFF and their parameters to 0xA000 - 0xBFFF.
+ *   0x0 - 0x7: 1KB CHR-ROM banks
+ *   0x8: 8KB bank at 0x6000, bit 0-5: bank, bit 6: 0: ROM, 1: RAM,
+ *        bit 7: RAM enable
+ *   0x9 - 0xB: 8KB PRG-ROM banks at 0x8000, 0xA000 and 0xC000
+ *   0xC: Mirroring, 0: vertical, 1: horizontal, 2: single A, 3: single B
+ *   0xD: IRQ control, bit 0: IRQ enable, bit 7: counter enable.
+ *        Writes acknowledge IRQ.
+ *   0xE - 0xF: Lower and upper bytes of IRQ counter
+ * 0xE000 - 0xFFFF is fixed to the last bank. The 16-bit IRQ counter
+ * decrements every CPU cycle and fires IRQ when it wraps to 0xFFFF.
+ * 0xC000 - 0xFFFF writes go to 5B audio.
+ */
+struct FME7Mapper {
+	program_bank_num: u16,
+	command_register: Register<u8>,
+	parameter_registers: [Register<u8>; 16],
+	irq_counter: u16,
+	irq_pending: bool,
+	audio: Sunsoft5BAudio
+}
+
+impl FME7Mapper {
+	fn new(header: &RomHeader) -> Self {
+		FME7Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			command_register: Register::<u8>::new(),
+			parameter_registers: [
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new()
+			],
+			irq_counter: 0,
+			irq_pending: false,
+			audio: Sunsoft5BAudio::new()
+		}
+	}
+
+	fn parameter(&self, command: usize) -> &Register<u8> {
+		&self.parameter_registers[command]
+	}
+
+	fn prg_ram_selected(&self) -> bool {
+		self.parameter(8).is_bit_set(6)
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.parameter(8).is_bit_set(7)
+	}
+
+	fn map_prg_ram(&self, address: u32) -> u32 {
+		self.parameter(8).load_bits(0, 6) as u32 * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store_parameter(&mut self, value: u8) {
+		let command = self.command_register.load_bits(0, 4);
+		self.parameter_registers[command as usize].store(value);
+		match command {
+			0xD => self.irq_pending = false,
+			0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+			0xF => self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16) << 8),
+			_ => {}
+		};
+	}
+}
+
+impl Mapper for FME7Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address {
+			0x6000..=0x7FFF => self.parameter(8).load_bits(0, 6) as u32,
+			0x8000..=0x9FFF => self.parameter(9).load_bits(0, 6) as u32,
+			0xA000..=0xBFFF => self.parameter(0xA).load_bits(0, 6) as u32,
+			0xC000..=0xDFFF => self.parameter(0xB).load_bits(0, 6) as u32,
+			_ => self.program_bank_num as u32 * 2 - 1
+		};
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.parameter((address >> 10) as usize).load() as u32 * 0x400 + (address & 0x3FF)
+	}
+
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x6000..=0x7FFF => match self.prg_ram_selected() {
+				true => match self.prg_ram_enabled() {
+					true => cartridge.load_prg_ram(self.map_prg_ram(address)),
+					false => 0
+				},
+				false => cartridge.load_prg_rom(self.map(address))
+			},
+			0x8000..=0xFFFF => cartridge.load_prg_rom(self.map(address)),
+			_ => 0
+		}
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x6000..=0x7FFF => {
+				if self.prg_ram_selected() && self.prg_ram_enabled() {
+					cartridge.store_prg_ram(self.map_prg_ram(address), value);
+				}
+			},
+			0x8000..=0xFFFF => self.store(address, value),
+			_ => {}
+		};
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0x8000..=0x9FFF => self.command_register.store(value),
+			0xA000..=0xBFFF => self.store_parameter(value),
+			_ => self.audio.store(address, value)
+		};
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		Some(match self.parameter(0xC).load_bits(0, 2) {
+			0 => VERTICAL_MIRRORING,
+			1 => HORIZONTAL_MIRRORING,
+			2 => SINGLE_SCREEN_A,
+			_ => SINGLE_SCREEN_B
+		})
+	}
+
+	fn step(&mut self) {
+		if self.parameter(0xD).is_bit_set(7) {
+			self.irq_counter = self.irq_counter.wrapping_sub(1);
+			if self.irq_counter == 0xFFFF && self.parameter(0xD).is_bit_set(0) {
+				self.irq_pending = true;
+			}
+		}
+		self.audio.step();
+	}
+
+	fn irq_line(&self) -> bool {
+		self.irq_pending
+	}
+
+	fn audio_output(&self) -> f32 {
+		self.audio.output()
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.command_register);
+		for register in self.parameter_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		writer.write_u16(self.irq_counter);
+		writer.write_bool(self.irq_pending);
+		self.audio.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.command_register)?;
+		for register in self.parameter_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		self.irq_counter = reader.read_u16()?;
+		self.irq_pending = reader.read_bool()?;
+		self.audio.load_state(reader)
+	}
+}
+
+/**
+ * Sunsoft 5B audio, a variant of YM2149F (AY-3-8910 compatible).
+ * The register number is written to 0xC000 - 0xDFFF and the value
+ * to 0xE000 - 0xFFFF.
+ *   0x0 - 0x5: 12-bit tone periods of channel A, B and C
+ *   0x6: 5-bit noise period
+ *   0x7: bit 0-2: tone disable, bit 3-5: noise disable of A, B and C
+ *   0x8 - 0xA: Channel A, B and C, bit 0-3: volume, bit 4: envelope
+ *   0xB - 0xC: 16-bit envelope period
+ *   0xD: Envelope shape, bit 0: hold, bit 1: alternate, bit 2: attack,
+ *        bit 3: continue. Writes restart the envelope.
+ * The chip runs at CPU clock / 2 and the internal divider / 8 more,
+ * so the tones toggle every (period * 16) CPU cycles and the 32-step
+ * envelope steps every (period * 16) CPU cycles too. The volumes are
+ * logarithmic, 3dB per volume step and 1.5dB per envelope step.
+ * Refer to https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+ */
+struct Sunsoft5BAudio {
+	register_select: Register<u8>,
+	registers: [Register<u8>; 16],
+	divider: u8,
+	tone_counters: [u16; 3],
+	tone_outputs: [bool; 3],
+	noise_counter: u8,
+	// 17-bit LFSR
+	noise_shift: u32,
+	envelope_counter: u16,
+	envelope_level: u8,
+	envelope_rising: bool,
+	envelope_holding: bool,
+	volume_table: [f32; 32]
+}
+
+impl Sunsoft5BAudio {
+	fn new() -> Self {
+		// 31 is as loud as APU pulse at volume 15, 1.5dB down per level
+		let mut volume_table = [0.0; 32];
+		for level in 1..32 {
+			let decibel = (31 - level) as f32 * -1.5;
+			volume_table[level] = PULSE_FULL_VOLUME_LEVEL * 10.0_f32.powf(decibel / 20.0);
+		}
+		Sunsoft5BAudio {
+			register_select: Register::<u8>::new(),
+			registers: [
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new()
+			],
+			divider: 0,
+			tone_counters: [0; 3],
+			tone_outputs: [false; 3],
+			noise_counter: 0,
+			noise_shift: 1,
+			envelope_counter: 0,
+			envelope_level: 0,
+			envelope_rising: false,
+			envelope_holding: false,
+			volume_table: volume_table
+		}
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0xC000..=0xDFFF => self.register_select.store(value),
+			_ /* 0xE000 - 0xFFFF */ => {
+				let index = self.register_select.load();
+				// The upper bits must be 0
+				if index > 0xF {
+					return;
+				}
+				self.registers[index as usize].store(value);
+				if index == 0xD {
+					self.restart_envelope();
+				}
+			}
+		};
+	}
+
+	fn tone_period(&self, channel: usize) -> u16 {
+		let lower = self.registers[channel * 2].load() as u16;
+		let upper = self.registers[channel * 2 + 1].load_bits(0, 4) as u16;
+		(upper << 8) | lower
+	}
+
+	fn envelope_period(&self) -> u16 {
+		((self.registers[0xC].load() as u16) << 8) | self.registers[0xB].load() as u16
+	}
+
+	fn restart_envelope(&mut self) {
+		self.envelope_rising = self.registers[0xD].is_bit_set(2);
+		self.envelope_level = match self.envelope_rising {
+			true => 0,
+			false => 31
+		};
+		self.envelope_holding = false;
+		self.envelope_counter = 0;
+	}
+
+	fn step(&mut self) {
+		self.divider += 1;
+		if self.divider < 16 {
+			return;
+		}
+		self.divider = 0;
+
+		for channel in 0..3 {
+			self.tone_counters[channel] += 1;
+			if self.tone_counters[channel] >= self.tone_period(channel) {
+				self.tone_counters[channel] = 0;
+				self.tone_outputs[channel] = !self.tone_outputs[channel];
+			}
+		}
+
+		// Noise runs at the half rate of the tones
+		self.noise_counter += 1;
+		if self.noise_counter >= self.registers[6].load_bits(0, 5) * 2 {
+			self.noise_counter = 0;
+			let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+			self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+		}
+
+		self.envelope_counter += 1;
+		if self.envelope_counter >= self.envelope_period() {
+			self.envelope_counter = 0;
+			self.drive_envelope();
+		}
+	}
+
+	fn drive_envelope(&mut self) {
+		if self.envelope_holding {
+			return;
+		}
+		let at_end = match self.envelope_rising {
+			true => self.envelope_level == 31,
+			false => self.envelope_level == 0
+		};
+		if !at_end {
+			match self.envelope_rising {
+				true => self.envelope_level += 1,
+				false => self.envelope_level -= 1
+			};
+			return;
+		}
+		let shape = self.registers[0xD].load();
+		let (hold, alternate, proceed) = ((shape & 1) != 0, (shape & 2) != 0, (shape & 8) != 0);
+		if !proceed {
+			// One cycle and stays silent
+			self.envelope_level = 0;
+			self.envelope_holding = true;
+		} else if hold {
+			if alternate {
+				self.envelope_level = 31 - self.envelope_level;
+			}
+			self.envelope_holding = true;
+		} else if alternate {
+			// Triangle. The endpoint is held for this step so that
+			// a cycle is 64 steps
+			self.envelope_rising = !self.envelope_rising;
+		} else {
+			// Sawtooth
+			self.envelope_level = match self.envelope_rising {
+				true => 0,
+				false => 31
+			};
+		}
+	}
+
+	// 5-bit level
+	fn channel_level(&self, channel: usize) -> u8 {
+		let tone_disabled = self.registers[7].is_bit_set(channel as u8);
+		let noise_disabled = self.registers[7].is_bit_set(channel as u8 + 3);
+		let tone = tone_disabled || self.tone_outputs[channel];
+		let noise = noise_disabled || (self.noise_shift & 1) == 1;
+		if !tone || !noise {
+			return 0;
+		}
+		let volume = &self.registers[8 + channel];
+		match volume.is_bit_set(4) {
+			true => self.envelope_level,
+			false => match volume.load_bits(0, 4) {
+				0 => 0,
+				v => v * 2 + 1
+			}
+		}
+	}
+
+	fn output(&self) -> f32 {
+		let mut output = 0.0;
+		for channel in 0..3 {
+			output += self.volume_table[self.channel_level(channel) as usize];
+		}
+		output
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_register_u8(&self.register_select);
+		for register in self.registers.iter() {
+			writer.write_register_u8(register);
+		}
+		writer.write_u8(self.divider);
+		for channel in 0..3 {
+			writer.write_u16(self.tone_counters[channel]);
+			writer.write_bool(self.tone_outputs[channel]);
+		}
+		writer.write_u8(self.noise_counter);
+		writer.write_u32(self.noise_shift);
+		writer.write_u16(self.envelope_counter);
+		writer.write_u8(self.envelope_level);
+		writer.write_bool(self.envelope_rising);
+		writer.write_bool(self.envelope_holding);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		reader.read_register_u8(&mut self.register_select)?;
+		for register in self.registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		self.divider = reader.read_u8()?;
+		if self.divider > 15 {
+			return Err(StateError::InvalidData);
+		}
+		for channel in 0..3 {
+			self.tone_counters[channel] = reader.read_u16()?;
+			self.tone_outputs[channel] = reader.read_bool()?;
+		}
+		self.noise_counter = reader.read_u8()?;
+		self.noise_shift = reader.read_u32()?;
+		self.envelope_counter = reader.read_u16()?;
+		self.envelope_level = reader.read_u8()?;
+		if self.envelope_level > 31 {
+			return Err(StateError::InvalidData);
+		}
+		self.envelope_rising = reader.read_bool()?;
+		self.envelope_holding = reader.read_bool()?;
+		Ok(())
+	}
+}
+
+/**
+ * Namco 163. The registers are
+ *   0x4800 - 0x4FFF: Internal RAM data port
+ *   0x5000 - 0x57FF: Lower 8 bits of IRQ counter
+ *   0x5800 - 0x5FFF: bit 0-6: upper 7 bits of IRQ counter, bit 7: IRQ enable.
+ *                    Writes acknowledge IRQ.
+ *   0x8000 - 0xBFFF: 1KB CHR banks at 0x0000 - 0x1C00 every 0x800
+ *   0xC000 - 0xDFFF: 1KB nametable banks of the quadrants 0 - 3 every 0x800
+ *   0xE000 - 0xE7FF: bit 0-5: 8KB PRG-ROM bank at 0x8000, bit 6: sound disable
+ *   0xE800 - 0xEFFF: bit 0-5: 8KB PRG-ROM bank at 0xA000,
+ *                    bit 6-7: CHR-ROM instead of CIRAM at 0x0000/0x1000
+ *   0xF000 - 0xF7FF: 8KB PRG-ROM bank at 0xC000
+ *   0xF800 - 0xFFFF: bit 0-6: internal RAM address, bit 7: auto increment.
+ *                    Also PRG-RAM write protection, 0x4X enables writes and
+ *                    bit 0-3 protect each 2KB.
+ * 0xE000 - 0xFFFF is fixed to the last bank. CHR banks 0xE0 - 0xFF select
+ * CIRAM page (bank & 1) unless bit 6/7 of 0xE800 is set, and so do
+ * the nametable banks always. The other nametable banks are CHR-ROM.
+ * The 15-bit IRQ counter counts up every CPU cycle and fires IRQ
+ * at 0x7FFF where it stops.
+ * The 128 bytes internal RAM is the wavetable and the channel registers
+ * of the audio, and is kept by the battery if the board has it.
+ */
+struct Namco163Mapper {
+	program_bank_num: u16,
+	character_registers: [Register<u8>; 8],
+	nametable_registers: [Register<u8>; 4],
+	program_registers: [Register<u8>; 3],
+	// 0xF800
+	address_register: Register<u8>,
+	irq_counter: Register<u16>,
+	irq_pending: bool,
+	internal_ram: Memory,
+	audio: Namco163Audio
+}
+
+impl Namco163Mapper {
+	fn new(header: &RomHeader) -> Self {
+		Namco163Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			character_registers: [
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new()
+			],
+			nametable_registers: [
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new()
+			],
+			program_registers: [Register::<u8>::new(), Register::<u8>::new(), Register::<u8>::new()],
+			address_register: Register::<u8>::new(),
+			irq_counter: Register::<u16>::new(),
+			irq_pending: false,
+			internal_ram: Memory::new(vec![0; 0x80]),
+			audio: Namco163Audio::new()
+		}
+	}
+
+	fn access_internal_ram(&mut self) -> u32 {
+		let address = self.address_register.load_bits(0, 7);
+		if self.address_register.is_bit_set(7) {
+			self.address_register.store_bits(0, 7, address.wrapping_add(1));
+		}
+		address as u32
+	}
+
+	fn prg_ram_writable(&self, address: u32) -> bool {
+		let protection = self.address_register.load();
+		(protection & 0xF0) == 0x40 && (protection & (1 << ((address - 0x6000) >> 11))) == 0
+	}
+
+	// CHR-ROM bank, or None if mapped to CIRAM
+	fn chr_bank(&self, address: u32) -> Option<u32> {
+		let bank = self.character_registers[(address >> 10) as usize].load();
+		let uses_chr_rom = match address < 0x1000 {
+			true => self.program_registers[1].is_bit_set(6),
+			false => self.program_registers[1].is_bit_set(7)
+		};
+		match bank >= 0xE0 && !uses_chr_rom {
+			true => None,
+			false => Some(bank as u32)
+		}
+	}
+}
+
+impl Mapper for Namco163Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address {
+			0x8000..=0x9FFF => self.program_registers[0].load_bits(0, 6) as u32,
+			0xA000..=0xBFFF => self.program_registers[1].load_bits(0, 6) as u32,
+			0xC000..=0xDFFF => self.program_registers[2].load_bits(0, 6) as u32,
+			_ => self.program_bank_num as u32 * 2 - 1
+		};
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.chr_bank(address).unwrap_or(0) * 0x400 + (address & 0x3FF)
+	}
+
+	fn load_prg(&mut self, cartridge: &Cartridge, address: u32) -> u8 {
+		match address {
+			0x4800..=0x4FFF => {
+				let address = self.access_internal_ram();
+				self.internal_ram.load(address)
+			},
+			0x5000..=0x57FF => self.irq_counter.load_bits(0, 8) as u8,
+			0x5800..=0x5FFF => (self.irq_counter.load() >> 8) as u8,
+			0x6000..=0x7FFF => cartridge.load_prg_ram(address - 0x6000),
+			0x8000..=0xFFFF => cartridge.load_prg_rom(self.map(address)),
+			_ => 0
+		}
+	}
+
+	fn store_prg(&mut self, cartridge: &mut Cartridge, address: u32, value: u8) {
+		match address {
+			0x4800..=0x4FFF => {
+				let address = self.access_internal_ram();
+				self.internal_ram.store(address, value);
+			},
+			0x5000..=0x57FF => {
+				self.irq_counter.store_lower_byte(value);
+				self.irq_pending = false;
+			},
+			0x5800..=0x5FFF => {
+				self.irq_counter.store_higher_byte(value);
+				self.irq_pending = false;
+			},
+			0x6000..=0x7FFF => {
+				if self.prg_ram_writable(address) {
+					cartridge.store_prg_ram(address - 0x6000, value);
+				}
+			},
+			0x8000..=0xFFFF => self.store(address, value),
+			_ => {}
+		};
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		let index = ((address >> 11) & 3) as usize;
+		match address {
+			0x8000..=0xBFFF => self.character_registers[((address - 0x8000) >> 11) as usize].store(value),
+			0xC000..=0xDFFF => self.nametable_registers[index].store(value),
+			0xE000..=0xF7FF => self.program_registers[index].store(value),
+			_ /* 0xF800 - 0xFFFF */ => self.address_register.store(value)
+		};
+	}
+
+	fn pattern_ciram_page(&self, address: u16) -> Option<u8> {
+		match self.chr_bank(address as u32) {
+			Some(_) => None,
+			None => Some(self.character_registers[(address >> 10) as usize].load() & 1)
+		}
+	}
+
+	fn nametable_mapping(&self) -> Option<NametableMapping> {
+		let nametable = |register: &Register<u8>| {
+			match register.load() {
+				bank @ 0xE0..=0xFF => Nametable::Ciram(bank & 1),
+				bank => Nametable::ChrRom(bank as u16)
+			}
+		};
+		Some([
+			nametable(&self.nametable_registers[0]),
+			nametable(&self.nametable_registers[1]),
+			nametable(&self.nametable_registers[2]),
+			nametable(&self.nametable_registers[3])
+		])
+	}
+
+	fn step(&mut self) {
+		if self.irq_counter.is_bit_set(15) && self.irq_counter.load_bits(0, 15) != 0x7FFF {
+			self.irq_counter.increment();
+			if self.irq_counter.load_bits(0, 15) == 0x7FFF {
+				self.irq_pending = true;
+			}
+		}
+		if !self.program_registers[0].is_bit_set(6) {
+			self.audio.step(&mut self.internal_ram);
+		}
+	}
+
+	fn irq_line(&self) -> bool {
+		self.irq_pending
+	}
+
+	fn audio_output(&self) -> f32 {
+		match self.program_registers[0].is_bit_set(6) {
+			true => 0.0,
+			false => self.audio.output(&self.internal_ram)
+		}
+	}
+
+	fn set_audio_multiplexing(&mut self, multiplexing: bool) {
+		self.audio.multiplexing = multiplexing;
+	}
+
+	fn battery_backed_chip_ram(&self) -> Option<&Memory> {
+		Some(&self.internal_ram)
+	}
+
+	fn battery_backed_chip_ram_mut(&mut self) -> Option<&mut Memory> {
+		Some(&mut self.internal_ram)
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		for register in self.character_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		for register in self.nametable_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		for register in self.program_registers.iter() {
+			writer.write_register_u8(register);
+		}
+		writer.write_register_u8(&self.address_register);
+		writer.write_register_u16(&self.irq_counter);
+		writer.write_bool(self.irq_pending);
+		writer.write_memory(&self.internal_ram);
+		self.audio.save_state(writer);
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		for register in self.character_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		for register in self.nametable_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		for register in self.program_registers.iter_mut() {
+			reader.read_register_u8(register)?;
+		}
+		reader.read_register_u8(&mut self.address_register)?;
+		reader.read_register_u16(&mut self.irq_counter)?;
+		self.irq_pending = reader.read_bool()?;
+		reader.read_memory(&mut self.internal_ram)?;
+		self.audio.load_state(reader)
+	}
+}
+
+/**
+ * Namco 163 wavetable audio. Up to eight channels whose registers are
+ * in the internal RAM 0x40 - 0x7F, 8 bytes for each channel.
+ *   +0, +2, +4 bit 0-1: 18-bit frequency
+ *   +1, +3, +5: 24-bit phase
+ *   +4 bit 2-7: Wave length, 256 - (value & 0xFC) samples
+ *   +6: Wave address in 4-bit samples
+ *   +7 bit 0-3: Volume. bit 4-6 of 0x7F: enabled channels - 1
+ * The enabled channels are the last ones, channel 7 to (8 - enabled).
+ * The waves are 4-bit samples, the lower nibble first.
+ * The chip updates a channel every 15 CPU cycles in turn and outputs
+ * only the channel being updated, so more channels lower the sample
+ * rate and the output aliases. Mixing mode outputs the average of
+ * the channels instead, the same level as multiplexing on average.
+ */
+struct Namco163Audio {
+	multiplexing: bool,
+	cycle: u8,
+	channel: u8,
+	channel_outputs: [i16; 8]
+}
+
+impl Namco163Audio {
+	fn new() -> Self {
+		Namco163Audio {
+			multiplexing: true,
+			cycle: 0,
+			channel: 7,
+			channel_outputs: [0; 8]
+		}
+	}
+
+	fn enabled_channel_num(ram: &Memory) -> u8 {
+		((ram.load(0x7F) >> 4) & 7) + 1
+	}
+
+	fn step(&mut self, ram: &mut Memory) {
+		self.cycle += 1;
+		if self.cycle < 15 {
+			return;
+		}
+		self.cycle = 0;
+		self.channel = match self.channel <= 8 - Namco163Audio::enabled_channel_num(ram) {
+			true => 7,
+			false => self.channel - 1
+		};
+		self.update_channel(ram);
+	}
+
+	fn update_channel(&mut self, ram: &mut Memory) {
+		let base = 0x40 + self.channel as u32 * 8;
+		let load_24bits = |offsets: [u32; 3]| {
+			(ram.load(base + offsets[0]) as u32) |
+				((ram.load(base + offsets[1]) as u32) << 8) |
+				((ram.load(base + offsets[2]) as u32) << 16)
+		};
+		let frequency = load_24bits([0, 2, 4]) & 0x3FFFF;
+		let phase = load_24bits([1, 3, 5]);
+		let length = 256 - (ram.load(base + 4) & 0xFC) as u32;
+
+		let phase = (phase + frequency) % (length << 16);
+		ram.store(base + 1, phase as u8);
+		ram.store(base + 3, (phase >> 8) as u8);
+		ram.store(base + 5, (phase >> 16) as u8);
+
+		let sample_address = ((phase >> 16) + ram.load(base + 6) as u32) & 0xFF;
+		let sample = match sample_address & 1 {
+			0 => ram.load(sample_address >> 1) & 0xF,
+			_ => ram.load(sample_address >> 1) >> 4
+		};
+		let volume = ram.load(base + 7) & 0xF;
+		self.channel_outputs[self.channel as usize] = (sample as i16 - 8) * volume as i16;
+	}
+
+	// Centered, -120 to 105 for a channel. -120 is as loud as
+	// APU pulse at volume 15
+	fn output(&self, ram: &Memory) -> f32 {
+		let output = match self.multiplexing {
+			true => self.channel_outputs[self.channel as usize] as f32,
+			false => {
+				let channel_num = Namco163Audio::enabled_channel_num(ram);
+				let sum: i16 = self.channel_outputs[(8 - channel_num as usize)..].iter().sum();
+				sum as f32 / channel_num as f32
+			}
+		};
+		output * PULSE_FULL_VOLUME_LEVEL / 120.0
+	}
+
+	fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_u8(self.cycle);
+		writer.write_u8(self.channel);
+		for output in self.channel_outputs.iter() {
+			writer.write_u16(*output as u16);
+		}
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		self.cycle = reader.read_u8()?;
+		if self.cycle > 14 {
+			return Err(StateError::InvalidData);
+		}
+		self.channel = reader.read_u8()?;
+		if self.channel > 7 {
+			return Err(StateError::InvalidData);
+		}
+		for output in self.channel_outputs.iter_mut() {
+			*output = reader.read_u16()? as i16;
+		}
+		Ok(())
+	}
+}
+
+// Cartridge for mapper tests. Each 8KB PRG-ROM bank is filled with its bank number.
+#[cfg(test)]
+fn test_cartridge(prg_rom_bank_num: u8, chr_rom_size: usize, prg_ram_size: usize) -> Cartridge {
+	let mut prg_rom = vec![];
+	for bank in 0..prg_rom_bank_num {
+		prg_rom.resize(prg_rom.len() + 0x2000, bank);
+	}
+	Cartridge::new(prg_rom, vec![0; chr_rom_size], 0, prg_ram_size, 0, 0)
+}
+
+#[cfg(test)]
+mod tests_nrom_mapper {
+	use super::*;
+
+	#[test]
+	fn initialize() {
+		NRomMapper{program_bank_num: 1};
+	}
+
+	#[test]
+	fn map_with_program_bank_num_1() {
+		let m = NRomMapper{program_bank_num: 1};
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x3FFF, m.map(0xBFFF));
+		assert_eq!(0x0000, m.map(0xC000));
+		assert_eq!(0x3FFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_with_program_bank_num_2() {
+		let m = NRomMapper{program_bank_num: 2};
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x3FFF, m.map(0xBFFF));
+		assert_eq!(0x4000, m.map(0xC000));
+		assert_eq!(0x7FFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let m = NRomMapper{program_bank_num: 1};
+		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x1FFF, m.map_for_chr_rom(0x1FFF));
+	}
+}
+
+#[cfg(test)]
+mod tests_axrom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = AxRomMapper{bus_conflict: false, register: Register::<u8>::new()};
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x7FFF, m.map(0xFFFF));
+		m.store(0x8000, 0x03);
+		assert_eq!(0x18000, m.map(0x8000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn nametable_mapping() {
+		let mut m = AxRomMapper{bus_conflict: false, register: Register::<u8>::new()};
+		assert_eq!(Some(SINGLE_SCREEN_A), m.nametable_mapping());
+		m.store(0x8000, 0x10);
+		assert_eq!(Some(SINGLE_SCREEN_B), m.nametable_mapping());
+	}
+
+	#[test]
+	fn bus_conflict() {
+		let mut c = Cartridge::new(vec![0x0F; 0x20000], vec![], 0x2000, 0, 0, 0);
+		let mut m = AxRomMapper{bus_conflict: false, register: Register::<u8>::new()};
+		m.store_prg(&mut c, 0x8000, 0x13);
+		assert_eq!(0x13, m.register.load());
+		let mut m2 = AxRomMapper{bus_conflict: true, register: Register::<u8>::new()};
+		m2.store_prg(&mut c, 0x8000, 0x13);
+		assert_eq!(0x03, m2.register.load());
+	}
+}
+
+#[cfg(test)]
+mod tests_bnrom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = BNRomMapper::new();
+		assert_eq!(0x0000, m.map(0x8000));
+		m.store(0x8000, 0x02);
+		assert_eq!(0x10000, m.map(0x8000));
+		assert_eq!(0x17FFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let m = BNRomMapper::new();
+		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x1FFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn bus_conflict() {
+		let mut c = Cartridge::new(vec![0x01; 0x10000], vec![], 0x2000, 0, 0, 0);
+		let mut m = BNRomMapper::new();
+		m.store_prg(&mut c, 0x8000, 0x03);
+		assert_eq!(0x01, m.register.load());
+	}
+}
+
+#[cfg(test)]
+mod tests_nina001_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut c = Cartridge::new(vec![0; 0x10000], vec![0; 0x10000], 0, 0x2000, 0, 0);
+		let mut m = Nina001Mapper::new();
+		m.store_prg(&mut c, 0x7FFD, 0x01);
+		assert_eq!(0x8000, m.map(0x8000));
+		assert_eq!(0x01, c.load_prg_ram(0x1FFD));
+		// Writes to PRG-ROM area are ignored
+		m.store_prg(&mut c, 0x8000, 0x00);
+		assert_eq!(0x8000, m.map(0x8000));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let mut c = Cartridge::new(vec![0; 0x10000], vec![0; 0x10000], 0, 0x2000, 0, 0);
+		let mut m = Nina001Mapper::new();
+		m.store_prg(&mut c, 0x7FFE, 0x02);
+		m.store_prg(&mut c, 0x7FFF, 0x05);
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x2FFF, m.map_for_chr_rom(0x0FFF));
+		assert_eq!(0x5000, m.map_for_chr_rom(0x1000));
+		assert_eq!(0x5FFF, m.map_for_chr_rom(0x1FFF));
+	}
+}
+
+#[cfg(test)]
+mod tests_gxrom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = GxRomMapper::new();
+		m.store(0x8000, 0x31);
+		assert_eq!(0x18000, m.map(0x8000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x3FFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn bus_conflict() {
+		let mut c = Cartridge::new(vec![0x10; 0x20000], vec![0; 0x8000], 0, 0, 0, 0);
+		let mut m = GxRomMapper::new();
+		m.store_prg(&mut c, 0x8000, 0x33);
+		assert_eq!(0x10, m.register.load());
+	}
+}
+
+#[cfg(test)]
+mod tests_color_dreams_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = ColorDreamsMapper::new();
+		m.store(0x8000, 0x52);
+		assert_eq!(0x10000, m.map(0x8000));
+		assert_eq!(0x17FFF, m.map(0xFFFF));
+		assert_eq!(0xA000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0xBFFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn bus_conflict() {
+		let mut c = Cartridge::new(vec![0xF0; 0x20000], vec![0; 0x20000], 0, 0, 0, 0);
+		let mut m = ColorDreamsMapper::new();
+		m.store_prg(&mut c, 0x8000, 0x52);
+		assert_eq!(0x50, m.register.load());
+	}
+}
+
+#[cfg(test)]
+mod tests_mmc1_mapper {
+	use rom::{HEADER_SIZE, Rom};
+
+	// NES 2.0 MMC1 rom. prg_ram is the PRG-RAM size byte.
+	fn rom(prg_rom_bank_num: u8, prg_ram: u8) -> Rom {
+		let mut v = vec![0x4E, 0x45, 0x53, 0x1A, prg_rom_bank_num, 0, 0x10, 0x08, 0, 0, prg_ram, 0x07];
+		v.resize(HEADER_SIZE, 0);
+		for bank in 0..prg_rom_bank_num {
+			v.resize(v.len() + 0x4000, bank);
+		}
+		Rom::parse(v).unwrap()
+	}
+
+	// Serial write to a register, a write every two CPU cycles
+	fn write(r: &mut Rom, address: u32, value: u8) {
+		for i in 0..5 {
+			r.store(address, (value >> i) & 1);
+			r.step();
+			r.step();
+		}
+	}
+
+	#[test]
+	fn consecutive_write() {
+		let mut r = rom(16, 0x07);
+		write(&mut r, 0x8000, 0x0C);
+		write(&mut r, 0xE000, 0x00);
+		// The second write in the next cycle is ignored
+		r.store(0xE000, 0x01);
+		r.step();
+		r.store(0xE000, 0x01);
+		r.step();
+		r.step();
+		for _ in 0..4 {
+			r.store(0xE000, 0x00);
+			r.step();
+			r.step();
+		}
+		assert_eq!(1, r.load(0x8000));
+	}
 
 	#[test]
-	fn initialize() {
-		NRomMapper{program_bank_num: 1};
+	fn prg_ram_disable() {
+		let mut r = rom(16, 0x07);
+		r.store(0x6000, 1);
+		assert_eq!(1, r.load(0x6000));
+		write(&mut r, 0xE000, 0x10);
+		assert_eq!(0, r.load(0x6000));
+		r.store(0x6000, 2);
+		write(&mut r, 0xE000, 0x00);
+		assert_eq!(1, r.load(0x6000));
 	}
 
 	#[test]
-	fn map_with_program_bank_num_1() {
-		let m = NRomMapper{program_bank_num: 1};
-		assert_eq!(0x0000, m.map(0x8000));
-		assert_eq!(0x3FFF, m.map(0xBFFF));
-		assert_eq!(0x0000, m.map(0xC000));
-		assert_eq!(0x3FFF, m.map(0xFFFF));
+	fn surom_outer_program_bank() {
+		let mut r = rom(32, 0x07);
+		write(&mut r, 0x8000, 0x0C);
+		write(&mut r, 0xE000, 0x01);
+		assert_eq!(1, r.load(0x8000));
+		assert_eq!(15, r.load(0xC000));
+		write(&mut r, 0xA000, 0x10);
+		assert_eq!(17, r.load(0x8000));
+		assert_eq!(31, r.load(0xC000));
 	}
 
 	#[test]
-	fn map_with_program_bank_num_2() {
-		let m = NRomMapper{program_bank_num: 2};
-		assert_eq!(0x0000, m.map(0x8000));
-		assert_eq!(0x3FFF, m.map(0xBFFF));
-		assert_eq!(0x4000, m.map(0xC000));
-		assert_eq!(0x7FFF, m.map(0xFFFF));
+	fn sxrom_prg_ram_bank() {
+		// 32KB PRG-RAM
+		let mut r = rom(32, 0x09);
+		for bank in 0..4 {
+			write(&mut r, 0xA000, bank << 2);
+			r.store(0x6000, bank + 1);
+		}
+		for bank in 0..4 {
+			write(&mut r, 0xA000, bank << 2);
+			assert_eq!(bank + 1, r.load(0x6000));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests_mmc2_mapper {
+	use super::*;
+
+	fn mapper(mmc4: bool) -> MMC2Mapper {
+		match mmc4 {
+			true => MMC2Mapper::new_mmc4(&RomHeader::new_for_test(8, 16, 10, None)),
+			false => MMC2Mapper::new(&RomHeader::new_for_test(8, 16, 9, None))
+		}
 	}
 
 	#[test]
-	fn map_for_chr_rom() {
-		let m = NRomMapper{program_bank_num: 1};
-		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
-		assert_eq!(0x1FFF, m.map_for_chr_rom(0x1FFF));
+	fn map() {
+		let mut m = mapper(false);
+		m.store(0xA000, 0x03);
+		assert_eq!(0x6000, m.map(0x8000));
+		assert_eq!(0x1A000, m.map(0xA000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+
+		let mut m2 = mapper(true);
+		m2.store(0xA000, 0x03);
+		assert_eq!(0xC000, m2.map(0x8000));
+		assert_eq!(0x1C000, m2.map(0xC000));
+		assert_eq!(0x1FFFF, m2.map(0xFFFF));
+	}
+
+	#[test]
+	fn latch() {
+		let c = Cartridge::new(vec![0; 0x20000], vec![0; 0x20000], 0, 0, 0, 0);
+		let mut m = mapper(false);
+		m.store(0xB000, 1);
+		m.store(0xC000, 2);
+		m.store(0xD000, 3);
+		m.store(0xE000, 4);
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x4000, m.map_for_chr_rom(0x1000));
+		// The bank switches after the fetch
+		assert_eq!(0x2FD8, m.map_for_chr_rom(0x0FD8));
+		m.load_chr(&c, 0x0FD8);
+		assert_eq!(0x1000, m.map_for_chr_rom(0x0000));
+		m.load_chr(&c, 0x1FDF);
+		assert_eq!(0x3000, m.map_for_chr_rom(0x1000));
+		m.load_chr(&c, 0x1FE8);
+		assert_eq!(0x4000, m.map_for_chr_rom(0x1000));
+		// Only 0x0FD8 and 0x0FE8 for latch 0 on MMC2
+		m.load_chr(&c, 0x0FE9);
+		assert_eq!(0x1000, m.map_for_chr_rom(0x0000));
+	}
+
+	#[test]
+	fn mmc4_latch() {
+		let c = Cartridge::new(vec![0; 0x20000], vec![0; 0x20000], 0, 0, 0, 0);
+		let mut m = mapper(true);
+		m.store(0xB000, 1);
+		m.store(0xC000, 2);
+		m.load_chr(&c, 0x0FDF);
+		assert_eq!(0x1000, m.map_for_chr_rom(0x0000));
+		m.load_chr(&c, 0x0FE9);
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+	}
+}
+
+#[cfg(test)]
+mod tests_mmc5_mapper {
+	use super::*;
+
+	fn cartridge() -> Cartridge {
+		test_cartridge(16, 0x40000, 0x10000)
+	}
+
+	// Three reads of the same nametable address start a scanline.
+	// The third read is the nametable fetch of the tile 2.
+	fn start_scanline(m: &mut MMC5Mapper, address: u16) {
+		m.notify_ppu_address(0x0000);
+		for _ in 0..3 {
+			m.notify_ppu_address(address);
+		}
+	}
+
+	#[test]
+	fn prg_bank() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		// 8KB mode, the last bank at power on
+		assert_eq!(15, m.load_prg(&c, 0x8000));
+		assert_eq!(15, m.load_prg(&c, 0xE000));
+		m.store_prg(&mut c, 0x5114, 0x83);
+		m.store_prg(&mut c, 0x5117, 0x05);
+		assert_eq!(3, m.load_prg(&c, 0x8000));
+		// 0x5117 always selects ROM
+		assert_eq!(5, m.load_prg(&c, 0xE000));
+		// 16KB mode ignores the low bit
+		m.store_prg(&mut c, 0x5100, 1);
+		m.store_prg(&mut c, 0x5115, 0x83);
+		assert_eq!(2, m.load_prg(&c, 0x8000));
+		assert_eq!(3, m.load_prg(&c, 0xA000));
+		assert_eq!(4, m.load_prg(&c, 0xC000));
+	}
+
+	#[test]
+	fn prg_ram() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(0, m.load_prg(&c, 0x6000));
+		m.store_prg(&mut c, 0x5102, 2);
+		m.store_prg(&mut c, 0x5103, 1);
+		m.store_prg(&mut c, 0x5113, 2);
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(1, m.load_prg(&c, 0x6000));
+		// RAM bank 2 at 0x8000
+		m.store_prg(&mut c, 0x5114, 0x02);
+		assert_eq!(1, m.load_prg(&c, 0x8000));
+	}
+
+	#[test]
+	fn multiplier() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		m.store_prg(&mut c, 0x5205, 0xFF);
+		m.store_prg(&mut c, 0x5206, 0x03);
+		assert_eq!(0xFD, m.load_prg(&c, 0x5205));
+		assert_eq!(0x02, m.load_prg(&c, 0x5206));
+	}
+
+	#[test]
+	fn nametable() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		m.store_prg(&mut c, 0x5105, 0xE4);
+		assert_eq!(Some([Nametable::Ciram(0), Nametable::Ciram(1), Nametable::ExRam, Nametable::Fill]),
+			m.nametable_mapping());
+		m.store_prg(&mut c, 0x5106, 0x12);
+		m.store_prg(&mut c, 0x5107, 0x02);
+		assert_eq!(0x12, m.load_nametable(&c, Nametable::Fill, 0x000));
+		assert_eq!(0xAA, m.load_nametable(&c, Nametable::Fill, 0x3C0));
+		m.store_nametable(&mut c, Nametable::ExRam, 0x10, 3);
+		assert_eq!(3, m.load_nametable(&c, Nametable::ExRam, 0x10));
+		// ExRAM as CPU RAM
+		m.store_prg(&mut c, 0x5104, 2);
+		assert_eq!(0, m.load_nametable(&c, Nametable::ExRam, 0x10));
+		m.store_prg(&mut c, 0x5C10, 4);
+		assert_eq!(4, m.load_prg(&c, 0x5C10));
+	}
+
+	#[test]
+	fn chr_bank() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		m.store_prg(&mut c, 0x5101, 3);
+		m.store_prg(&mut c, 0x5120, 0x01);
+		m.store_prg(&mut c, 0x5130, 0x01);
+		m.store_prg(&mut c, 0x5127, 0x02);
+		m.store_prg(&mut c, 0x5128, 0x03);
+		assert_eq!(0x0400, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x40800, m.map_for_chr_rom(0x1C00));
+		// The background set in 8x16 sprite mode
+		m.notify_ppu_register_store(0x2000, 0x20);
+		assert_eq!(0x40C00, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x40C00, m.map_for_chr_rom(0x1000));
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		m.store_prg(&mut c, 0x5203, 2);
+		m.store_prg(&mut c, 0x5204, 0x80);
+		start_scanline(&mut m, 0x2000);
+		assert_eq!(0x40, m.load_prg(&c, 0x5204));
+		start_scanline(&mut m, 0x2000);
+		assert_eq!(false, m.irq_line());
+		start_scanline(&mut m, 0x2000);
+		assert_eq!(true, m.irq_line());
+		assert_eq!(0xC0, m.load_prg(&c, 0x5204));
+		assert_eq!(false, m.irq_line());
+		// No PPU reads for three CPU cycles
+		for _ in 0..3 {
+			m.step();
+		}
+		assert_eq!(0x00, m.load_prg(&c, 0x5204));
+	}
+
+	#[test]
+	fn extended_attribute() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		m.store_prg(&mut c, 0x5104, 1);
+		start_scanline(&mut m, 0x2000);
+		// 4KB CHR bank 3 and palette 2
+		m.store_prg(&mut c, 0x5C02, 0x83);
+		c.chr_rom.store(0x3010, 5);
+		start_scanline(&mut m, 0x2002);
+		m.notify_ppu_address(0x23C0);
+		assert_eq!(Some([Nametable::ExRam; 4]), m.nametable_mapping());
+		assert_eq!(0xAA, m.load_nametable(&c, Nametable::ExRam, 0x3C0));
+		m.notify_ppu_address(0x0010);
+		assert_eq!(5, m.load_chr(&c, 0x0010));
+	}
+
+	#[test]
+	fn split() {
+		let mut c = cartridge();
+		let mut m = MMC5Mapper::new();
+		// Left 4 tiles with scroll 8 and CHR bank 1
+		m.store_prg(&mut c, 0x5200, 0x84);
+		m.store_prg(&mut c, 0x5201, 8);
+		m.store_prg(&mut c, 0x5202, 1);
+		start_scanline(&mut m, 0x2000);
+		m.store_prg(&mut c, 0x5C22, 0x07);
+		m.store_prg(&mut c, 0x5FC0, 0x08);
+		c.chr_rom.store(0x1071, 9);
+		// Tile 2 on scanline 1 is at (2, 9) in the split region
+		start_scanline(&mut m, 0x2000);
+		assert_eq!(Some([Nametable::ExRam; 4]), m.nametable_mapping());
+		assert_eq!(0x07, m.load_nametable(&c, Nametable::ExRam, 0));
+		m.notify_ppu_address(0x23C0);
+		assert_eq!(0xAA, m.load_nametable(&c, Nametable::ExRam, 0x3C0));
+		m.notify_ppu_address(0x0070);
+		assert_eq!(9, m.load_chr(&c, 0x0070));
+		m.notify_ppu_address(0x0078);
+		// Tile 4 is out of the split region
+		for address in [0x2001, 0x23C0, 0x0000, 0x0008, 0x2002].iter() {
+			m.notify_ppu_address(*address);
+		}
+		assert_eq!(Some([Nametable::Ciram(0); 4]), m.nametable_mapping());
+	}
+}
+
+#[cfg(test)]
+mod tests_mmc3_mapper {
+	use super::*;
+
+	fn mapper(old_irq: bool) -> MMC3Mapper {
+		let header = RomHeader::new_for_test(2, 1, 4, None);
+		match old_irq {
+			true => MMC3Mapper::new_with_old_irq(&header),
+			false => MMC3Mapper::new(&header)
+		}
+	}
+
+	// A12 rise after staying low for `low_cycles` CPU cycles
+	fn rise_a12(m: &mut MMC3Mapper, low_cycles: u8) {
+		m.notify_ppu_address(0x0000);
+		for _ in 0..low_cycles {
+			m.step();
+		}
+		m.notify_ppu_address(0x1000);
+	}
+
+	#[test]
+	fn irq_counter() {
+		let mut m = mapper(false);
+		m.store(0xC000, 2);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		rise_a12(&mut m, 10); // reload with 2
+		rise_a12(&mut m, 10); // 1
+		assert_eq!(false, m.irq_line());
+		rise_a12(&mut m, 10); // 0
+		assert_eq!(true, m.irq_line());
+		m.store(0xE000, 0);
+		assert_eq!(false, m.irq_line());
+	}
+
+	#[test]
+	fn a12_filter() {
+		let mut m = mapper(false);
+		m.store(0xC000, 1);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		rise_a12(&mut m, 10);
+		assert_eq!(1, m.irq_counter);
+		// Short low pulses like between background tile fetches are ignored
+		rise_a12(&mut m, 1);
+		rise_a12(&mut m, 3);
+		assert_eq!(1, m.irq_counter);
+		rise_a12(&mut m, 4);
+		assert_eq!(0, m.irq_counter);
+		assert_eq!(true, m.irq_line());
+	}
+
+	#[test]
+	fn latch_zero_new_irq() {
+		// MMC3B/C fires IRQ on every clock while the latch is 0
+		let mut m = mapper(false);
+		m.store(0xC000, 0);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		rise_a12(&mut m, 10);
+		assert_eq!(true, m.irq_line());
+		m.store(0xE000, 0);
+		m.store(0xE001, 0);
+		rise_a12(&mut m, 10);
+		assert_eq!(true, m.irq_line());
+	}
+
+	#[test]
+	fn latch_zero_old_irq() {
+		// MMC3A fires IRQ only when reloaded with 0 by 0xC001
+		let mut m = mapper(true);
+		m.store(0xC000, 0);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		rise_a12(&mut m, 10);
+		assert_eq!(true, m.irq_line());
+		m.store(0xE000, 0);
+		m.store(0xE001, 0);
+		rise_a12(&mut m, 10);
+		assert_eq!(false, m.irq_line());
+	}
+
+	#[test]
+	fn disabled_irq() {
+		let mut m = mapper(false);
+		m.store(0xC000, 1);
+		m.store(0xC001, 0);
+		rise_a12(&mut m, 10);
+		rise_a12(&mut m, 10);
+		assert_eq!(0, m.irq_counter);
+		assert_eq!(false, m.irq_line());
+	}
+}
+
+#[cfg(test)]
+mod tests_vrc6_mapper {
+	use super::*;
+
+	// 256KB PRG-ROM and 128KB CHR-ROM
+	fn mapper(vrc6b: bool) -> VRC6Mapper {
+		match vrc6b {
+			true => VRC6Mapper::new_vrc6b(&RomHeader::new_for_test(16, 16, 26, None)),
+			false => VRC6Mapper::new(&RomHeader::new_for_test(16, 16, 24, None))
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper(false);
+		m.store(0x8000, 0x03);
+		m.store(0xC000, 0x05);
+		assert_eq!(0xC000, m.map(0x8000));
+		assert_eq!(0xFFFF, m.map(0xBFFF));
+		assert_eq!(0xA000, m.map(0xC000));
+		assert_eq!(0x3E000, m.map(0xE000));
+		assert_eq!(0x3FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn chr_bank() {
+		let mut m = mapper(false);
+		for i in 0..8 {
+			m.store(0xD000 + (i / 4) * 0x1000 + (i % 4), 0x10 + i as u8 * 2);
+		}
+		assert_eq!(0x4000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x7BFF, m.map_for_chr_rom(0x1FFF));
+
+		// 2KB banks R0 - R3 with A10 from PPU
+		m.store(0xB003, 0x21);
+		assert_eq!(0x4000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x4400, m.map_for_chr_rom(0x0400));
+		assert_eq!(0x4800, m.map_for_chr_rom(0x0800));
+		assert_eq!(0x5FFF, m.map_for_chr_rom(0x1FFF));
+
+		// A10 from the register
+		m.store(0xB003, 0x01);
+		assert_eq!(0x4000, m.map_for_chr_rom(0x0400));
+
+		// 1KB banks R0 - R3 and 2KB banks R4 and R5
+		m.store(0xB003, 0x22);
+		assert_eq!(0x5800, m.map_for_chr_rom(0x0C00));
+		assert_eq!(0x6000, m.map_for_chr_rom(0x1000));
+		assert_eq!(0x6400, m.map_for_chr_rom(0x1400));
+		assert_eq!(0x6800, m.map_for_chr_rom(0x1800));
+	}
+
+	#[test]
+	fn swapped_address_lines() {
+		let mut m = mapper(true);
+		// 0xD001 on VRC6b is R2
+		m.store(0xD001, 0x07);
+		assert_eq!(0x1C00, m.map_for_chr_rom(0x0800));
+		// 0xB003 is the same
+		m.store(0xB003, 0x04);
+		assert_eq!(Some(HORIZONTAL_MIRRORING), m.nametable_mapping());
+	}
+
+	#[test]
+	fn prg_ram_enable() {
+		let mut c = Cartridge::new(vec![0; 0x40000], vec![0; 0x20000], 0, 0x2000, 0, 0);
+		let mut m = mapper(false);
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(0, m.load_prg(&c, 0x6000));
+		m.store_prg(&mut c, 0xB003, 0x80);
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(1, m.load_prg(&c, 0x6000));
+	}
+
+	#[test]
+	fn cycle_irq() {
+		let mut m = mapper(false);
+		m.store(0xF000, 0xFD);
+		m.store(0xF001, 0x07);
+		m.step();
+		m.step();
+		assert_eq!(false, m.irq_line());
+		m.step();
+		assert_eq!(true, m.irq_line());
+		// Acknowledgement copies the bit 0 to the enable bit
+		m.store(0xF002, 0);
+		assert_eq!(false, m.irq_line());
+		for _ in 0..3 {
+			m.step();
+		}
+		assert_eq!(true, m.irq_line());
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut m = mapper(false);
+		m.store(0xF000, 0xFE);
+		m.store(0xF001, 0x02);
+		// 341 / 3 CPU cycles per clock
+		for _ in 0..227 {
+			m.step();
+		}
+		assert_eq!(false, m.irq_line());
+		m.step();
+		assert_eq!(true, m.irq_line());
+		// Disabled after acknowledgement
+		m.store(0xF002, 0);
+		for _ in 0..1000 {
+			m.step();
+		}
+		assert_eq!(false, m.irq_line());
+	}
+
+	#[test]
+	fn audio() {
+		let mut m = mapper(false);
+		assert_eq!(0.0, m.audio_output());
+		// Pulse 1 with constant volume 15
+		m.store(0x9000, 0x8F);
+		m.store(0x9002, 0x80);
+		assert_eq!(15, m.audio.pulse1.output());
+
+		// Duty 1/16 with period 0
+		m.store(0x9000, 0x0F);
+		assert_eq!(0, m.audio.pulse1.output());
+		for _ in 0..15 {
+			m.step();
+		}
+		assert_eq!(15, m.audio.pulse1.output());
+
+		// Sawtooth adds 8 every two clocks
+		m.store(0xB000, 0x08);
+		m.store(0xB002, 0x80);
+		for _ in 0..12 {
+			m.step();
+		}
+		assert_eq!(6, m.audio.sawtooth.output());
+		m.step();
+		m.step();
+		assert_eq!(0, m.audio.sawtooth.output());
+	}
+
+	#[test]
+	fn invalid_audio_state() {
+		let mut m = mapper(false);
+		m.audio.pulse1.step = 16;
+		let mut writer = StateWriter::new();
+		m.audio.save_state(&mut writer);
+		let data = writer.into_vec();
+		assert_eq!(Err(StateError::InvalidData), mapper(false).audio.load_state(&mut StateReader::new(&data)));
+
+		let mut m = mapper(false);
+		m.audio.sawtooth.step = 14;
+		let mut writer = StateWriter::new();
+		m.audio.save_state(&mut writer);
+		let data = writer.into_vec();
+		assert_eq!(Err(StateError::InvalidData), mapper(false).audio.load_state(&mut StateReader::new(&data)));
+	}
+}
+
+#[cfg(test)]
+mod tests_vrc4_mapper {
+	use super::*;
+
+	// 256KB PRG-ROM and 256KB CHR-ROM. NES 2.0 header if submapper is some
+	fn mapper(mapper_num: u8, submapper_num: Option<u8>) -> VRC4Mapper {
+		VRC4Mapper::new(&RomHeader::new_for_test(16, 32, mapper_num, submapper_num))
+	}
+
+	#[test]
+	fn register_address() {
+		// Register 2, lower 4 bits of CHR bank 1, of each variant
+		let variants = [
+			(21, Some(1), 0xB004),
+			(21, Some(2), 0xB080),
+			(21, None, 0xB004),
+			(21, None, 0xB080),
+			(22, None, 0xB001),
+			(23, Some(1), 0xB002),
+			(23, Some(2), 0xB008),
+			(23, Some(3), 0xB002),
+			(23, None, 0xB002),
+			(23, None, 0xB008),
+			(25, Some(1), 0xB001),
+			(25, Some(2), 0xB004),
+			(25, Some(3), 0xB001),
+			(25, None, 0xB001),
+			(25, None, 0xB004)
+		];
+		for &(mapper_num, submapper_num, address) in variants.iter() {
+			let mut m = mapper(mapper_num, submapper_num);
+			m.store(address, 6);
+			// VRC2a ignores bit 0
+			let expected = match mapper_num {
+				22 => 0xC00,
+				_ => 0x1800
+			};
+			assert_eq!(expected, m.map_for_chr_rom(0x0400), "mapper {} {:?}", mapper_num, submapper_num);
+		}
+	}
+
+	#[test]
+	fn prg_swap_mode() {
+		let mut m = mapper(23, Some(1));
+		m.store(0x8000, 3);
+		m.store(0xA000, 4);
+		assert_eq!(0x6000, m.map(0x8000));
+		assert_eq!(0x8000, m.map(0xA000));
+		assert_eq!(0x3C000, m.map(0xC000));
+		assert_eq!(0x3FFFF, m.map(0xFFFF));
+		m.store(0x9002, 0x02);
+		assert_eq!(0x3C000, m.map(0x8000));
+		assert_eq!(0x6000, m.map(0xC000));
+
+		// VRC2 has no swap mode
+		let mut m2 = mapper(23, Some(3));
+		m2.store(0x8000, 3);
+		m2.store(0x9002, 0x02);
+		assert_eq!(0x6000, m2.map(0x8000));
+	}
+
+	#[test]
+	fn chr_bank() {
+		let mut m = mapper(25, Some(1));
+		// 9 bits on VRC4. 0xE001 and 0xE003 are bank 7 on VRC4b
+		m.store(0xE001, 0x0F);
+		m.store(0xE003, 0x1F);
+		assert_eq!(0x7FC00, m.map_for_chr_rom(0x1C00));
+
+		// 8 bits on VRC2
+		let mut m2 = mapper(25, Some(3));
+		m2.store(0xE001, 0x0F);
+		m2.store(0xE003, 0x1F);
+		assert_eq!(0x3FC00, m2.map_for_chr_rom(0x1C00));
+	}
+
+	#[test]
+	fn mirroring() {
+		let mut m = mapper(21, Some(1));
+		m.store(0x9000, 0x03);
+		assert_eq!(Some(SINGLE_SCREEN_B), m.nametable_mapping());
+
+		let mut m2 = mapper(22, None);
+		m2.store(0x9000, 0x03);
+		assert_eq!(Some(HORIZONTAL_MIRRORING), m2.nametable_mapping());
+	}
+
+	#[test]
+	fn irq() {
+		let mut m = mapper(21, Some(2));
+		m.store(0xF000, 0x0E);
+		m.store(0xF040, 0x0F);
+		m.store(0xF080, 0x06);
+		m.step();
+		assert_eq!(false, m.irq_line());
+		m.step();
+		assert_eq!(true, m.irq_line());
+		m.store(0xF0C0, 0);
+		assert_eq!(false, m.irq_line());
+
+		// VRC2 has no IRQ
+		let mut m2 = mapper(22, None);
+		m2.store(0xF000, 0x0F);
+		m2.store(0xF001, 0x0F);
+		m2.store(0xF002, 0x06);
+		m2.step();
+		assert_eq!(false, m2.irq_line());
+	}
+
+	#[test]
+	fn microwire_latch() {
+		let mut c = Cartridge::new(vec![0; 0x40000], vec![0; 0x40000], 0, 0, 0, 0);
+		let mut m = mapper(23, Some(3));
+		m.store_prg(&mut c, 0x6000, 0xFF);
+		assert_eq!(1, m.load_prg(&c, 0x6000));
+		m.store_prg(&mut c, 0x6000, 0xFE);
+		assert_eq!(0, m.load_prg(&c, 0x6000));
+
+		// PRG-RAM if the board has
+		let mut c2 = Cartridge::new(vec![0; 0x40000], vec![0; 0x40000], 0, 0x2000, 0, 0);
+		m.store_prg(&mut c2, 0x6000, 0xFE);
+		assert_eq!(0xFE, m.load_prg(&c2, 0x6000));
+	}
+}
+
+#[cfg(test)]
+mod tests_fme7_mapper {
+	use super::*;
+
+	// 256KB PRG-ROM and 256KB CHR-ROM
+	fn mapper() -> FME7Mapper {
+		FME7Mapper::new(&RomHeader::new_for_test(16, 32, 69, None))
+	}
+
+	fn command(m: &mut FME7Mapper, command: u8, parameter: u8) {
+		m.store(0x8000, command);
+		m.store(0xA000, parameter);
+	}
+
+	fn store_audio(m: &mut FME7Mapper, register: u8, value: u8) {
+		m.store(0xC000, register);
+		m.store(0xE000, value);
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper();
+		command(&mut m, 0x9, 0x01);
+		command(&mut m, 0xA, 0x02);
+		command(&mut m, 0xB, 0x43);
+		assert_eq!(0x2000, m.map(0x8000));
+		assert_eq!(0x4000, m.map(0xA000));
+		assert_eq!(0x6000, m.map(0xC000));
+		assert_eq!(0x3FFFF, m.map(0xFFFF));
+
+		command(&mut m, 0x7, 0xFF);
+		assert_eq!(0x3FC00, m.map_for_chr_rom(0x1C00));
+	}
+
+	#[test]
+	fn prg_ram() {
+		let mut v = vec![0; 0x40000];
+		v[0x4000] = 5;
+		let mut c = Cartridge::new(v, vec![0; 0x40000], 0, 0x2000, 0, 0);
+		let mut m = mapper();
+		// PRG-ROM bank 2
+		command(&mut m, 0x8, 0x02);
+		assert_eq!(5, m.load_prg(&c, 0x6000));
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(0, c.load_prg_ram(0));
+
+		// Disabled PRG-RAM
+		command(&mut m, 0x8, 0x40);
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(0, m.load_prg(&c, 0x6000));
+
+		command(&mut m, 0x8, 0xC0);
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(1, m.load_prg(&c, 0x6000));
+	}
+
+	#[test]
+	fn irq() {
+		let mut m = mapper();
+		command(&mut m, 0xE, 0x01);
+		command(&mut m, 0xF, 0x00);
+		// Counter only
+		command(&mut m, 0xD, 0x80);
+		m.step();
+		m.step();
+		assert_eq!(false, m.irq_line());
+		assert_eq!(0xFFFF, m.irq_counter);
+
+		command(&mut m, 0xE, 0x01);
+		command(&mut m, 0xF, 0x00);
+		command(&mut m, 0xD, 0x81);
+		m.step();
+		assert_eq!(false, m.irq_line());
+		m.step();
+		assert_eq!(true, m.irq_line());
+		// Writing control register acknowledges
+		command(&mut m, 0xD, 0x81);
+		assert_eq!(false, m.irq_line());
+	}
+
+	#[test]
+	fn tone() {
+		let mut m = mapper();
+		assert_eq!(0.0, m.audio_output());
+		// Channel A with period 2 at volume 15
+		store_audio(&mut m, 0x0, 0x02);
+		store_audio(&mut m, 0x7, 0x3E);
+		store_audio(&mut m, 0x8, 0x0F);
+		for _ in 0..32 {
+			m.step();
+		}
+		assert_eq!(PULSE_FULL_VOLUME_LEVEL, m.audio_output());
+		for _ in 0..32 {
+			m.step();
+		}
+		assert_eq!(0.0, m.audio_output());
+
+		// 3dB per volume step
+		for _ in 0..32 {
+			m.step();
+		}
+		store_audio(&mut m, 0x8, 0x0D);
+		let ratio = m.audio_output() / PULSE_FULL_VOLUME_LEVEL;
+		assert!((ratio - 0.5).abs() < 0.01);
+	}
+
+	#[test]
+	fn envelope() {
+		let mut m = mapper();
+		// Period 1, attack and hold
+		store_audio(&mut m, 0xB, 0x01);
+		store_audio(&mut m, 0xD, 0x0D);
+		assert_eq!(0, m.audio.envelope_level);
+		for _ in 0..16 * 31 {
+			m.step();
+		}
+		assert_eq!(31, m.audio.envelope_level);
+		for _ in 0..16 * 4 {
+			m.step();
+		}
+		assert_eq!(31, m.audio.envelope_level);
+
+		// Falling triangle
+		store_audio(&mut m, 0xD, 0x0A);
+		assert_eq!(31, m.audio.envelope_level);
+		for _ in 0..16 * 32 {
+			m.step();
+		}
+		assert_eq!(0, m.audio.envelope_level);
+		for _ in 0..16 {
+			m.step();
+		}
+		assert_eq!(1, m.audio.envelope_level);
+
+		// One cycle
+		store_audio(&mut m, 0xD, 0x00);
+		for _ in 0..16 * 40 {
+			m.step();
+		}
+		assert_eq!(0, m.audio.envelope_level);
+		assert_eq!(true, m.audio.envelope_holding);
+	}
+
+	#[test]
+	fn invalid_envelope_state() {
+		let mut m = mapper();
+		m.audio.envelope_level = 32;
+		let mut writer = StateWriter::new();
+		m.audio.save_state(&mut writer);
+		let data = writer.into_vec();
+		assert_eq!(Err(StateError::InvalidData), mapper().audio.load_state(&mut StateReader::new(&data)));
+	}
+
+	#[test]
+	fn invalid_divider_state() {
+		let mut m = mapper();
+		m.audio.divider = 16;
+		let mut writer = StateWriter::new();
+		m.audio.save_state(&mut writer);
+		let data = writer.into_vec();
+		assert_eq!(Err(StateError::InvalidData), mapper().audio.load_state(&mut StateReader::new(&data)));
+	}
+}
+
+#[cfg(test)]
+mod tests_namco163_mapper {
+	use super::*;
+
+	// 256KB PRG-ROM and 256KB CHR-ROM
+	fn mapper() -> Namco163Mapper {
+		Namco163Mapper::new(&RomHeader::new_for_test(16, 32, 19, None))
+	}
+
+	fn cartridge() -> Cartridge {
+		test_cartridge(32, 0x40000, 0x2000)
+	}
+
+	fn store_internal_ram(m: &mut Namco163Mapper, c: &mut Cartridge, address: u8, values: &[u8]) {
+		m.store_prg(c, 0xF800, 0x80 | address);
+		for value in values.iter() {
+			m.store_prg(c, 0x4800, *value);
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper();
+		m.store(0xE000, 0x41);
+		m.store(0xE800, 0x02);
+		m.store(0xF000, 0x03);
+		assert_eq!(0x2000, m.map(0x8000));
+		assert_eq!(0x4000, m.map(0xA000));
+		assert_eq!(0x6000, m.map(0xC000));
+		assert_eq!(0x3FFFF, m.map(0xFFFF));
+		m.store(0xB800, 0x12);
+		assert_eq!(0x4BFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn internal_ram() {
+		let mut c = cartridge();
+		let mut m = mapper();
+		store_internal_ram(&mut m, &mut c, 0x7F, &[1, 2]);
+		// Wraps around
+		assert_eq!(1, m.internal_ram.load(0x7F));
+		assert_eq!(2, m.internal_ram.load(0x00));
+		// Without auto increment
+		m.store_prg(&mut c, 0xF800, 0x7F);
+		assert_eq!(1, m.load_prg(&c, 0x4800));
+		assert_eq!(1, m.load_prg(&c, 0x4800));
+	}
+
+	#[test]
+	fn prg_ram_protection() {
+		let mut c = cartridge();
+		let mut m = mapper();
+		m.store_prg(&mut c, 0x6000, 1);
+		assert_eq!(0, m.load_prg(&c, 0x6000));
+		// 0x6800 - 0x6FFF is protected
+		m.store_prg(&mut c, 0xF800, 0x42);
+		m.store_prg(&mut c, 0x6000, 1);
+		m.store_prg(&mut c, 0x6800, 2);
+		assert_eq!(1, m.load_prg(&c, 0x6000));
+		assert_eq!(0, m.load_prg(&c, 0x6800));
+	}
+
+	#[test]
+	fn ciram() {
+		let mut c = cartridge();
+		c.chr_rom.store(0x1400, 7);
+		let mut m = mapper();
+		m.store(0x8000, 0xE1);
+		m.store(0x8800, 0x05);
+		m.store(0xA000, 0xE0);
+		assert_eq!(Some(1), m.pattern_ciram_page(0x0000));
+		assert_eq!(None, m.pattern_ciram_page(0x0400));
+		assert_eq!(Some(0), m.pattern_ciram_page(0x1000));
+		// CHR-ROM for 0x0000 - 0x0FFF
+		m.store(0xE800, 0x40);
+		assert_eq!(None, m.pattern_ciram_page(0x0000));
+		assert_eq!(0x38400, m.map_for_chr_rom(0x0000));
+		assert_eq!(Some(0), m.pattern_ciram_page(0x1000));
+
+		m.store(0xC000, 0xE0);
+		m.store(0xC800, 0xE1);
+		m.store(0xD000, 0x05);
+		m.store(0xD800, 0xFF);
+		assert_eq!(Some([Nametable::Ciram(0), Nametable::Ciram(1), Nametable::ChrRom(5), Nametable::Ciram(1)]),
+			m.nametable_mapping());
+		assert_eq!(7, m.load_nametable(&c, Nametable::ChrRom(5), 0));
+	}
+
+	#[test]
+	fn irq() {
+		let mut c = cartridge();
+		let mut m = mapper();
+		m.store_prg(&mut c, 0x5000, 0xFD);
+		m.store_prg(&mut c, 0x5800, 0xFF);
+		m.step();
+		assert_eq!(false, m.irq_line());
+		m.step();
+		assert_eq!(true, m.irq_line());
+		// Stops at 0x7FFF
+		m.step();
+		assert_eq!(0xFF, m.load_prg(&c, 0x5000));
+		assert_eq!(0xFF, m.load_prg(&c, 0x5800));
+		m.store_prg(&mut c, 0x5800, 0x7F);
+		assert_eq!(false, m.irq_line());
+	}
+
+	#[test]
+	fn audio() {
+		let mut c = cartridge();
+		let mut m = mapper();
+		// 4-sample wave 0, 15, 0, 15 at 0
+		store_internal_ram(&mut m, &mut c, 0x00, &[0xF0, 0xF0]);
+		// Channel 7 advances a sample every update at volume 15
+		store_internal_ram(&mut m, &mut c, 0x78, &[0, 0, 0, 0, 0xFD, 0, 0, 0x0F]);
+		assert_eq!(0.0, m.audio_output());
+		for _ in 0..15 {
+			m.step();
+		}
+		assert_eq!(105.0 * PULSE_FULL_VOLUME_LEVEL / 120.0, m.audio_output());
+		for _ in 0..15 {
+			m.step();
+		}
+		assert_eq!(-PULSE_FULL_VOLUME_LEVEL, m.audio_output());
+
+		// Sound disable
+		m.store(0xE000, 0x40);
+		assert_eq!(0.0, m.audio_output());
+	}
+
+	#[test]
+	fn mixing() {
+		let mut c = cartridge();
+		let mut m = mapper();
+		store_internal_ram(&mut m, &mut c, 0x00, &[0xF0, 0xF0]);
+		// Channel 7 same as above and silent channel 6
+		store_internal_ram(&mut m, &mut c, 0x78, &[0, 0, 0, 0, 0xFD, 0, 0, 0x1F]);
+		for _ in 0..15 * 2 {
+			m.step();
+		}
+		let output = 105.0 * PULSE_FULL_VOLUME_LEVEL / 120.0;
+		assert_eq!(output, m.audio_output());
+		m.set_audio_multiplexing(false);
+		assert_eq!(output / 2.0, m.audio_output());
+		// Channel 6 is being output
+		m.set_audio_multiplexing(true);
+		for _ in 0..15 {
+			m.step();
+		}
+		assert_eq!(0.0, m.audio_output());
+		m.set_audio_multiplexing(false);
+		assert_eq!(output / 2.0, m.audio_output());
+	}
+
+	#[test]
+	fn invalid_audio_state() {
+		let mut m = mapper();
+		m.audio.channel = 8;
+		let mut writer = StateWriter::new();
+		m.audio.save_state(&mut writer);
+		let data = writer.into_vec();
+		assert_eq!(Err(StateError::InvalidData), mapper().audio.load_state(&mut StateReader::new(&data)));
+
+		let mut m = mapper();
+		m.audio.cycle = 15;
+		let mut writer = StateWriter::new();
+		m.audio.save_state(&mut writer);
+		let data = writer.into_vec();
+		assert_eq!(Err(StateError::InvalidData), mapper().audio.load_state(&mut StateReader::new(&data)));
 	}
 }
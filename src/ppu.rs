@@ -1,20 +1,26 @@
 use register::Register;
 use memory::Memory;
 use rom::Rom;
-use rom::Mirrorings;
+use rom::Nametable;
 use display::Display;
+use state::{StateError, StateReader, StateWriter};
+use region::{NTSC_TIMING, Region, RegionTiming};
 
 /**
  * RP2A03
  * The comments about PPU spec are based on https://wiki.nesdev.com/w/index.php/PPU
  */
 pub struct Ppu {
+	region: Region,
+	timing: RegionTiming,
+
 	pub frame: u32,
 
 	// 341 cycles per scan line. 0-340.
 	pub cycle: u16,
 
-	// 262 scan lines per frame. 0-261
+	// 262 scan lines per frame on NTSC. 0-261
+	// 0-239: visible, 240: post-render, 241-260: vblank, 261: pre-render
 	scanline: u16,
 
 	// manage a case where vblank doesn't set due to 0x2002 read
@@ -66,6 +72,15 @@ pub struct Ppu {
 	// Secondary OAM, holds 8 sprites for the current scanline
 	secondary_oam: SpritesManager,
 
+	// The number of the sprites in secondary OAM and their indices in primary OAM
+	sprite_count: u8,
+	sprite_indices: [u8; 8],
+
+	// Pattern table tiles of the sprites in secondary OAM
+	// fetched during cycle 257-320
+	sprite_pattern_table_lows: [u8; 8],
+	sprite_pattern_table_highs: [u8; 8],
+
 	// -- Eight + one CPU memory-mapped registers
 
 	// 0x2000. See PpuControlRegister comment.
@@ -121,13 +136,10 @@ pub struct Ppu {
 
 	// -- 
 
-	display: Box<dyn Display>,
-
-	pub nmi_interrupted: bool,
-	pub irq_interrupted: bool
+	display: Box<dyn Display>
 }
 
-static PALETTES: [u32; 0x40] = [
+static NTSC_PALETTES: [u32; 0x40] = [
     /* 0x00 */ 0xff757575,
     /* 0x01 */ 0xff8f1b27,
     /* 0x02 */ 0xffab0000,
@@ -194,9 +206,81 @@ static PALETTES: [u32; 0x40] = [
     /* 0x3f */ 0xff000000
 ];
 
+// 2C07 PAL PPU palette, also used by Dendy. The PAL PPU generates
+// the colour phases 15 degrees off from NTSC, so this is the NTSC
+// palette with the chroma rotated by 15 degrees in YIQ space.
+static PAL_PALETTES: [u32; 0x40] = [
+    /* 0x00 */ 0xff757575,
+    /* 0x01 */ 0xff8a2319,
+    /* 0x02 */ 0xff9d0e00,
+    /* 0x03 */ 0xffaa0734,
+    /* 0x04 */ 0xff9d0083,
+    /* 0x05 */ 0xff4a00ac,
+    /* 0x06 */ 0xff3800aa,
+    /* 0x07 */ 0xff270183,
+    /* 0x08 */ 0xff0a2a49,
+    /* 0x09 */ 0xff004707,
+    /* 0x0a */ 0xff005109,
+    /* 0x0b */ 0xff054104,
+    /* 0x0c */ 0xff504416,
+    /* 0x0d */ 0xff000000,
+    /* 0x0e */ 0xff000000,
+    /* 0x0f */ 0xff000000,
+    /* 0x10 */ 0xffbcbcbc,
+    /* 0x11 */ 0xffbf8500,
+    /* 0x12 */ 0xffd84b0c,
+    /* 0x13 */ 0xffff0a67,
+    /* 0x14 */ 0xffef01ab,
+    /* 0x15 */ 0xffa000e0,
+    /* 0x16 */ 0xff3e1ae4,
+    /* 0x17 */ 0xff3d41d5,
+    /* 0x18 */ 0xff11689a,
+    /* 0x19 */ 0xff009610,
+    /* 0x1a */ 0xff00aa12,
+    /* 0x1b */ 0xff119708,
+    /* 0x1c */ 0xff5f8d00,
+    /* 0x1d */ 0xff000000,
+    /* 0x1e */ 0xff000000,
+    /* 0x1f */ 0xff000000,
+    /* 0x20 */ 0xffffffff,
+    /* 0x21 */ 0xffcfce34,
+    /* 0x22 */ 0xffe4a351,
+    /* 0x23 */ 0xfffd9299,
+    /* 0x24 */ 0xffff7ce9,
+    /* 0x25 */ 0xffdf72fa,
+    /* 0x26 */ 0xff926bff,
+    /* 0x27 */ 0xff648cff,
+    /* 0x28 */ 0xff5bb1ff,
+    /* 0x29 */ 0xff08ca99,
+    /* 0x2a */ 0xff27de5f,
+    /* 0x2b */ 0xff6bfc61,
+    /* 0x2c */ 0xff8efb00,
+    /* 0x2d */ 0xff000000,
+    /* 0x2e */ 0xff000000,
+    /* 0x2f */ 0xff000000,
+    /* 0x30 */ 0xffffffff,
+    /* 0x31 */ 0xffe9eda7,
+    /* 0x32 */ 0xfff6dbc2,
+    /* 0x33 */ 0xffffced1,
+    /* 0x34 */ 0xffffc7f9,
+    /* 0x35 */ 0xffecc4fe,
+    /* 0x36 */ 0xffc9b9ff,
+    /* 0x37 */ 0xffbbd5ff,
+    /* 0x38 */ 0xffb1e0ff,
+    /* 0x39 */ 0xffa1faee,
+    /* 0x3a */ 0xffabf4b0,
+    /* 0x3b */ 0xffbaffb8,
+    /* 0x3c */ 0xffd4ff9f,
+    /* 0x3d */ 0xff000000,
+    /* 0x3e */ 0xff000000,
+    /* 0x3f */ 0xff000000
+];
+
 impl Ppu {
 	pub fn new(display: Box<dyn Display>) -> Self {
 		Ppu {
+			region: Region::Ntsc,
+			timing: NTSC_TIMING,
 			frame: 0,
 			cycle: 0,
 			scanline: 0,
@@ -221,6 +305,10 @@ impl Ppu {
 			oamdma: Register::<u8>::new(),
 			primary_oam: SpritesManager::new(Memory::new(vec![0; 256])), // primary 256B
 			secondary_oam: SpritesManager::new(Memory::new(vec![0; 32])), // secondary 32B
+			sprite_count: 0,
+			sprite_indices: [0; 8],
+			sprite_pattern_table_lows: [0; 8],
+			sprite_pattern_table_highs: [0; 8],
 			vram_read_buffer: 0,
 			ppuaddr: Register::<u8>::new(),
 			ppudata: Register::<u8>::new(),
@@ -233,9 +321,7 @@ impl Ppu {
 			attribute_table_high: Register::<u16>::new(),
 			pattern_table_low: Register::<u16>::new(),
 			pattern_table_high: Register::<u16>::new(),
-			display: display,
-			nmi_interrupted: false,
-			irq_interrupted: false
+			display: display
 		}
 	}
 
@@ -243,6 +329,15 @@ impl Ppu {
 		self.ppustatus.store(0x80);
 	}
 
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+		self.timing = region.timing();
+	}
+
+	pub fn get_scanline(&self) -> u16 {
+		self.scanline
+	}
+
 	pub fn reset(&mut self) {
 		self.ppuctrl.store(0x00);
 		self.ppumask.store(0x00);
@@ -260,13 +355,19 @@ impl Ppu {
 		self.render_pixel(rom);
 		self.shift_registers();
 		self.fetch(rom);
-		self.evaluate_sprites(rom);
-		self.update_flags(rom);
+		self.evaluate_sprites();
+		self.update_flags();
 		self.countup_scroll_counters();
 		self.countup_cycle();
 	}
 
-	pub fn load_register(&mut self, address: u16, rom: &Rom) -> u8 {
+	// NMI output is asserted while vblank flag and NMI enabled flag are set.
+	// CPU detects its rising edge.
+	pub fn nmi_line(&self) -> bool {
+		self.ppustatus.is_vblank() && self.ppuctrl.is_nmi_enabled()
+	}
+
+	pub fn load_register(&mut self, address: u16, rom: &mut Rom) -> u8 {
 		match address {
 			// ppustatus load
 			0x2002 => {
@@ -280,24 +381,19 @@ impl Ppu {
 				// unused 4 lsb bits don't override data bus.
 				self.data_bus = (value & 0xE0) | (self.data_bus & 0x1F);
 
-				// reading 0x2002 at cycle=0 and scanline=241
-				// won't set vblank flag (7-bit) or fire NMI.
-				// reading 0x2002 at cycle=1or2 and scanline=241
-				// returns the data as vblank flag is set,
-				// clears the flag, and won't fire NMI
+				// Reading 0x2002 one PPU clock before vblank flag (7-bit) is set
+				// at cycle=1 and scanline=241 returns the flag clear and
+				// the flag isn't set and NMI doesn't fire for the frame.
+				// Reading right after it's set returns the flag set and
+				// clears it before CPU notices NMI so NMI doesn't fire.
+				// CPU accesses happen between PPU cycles so self.cycle here
+				// is the next cycle PPU processes.
 
-				// Note: update_flags() which can set vblank is called
-				// after this method in the same cycle, so set supress_vblank true
-				// even at cycle=1 not only cycle=0
-
-				if self.scanline == 241 && (self.cycle == 0 || self.cycle == 1) {
+				if self.scanline == self.timing.vblank_scanline && self.cycle == 1 {
 					self.suppress_vblank = true;
 				}
 
-				value | match self.scanline == 241 && (self.cycle == 1 || self.cycle == 2) {
-					true => 0x80,
-					false => 0x00
-				}
+				value
 			},
 			// oamdata load
 			0x2004 => {
@@ -346,18 +442,10 @@ impl Ppu {
 				//	return;
 				//}
 
-				let previous_nmi_enabled = self.ppuctrl.is_nmi_enabled();
+				// Changing the NMI flag from 0 to 1 while vblank flag is set
+				// raises NMI line and CPU generates an NMI. See nmi_line().
 				self.ppuctrl.store(value);
 
-				// Immediately generate an NMI if the PPU is currently
-				// in vertical blank, PPUSTATUS vblank flag is still set,
-				// and changing the NMI flag from 0 to 1
-				if self.ppustatus.is_vblank() &&
-					!previous_nmi_enabled &&
-					self.ppuctrl.is_nmi_enabled() {
-					self.nmi_interrupted = true;
-				}
-
 				// Copy the 1-0 bits of value to 11-10 bits of temporal vram_address for scrolling
 				// Refer to http://wiki.nesdev.com/w/index.php/PPU_scrolling
 				self.temporal_vram_address &= 0xF3FF;
@@ -412,6 +500,8 @@ impl Ppu {
 					self.temporal_vram_address &= 0xFF00;
 					self.temporal_vram_address |= value as u16;
 					self.current_vram_address = self.temporal_vram_address;
+					// The new address appears on the PPU address bus
+					rom.notify_ppu_address(self.current_vram_address & 0x3FFF);
 				}
 
 				self.register_first_store = !self.register_first_store;
@@ -432,60 +522,86 @@ impl Ppu {
 		}
 	}
 
-	fn load(&self, mut address: u16, rom: &Rom) -> u8 {
+	fn load(&self, mut address: u16, rom: &mut Rom) -> u8 {
 		address = address & 0x3FFF;  // just in case
 
-		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM if exists.
-		// Otherwise load from VRAM.
+		// Palette is inside PPU and its access doesn't appear on the bus
+		if address >= 0x3F00 {
+			return self.vram.load(self.convert_palette_address(address) as u32);
+		}
+
+		rom.notify_ppu_address(address);
 
-		match address < 0x2000 && rom.has_chr_rom() {
-			true => rom.load(address as u32),
-			false => self.vram.load(self.convert_vram_address(address, rom) as u32)
+		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM or CHR-RAM.
+		// Each nametable is mapped by mapper with VRAM (CIRAM)
+		// or cartridge's memory.
+
+		if address < 0x2000 {
+			return match rom.pattern_ciram_page(address) {
+				Some(page) => self.vram.load(self.convert_ciram_address(page, address & 0x3FF) as u32),
+				None => rom.load(address as u32)
+			};
+		}
+		match self.get_name_table_address_with_mirroring(address, rom) {
+			(Nametable::Ciram(page), offset) => self.vram.load(self.convert_ciram_address(page, offset) as u32),
+			(nametable, offset) => rom.load_nametable(nametable, offset as u32)
 		}
 	}
 
 	fn store(&mut self, mut address: u16, value: u8, rom: &mut Rom) {
 		address = address & 0x3FFF;  // just in case
 
-		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM if exists.
-		// Otherwise store to VRAM.
+		if address >= 0x3F00 {
+			self.vram.store(self.convert_palette_address(address) as u32, value);
+			return;
+		}
 
-		match address < 0x2000 && rom.has_chr_rom() {
-			true => rom.store(address as u32, value),
-			false => self.vram.store(self.convert_vram_address(address, rom) as u32, value)
-		};
+		rom.notify_ppu_address(address);
+
+		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM or CHR-RAM.
+		// Each nametable is mapped by mapper with VRAM (CIRAM)
+		// or cartridge's memory.
+
+		if address < 0x2000 {
+			match rom.pattern_ciram_page(address) {
+				Some(page) => {
+					let address = self.convert_ciram_address(page, address & 0x3FF);
+					self.vram.store(address as u32, value);
+				},
+				None => rom.store(address as u32, value)
+			};
+			return;
+		}
+		match self.get_name_table_address_with_mirroring(address, rom) {
+			(Nametable::Ciram(page), offset) => {
+				let address = self.convert_ciram_address(page, offset);
+				self.vram.store(address as u32, value);
+			},
+			(nametable, offset) => rom.store_nametable(nametable, offset as u32, value)
+		}
 	}
 
-	fn convert_vram_address(&self, address: u16, rom: &Rom) -> u16 {
-		// 0x0000 - 0x0FFF: pattern table 0
-		// 0x1000 - 0x1FFF: pattern table 1
-		// 0x2000 - 0x23FF: nametable 0
-		// 0x2400 - 0x27FF: nametable 1
-		// 0x2800 - 0x2BFF: nametable 2
-		// 0x2C00 - 0x2FFF: nametable 3
-		// 0x3000 - 0x3EFF: Mirrors of 0x2000 - 0x2EFF
+	fn convert_palette_address(&self, address: u16) -> u16 {
 		// 0x3F00 - 0x3F1F: Palette RAM indices
 		// 0x3F20 - 0x3FFF: Mirrors of 0x3F00 - 0x3F1F
 
+		// 0x3F10/0x3F14/0x3F18/0x3F1C are mirrors of
+		// 0x3F00/0x3F04/0x3F08/0x3F0C.
 		match address {
-			0..=0x1FFF => address,
-			0x2000..=0x3EFF => self.get_name_table_address_with_mirroring(address & 0x2FFF, rom),
-			_ /* 0x3F00..=0x3FFF */ => {
-				// Addresses for palette
-				// 0x3F10/0x3F14/0x3F18/0x3F1C are mirrors of
-				// 0x3F00/0x3F04/0x3F08/0x3F0C.
-				match address {
-					0x3F10 => 0x3F00,
-					0x3F14 => 0x3F04,
-					0x3F18 => 0x3F08,
-					0x3F1C => 0x3F0C,
-					_ => address
-				}
-			}
+			0x3F10 => 0x3F00,
+			0x3F14 => 0x3F04,
+			0x3F18 => 0x3F08,
+			0x3F1C => 0x3F0C,
+			_ => address
 		}
 	}
 
-	fn render_pixel(&mut self, rom: &Rom) {
+	// CIRAM, 2KB VRAM in the console, is placed at 0x2000 - 0x27FF of vram
+	fn convert_ciram_address(&self, page: u8, offset: u16) -> u16 {
+		0x2000 | ((page as u16 & 1) << 10) | offset
+	}
+
+	fn render_pixel(&mut self, rom: &mut Rom) {
 		// Note: this comparison order is for performance.
 		if self.cycle >= 257 || self.scanline >= 240 || self.cycle == 0 {
 			return;
@@ -561,7 +677,7 @@ impl Ppu {
 	}
 
 	fn shift_registers(&mut self) {
-		if self.scanline >= 240 && self.scanline <= 260 {
+		if self.is_idle_scanline() {
 			return;
 		}
 
@@ -574,9 +690,10 @@ impl Ppu {
 		}
 	}
 
-	fn fetch(&mut self, rom: &Rom) {
+	fn fetch(&mut self, rom: &mut Rom) {
 		// No fetch during post-rendering scanline 240 and vblank interval 241-260
-		if self.scanline >= 240 && self.scanline <= 260 {
+		// and while rendering is disabled
+		if self.is_idle_scanline() || !self.is_rendering_enabled() {
 			return;
 		}
 
@@ -591,15 +708,28 @@ impl Ppu {
 		//     - Attribute table byte
 		//     - Pattern table tile low
 		//     - Pattern table tile high
-		// Cycle 257-320: @TODO
-		// Cycle 321-336: @TODO
-		// Cycle 337-340: @TODO
+		// Cycle 257-320:
+		//   The tile data for the sprites on the next scanline are fetched.
+		//   See fetch_sprite().
+		// Cycle 321-336:
+		//   The first two tiles for the next scanline are fetched.
+		// Cycle 337-340:
+		//   Two nametable bytes are fetched but unused.
+		//   Some mappers like MMC5 watch them.
 
 		if self.cycle == 0 {
 			return;
 		}
 
-		if (self.cycle >= 257 && self.cycle <= 320) || self.cycle >= 337 {
+		if self.cycle >= 257 && self.cycle <= 320 {
+			self.fetch_sprite(rom);
+			return;
+		}
+
+		if self.cycle >= 337 {
+			if self.cycle == 337 || self.cycle == 339 {
+				self.load(0x2000 | (self.current_vram_address & 0x0FFF), rom);
+			}
 			return;
 		}
 
@@ -635,7 +765,51 @@ impl Ppu {
 		};
 	}
 
-	fn fetch_name_table(&mut self, rom: &Rom) {
+	fn is_rendering_enabled(&self) -> bool {
+		self.ppumask.is_background_visible() || self.ppumask.is_sprites_visible()
+	}
+
+	fn fetch_sprite(&mut self, rom: &mut Rom) {
+		// Each sprite takes 8 cycles
+		// 0-1: Garbage nametable byte
+		// 2-3: Garbage nametable byte
+		// 4-5: Pattern table tile low
+		// 6-7: Pattern table tile high
+		// Eight sprites are fetched even if secondary OAM has less.
+		// Tile 0xFF is fetched for the empty slots.
+		let n = ((self.cycle - 257) / 8) as u8;
+		match (self.cycle - 257) % 8 {
+			0 | 2 => {
+				self.load(0x2000 | (self.current_vram_address & 0x0FFF), rom);
+			},
+			4 => {
+				let address = self.get_sprite_pattern_table_address(n);
+				self.sprite_pattern_table_lows[n as usize] = self.load(address, rom);
+			},
+			6 => {
+				let address = self.get_sprite_pattern_table_address(n);
+				self.sprite_pattern_table_highs[n as usize] = self.load(address + 8, rom);
+			},
+			_ => {}
+		};
+	}
+
+	fn get_sprite_pattern_table_address(&self, n: u8) -> u16 {
+		let height = self.ppuctrl.sprite_height();
+		match n < self.sprite_count && self.scanline < 240 {
+			true => {
+				let s = self.secondary_oam.get(n);
+				let y_in_sprite = s.get_y_in_sprite(self.scanline as u8, height);
+				self.get_pattern_table_address_for_sprite(&s, y_in_sprite, height)
+			},
+			false => {
+				let s = Sprite { byte0: 0xFF, byte1: 0xFF, byte2: 0xFF, byte3: 0xFF };
+				self.get_pattern_table_address_for_sprite(&s, 0, height)
+			}
+		}
+	}
+
+	fn fetch_name_table(&mut self, rom: &mut Rom) {
 		// A nametable is a 1024 byte area of memory used by the PPU to lay out backgrounds.
 		// Each byte in the nametable controls one 8x8 pixel character cell, and each nametable
 		// has 30 rows of 32 tiles each, for 960 (0x3C0) bytes; the rest is used by each nametable's
@@ -656,7 +830,7 @@ impl Ppu {
 		self.name_table_latch = self.load(0x2000 | (self.current_vram_address & 0x0FFF), rom);
 	}
 
-	fn fetch_attribute_table(&mut self, rom: &Rom) {
+	fn fetch_attribute_table(&mut self, rom: &mut Rom) {
 		// @TODO: Implement properly
 
 		// The attribute table is a 64-byte array at the end of each nametable
@@ -718,23 +892,23 @@ impl Ppu {
 		};
 	}
 
-	fn fetch_pattern_table_low(&mut self, rom: &Rom) {
+	fn fetch_pattern_table_low(&mut self, rom: &mut Rom) {
 		let fine_scroll_y = (self.current_vram_address >> 12) & 0x7;
 		let index = self.ppuctrl.background_pattern_table_base_address() +
 			((self.name_table.load() as u16) << 4) + fine_scroll_y;
 		self.pattern_table_low_latch = self.load(index, rom);
 	}
 
-	fn fetch_pattern_table_high(&mut self, rom: &Rom) {
+	fn fetch_pattern_table_high(&mut self, rom: &mut Rom) {
 		let fine_scroll_y = (self.current_vram_address >> 12) & 0x7;
 		let index = self.ppuctrl.background_pattern_table_base_address() +
 			((self.name_table.load() as u16) << 4) + fine_scroll_y;
 		self.pattern_table_high_latch = self.load(index + 0x8, rom);
 	}
 
-	fn update_flags(&mut self, rom: &mut Rom) {
+	fn update_flags(&mut self) {
 		if self.cycle == 1 {
-			if self.scanline == 241 {
+			if self.scanline == self.timing.vblank_scanline {
 				// set vblank and occur NMI at cycle 1 in scanline 241
 				if !self.suppress_vblank {
 					self.ppustatus.set_vblank();
@@ -742,7 +916,7 @@ impl Ppu {
 				self.suppress_vblank = false;
 				// Pixels for this frame should be ready so update the display
 				self.display.vblank();
-			} else if self.scanline == 261 {
+			} else if self.is_pre_render_scanline() {
 				// clear vblank, sprite zero hit flag,
 				// and sprite overflow flags at cycle 1 in pre-render line 261
 				self.ppustatus.clear_vblank();
@@ -751,49 +925,6 @@ impl Ppu {
 			}
 		}
 
-		// According to http://wiki.nesdev.com/w/index.php/PPU_frame_timing#VBL_Flag_Timing
-		// reading 0x2002 at cycle=2 and scanline=241 can suppress NMI
-		// so firing NMI at some cycles away not at cycle=1 so far
-
-		// There is a chance that CPU 0x2002 read gets the data vblank flag set
-		// before CPU starts NMI interrupt routine.
-		// CPU instructions take multiple CPU clocks to complete.
-		// If CPU starts an operation of an istruction including 0x2002 read right before
-		// PPU sets vblank flag and fires NMI,
-		// the 0x2002 read gets the data with vblank flag set even before
-		// CPU starts NMI routine.
-		//
-		//    CPU                              PPU
-		// 1. instruction operation start
-		// 2.   - doing something              vblank start and fire NMI
-		// 3.   - read 0x2002 with
-		//        vblank flag set
-		// 4.   - doing something
-		// 5. Notice NMI and start
-		//    NMI routine
-		//
-		// It seems some games rely on this behavior.
-		// To simulate this behavior we fire NMI at cycle=20 so far.
-		// If CPU reads 0x2002 between PPU cycle 3~20 it gets data
-		// vblank flag set before NMI routine.
-		// (reading at cycle 1~2 suppresses NMI, see load_register())
-		// @TODO: Safer and more appropriate approach.
-
-		if self.cycle == 20 && self.scanline == 241 {
-			if self.ppustatus.is_vblank() &&
-				self.ppuctrl.is_nmi_enabled() {
-				self.nmi_interrupted = true;
-			}
-		}
-
-		// @TODO: check this driving IRQ counter for MMC3Mapper timing is correct
-		// @TODO: This is MMC3Mapper specific. Should this be here?
-
-		if self.cycle == 340 && self.scanline <= 240 &&
-			self.ppumask.is_background_visible() &&
-			rom.irq_interrupted() {
-				self.irq_interrupted = true
-		}
 	}
 
 	fn countup_scroll_counters(&mut self) {
@@ -801,11 +932,11 @@ impl Ppu {
 			return;
 		}
 
-		if self.scanline >= 240 && self.scanline <= 260 {
+		if self.is_idle_scanline() {
 			return;
 		}
 
-		if self.scanline == 261 {
+		if self.is_pre_render_scanline() {
 			if self.cycle >= 280 && self.cycle <= 304 {
 				self.current_vram_address &= !0x7BE0;
 				self.current_vram_address |= self.temporal_vram_address & 0x7BE0;
@@ -869,17 +1000,35 @@ impl Ppu {
 		// cycle:    0 - 340
 		// scanline: 0 - 261
 		self.cycle += 1;
-		if self.cycle > 340 {
+
+		// The last cycle of pre-render scanline is skipped
+		// on odd frames if rendering is enabled
+		let skips_cycle = self.timing.skips_odd_frame_dot &&
+			self.cycle == 340 &&
+			self.is_pre_render_scanline() &&
+			(self.frame % 2) == 1 &&
+			(self.ppumask.is_background_visible() || self.ppumask.is_sprites_visible());
+
+		if self.cycle > 340 || skips_cycle {
 			self.cycle = 0;
 			self.scanline += 1;
 
-			if self.scanline > 261 {
+			if self.scanline >= self.timing.scanlines_per_frame {
 				self.scanline = 0;
 				self.frame += 1;
 			}
 		}
 	}
 
+	// Post-render and vblank scanlines
+	fn is_idle_scanline(&self) -> bool {
+		self.scanline >= 240 && self.scanline < self.timing.pre_render_scanline()
+	}
+
+	fn is_pre_render_scanline(&self) -> bool {
+		self.scanline == self.timing.pre_render_scanline()
+	}
+
 	//
 
 	fn increment_vram_address(&mut self) {
@@ -889,10 +1038,10 @@ impl Ppu {
 		self.ppuaddr.store(self.current_vram_address as u8 & 0xFF);
 	}
 
-	fn evaluate_sprites(&mut self, rom: &Rom) {
+	fn evaluate_sprites(&mut self) {
 		// oamaddr is set to 0 during cycle 257-320 of the pre-render and visible scanlines.
 		// @TODO: Optimize
-		if (self.scanline < 240 || self.scanline == 261) &&
+		if (self.scanline < 240 || self.is_pre_render_scanline()) &&
 			self.cycle >= 257 && self.cycle <= 320 {
 			self.oamaddr.store(0);
 		}
@@ -917,92 +1066,94 @@ impl Ppu {
 		} else if self.cycle == 257 {
 			// Evaluate at a time at cycle 257 due to performance
 			// and simplicity so far
-			self.process_sprite_pixels(rom);
+			self.evaluate_sprites_on_scanline();
+		} else if self.cycle == 320 {
+			// Process all bits of a scanline for sprites after fetching
+			// their tiles for the performance and simplicity.
+			self.process_sprite_pixels();
 		}
 	}
 
-	fn process_sprite_pixels(&mut self, rom: &Rom) {
-		for i in 0..self.sprite_availables.len() {
-			self.sprite_availables[i] = false;
-		}
-
+	// Find up to eight sprite on this scan line from primary OAM and
+	// copy them to secondary OAM.
+	fn evaluate_sprites_on_scanline(&mut self) {
 		let y = self.scanline as u8;
 		let height = self.ppuctrl.sprite_height();
-		let mut n = 0;
+		self.sprite_count = 0;
 
-		// Find up to eight sprite on this scan line from primary OAM and
-		// copy them to secondary OAM.
-		// And process all bits of a scanline for sprites here now
-		// for the performance and simplicity.
 		for i in 0..64 {
 			let s = self.primary_oam.get(i);
 			if s.on(y, height) {
-				if n >= 8 {
+				if self.sprite_count >= 8 {
 					// Set sprite overflow flag if
 					// more than eight sprites appear on a scanline
 					self.ppustatus.set_overflow();
 					break;
 				}
-				let base_x = s.get_x();
-				let y_in_sprite = s.get_y_in_sprite(y, height);
-				let msb = s.get_palette_num() as u16;
-				for j in 0..8 {
-					//
-					if base_x as u16 + j as u16 >= 256 {
-						break;
-					}
-					let x = base_x + j;
-					// No override with later sprites
-					if self.sprite_availables[x as usize] {
-						continue;
-					}
-					let x_in_sprite = match s.horizontal_flip() {
-						true => 7 - j,
-						false => j
-					};
-					// pattern table holds the lowest two bits of palette memory address
-					let lsb = self.get_pattern_table_element_for_sprite(&s, x_in_sprite, y_in_sprite, height, rom) as u16;
-					// the lowest two 0 bits means transparent (=no sprite pixel)
-					if lsb != 0 {
-						self.sprite_availables[x as usize] = true;
-						// Sprite palette indices are in 0x3F10-0x3F1F
-						self.sprite_palette_addresses[x as usize] = 0x3F10 | (msb << 2) | lsb;
-						self.sprite_ids[x as usize] = i;
-						self.sprite_priorities[x as usize] = s.get_priority();
-					}
-				}
-				self.secondary_oam.copy(n, s);
-				n += 1;
+				self.sprite_indices[self.sprite_count as usize] = i;
+				self.secondary_oam.copy(self.sprite_count, s);
+				self.sprite_count += 1;
 			}
 		}
 	}
 
-	fn get_name_table_address_with_mirroring(&self, address: u16, rom: &Rom) -> u16 {
-		let name_table_address = address & 0x2C00;
-		(address & 0x3FF) | match rom.mirroring_type() {
-			Mirrorings::SingleScreen => 0x2000,
-			Mirrorings::Horizontal => match name_table_address {
-				0x2000 => 0x2000,
-				0x2400 => 0x2000,
-				0x2800 => 0x2800,
-				_ /* 0x2C00 */ => 0x2800
-			},
-			Mirrorings::Vertical => match name_table_address {
-				0x2000 => 0x2000,
-				0x2400 => 0x2400,
-				0x2800 => 0x2000,
-				_ /* 0x2C00 */ => 0x2400
-			},
-			Mirrorings::FourScreen => name_table_address
+	fn process_sprite_pixels(&mut self) {
+		for i in 0..self.sprite_availables.len() {
+			self.sprite_availables[i] = false;
 		}
+
+		for n in 0..self.sprite_count {
+			let s = self.secondary_oam.get(n);
+			let base_x = s.get_x();
+			let msb = s.get_palette_num() as u16;
+			let lower_bits = self.sprite_pattern_table_lows[n as usize];
+			let higher_bits = self.sprite_pattern_table_highs[n as usize];
+			for j in 0..8 {
+				//
+				if base_x as u16 + j as u16 >= 256 {
+					break;
+				}
+				let x = base_x + j;
+				// No override with later sprites
+				if self.sprite_availables[x as usize] {
+					continue;
+				}
+				let x_in_sprite = match s.horizontal_flip() {
+					true => 7 - j,
+					false => j
+				};
+				// pattern table holds the lowest two bits of palette memory address.
+				// The first 8bytes in a tile are for 0-bit,
+				// while the second 8bytes are for 1-bit of palette memory address
+				let pos = 7 - x_in_sprite; // xxx_bits[7:0] corresponds to x_in_sprite[0:7]
+				let lsb = ((((higher_bits >> pos) & 1) << 1) | ((lower_bits >> pos) & 1)) as u16;
+				// the lowest two 0 bits means transparent (=no sprite pixel)
+				if lsb != 0 {
+					self.sprite_availables[x as usize] = true;
+					// Sprite palette indices are in 0x3F10-0x3F1F
+					self.sprite_palette_addresses[x as usize] = 0x3F10 | (msb << 2) | lsb;
+					self.sprite_ids[x as usize] = self.sprite_indices[n as usize];
+					self.sprite_priorities[x as usize] = s.get_priority();
+				}
+			}
+		}
+	}
+
+	fn get_name_table_address_with_mirroring(&self, address: u16, rom: &Rom) -> (Nametable, u16) {
+		// 0x2000 - 0x23FF: nametable 0
+		// 0x2400 - 0x27FF: nametable 1
+		// 0x2800 - 0x2BFF: nametable 2
+		// 0x2C00 - 0x2FFF: nametable 3
+		// 0x3000 - 0x3EFF: Mirrors of 0x2000 - 0x2EFF
+		let quadrant = (address >> 10) & 3;
+		(rom.nametable_mapping()[quadrant as usize], address & 0x3FF)
 	}
 
-	fn get_pattern_table_element_for_sprite(&self, s: &Sprite, x_in_sprite: u8, y_in_sprite: u8, height: u8, rom: &Rom) -> u8 {
-		// Get an element from pattern table consisting of the lowest two bits
-		// of palette memory address for sprites
+	fn get_pattern_table_address_for_sprite(&self, s: &Sprite, y_in_sprite: u8, height: u8) -> u16 {
+		// Get the address of the lower row in pattern table for sprites
 
 		// 8x8 sprite and 8x16 sprite calculates tile address differently
-		let address = match height == 8 {
+		match height == 8 {
 			true => {
 				// 8x8 sprite
 				// ppuctrl selects base address 0x0000 or 0x1000
@@ -1025,15 +1176,7 @@ impl Ppu {
 				let row = ((y_in_sprite % 8) + ((y_in_sprite & 0x8) << 1)) as u16;
 				base_address + byte_offset + row
 			}
-		};
-
-		// Each tile has 16bytes (8x2 rows)
-		// The first 8bytes in a tile are for 0-bit,
-		// while the second 8bytes are for 1-bit of palette memory address
-		let lower_bits = self.load(address, rom);
-		let higher_bits = self.load(address + 8, rom);
-		let pos = 7 - x_in_sprite; // xxx_bits[7:0] corresponds to x_in_sprite[0:7] 
-		(((higher_bits >> pos) & 1) << 1) | ((lower_bits >> pos) & 1)
+		}
 	}
 
 	fn load_palette(&self, address: u8) -> u32 {
@@ -1043,16 +1186,25 @@ impl Ppu {
 			true => 0x30,
 			false => 0xFF
 		};
-		PALETTES[(address & mask) as usize] & 0xFFFFFF
+		let palettes = match self.region {
+			Region::Ntsc => &NTSC_PALETTES,
+			_ => &PAL_PALETTES
+		};
+		palettes[(address & mask) as usize] & 0xFFFFFF
 	}
 
 	fn get_emphasis_color(&self, mut c: u32) -> u32 {
 		// Color emphasis bases on ppumask
 		// @TODO: Implement properly
-		if self.ppumask.is_emphasis_red() {
+		// PAL and Dendy PPUs swap red and green emphasis bits
+		let (emphasis_red, emphasis_green) = match self.region {
+			Region::Ntsc => (self.ppumask.is_emphasis_red(), self.ppumask.is_emphasis_green()),
+			_ => (self.ppumask.is_emphasis_green(), self.ppumask.is_emphasis_red())
+		};
+		if emphasis_red {
 			c = c | 0x00FF0000;
 		}
-		if self.ppumask.is_emphasis_green() {
+		if emphasis_green {
 			c = c | 0x0000FF00;
 		}
 		if self.ppumask.is_emphasis_blue() {
@@ -1064,6 +1216,100 @@ impl Ppu {
 	pub fn get_display(&self) -> &Box<dyn Display> {
 		&self.display
 	}
+
+	pub fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_u32(self.frame);
+		writer.write_u16(self.cycle);
+		writer.write_u16(self.scanline);
+		writer.write_bool(self.suppress_vblank);
+		writer.write_bool(self.register_first_store);
+		writer.write_u8(self.fine_x_scroll);
+		writer.write_u8(self.name_table_latch);
+		writer.write_register_u8(&self.name_table);
+		writer.write_u8(self.pattern_table_low_latch);
+		writer.write_u8(self.pattern_table_high_latch);
+		writer.write_register_u16(&self.pattern_table_low);
+		writer.write_register_u16(&self.pattern_table_high);
+		writer.write_u8(self.attribute_table_low_latch);
+		writer.write_u8(self.attribute_table_high_latch);
+		writer.write_register_u16(&self.attribute_table_low);
+		writer.write_register_u16(&self.attribute_table_high);
+		writer.write_u16(self.current_vram_address);
+		writer.write_u16(self.temporal_vram_address);
+		writer.write_u8(self.vram_read_buffer);
+		writer.write_memory(&self.vram);
+		for i in 0..256 {
+			writer.write_bool(self.sprite_availables[i]);
+			writer.write_u8(self.sprite_ids[i]);
+			writer.write_u16(self.sprite_palette_addresses[i]);
+			writer.write_u8(self.sprite_priorities[i]);
+		}
+		writer.write_memory(&self.primary_oam.memory);
+		writer.write_memory(&self.secondary_oam.memory);
+		writer.write_u8(self.sprite_count);
+		writer.write_bytes(&self.sprite_indices);
+		writer.write_bytes(&self.sprite_pattern_table_lows);
+		writer.write_bytes(&self.sprite_pattern_table_highs);
+		writer.write_register_u8(&self.ppuctrl.register);
+		writer.write_register_u8(&self.ppumask.register);
+		writer.write_register_u8(&self.ppustatus.register);
+		writer.write_register_u8(&self.oamaddr);
+		writer.write_register_u8(&self.oamdata);
+		writer.write_register_u8(&self.ppuscroll);
+		writer.write_register_u8(&self.ppuaddr);
+		writer.write_register_u8(&self.ppudata);
+		writer.write_register_u8(&self.oamdma);
+		writer.write_u8(self.data_bus);
+	}
+
+	pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		self.frame = reader.read_u32()?;
+		self.cycle = reader.read_u16()?;
+		self.scanline = reader.read_u16()?;
+		self.suppress_vblank = reader.read_bool()?;
+		self.register_first_store = reader.read_bool()?;
+		self.fine_x_scroll = reader.read_u8()?;
+		self.name_table_latch = reader.read_u8()?;
+		reader.read_register_u8(&mut self.name_table)?;
+		self.pattern_table_low_latch = reader.read_u8()?;
+		self.pattern_table_high_latch = reader.read_u8()?;
+		reader.read_register_u16(&mut self.pattern_table_low)?;
+		reader.read_register_u16(&mut self.pattern_table_high)?;
+		self.attribute_table_low_latch = reader.read_u8()?;
+		self.attribute_table_high_latch = reader.read_u8()?;
+		reader.read_register_u16(&mut self.attribute_table_low)?;
+		reader.read_register_u16(&mut self.attribute_table_high)?;
+		self.current_vram_address = reader.read_u16()?;
+		self.temporal_vram_address = reader.read_u16()?;
+		self.vram_read_buffer = reader.read_u8()?;
+		reader.read_memory(&mut self.vram)?;
+		for i in 0..256 {
+			self.sprite_availables[i] = reader.read_bool()?;
+			self.sprite_ids[i] = reader.read_u8()?;
+			self.sprite_palette_addresses[i] = reader.read_u16()?;
+			self.sprite_priorities[i] = reader.read_u8()?;
+		}
+		reader.read_memory(&mut self.primary_oam.memory)?;
+		reader.read_memory(&mut self.secondary_oam.memory)?;
+		self.sprite_count = reader.read_u8()?;
+		if self.sprite_count > 8 {
+			return Err(StateError::InvalidData);
+		}
+		reader.read_bytes_into(&mut self.sprite_indices)?;
+		reader.read_bytes_into(&mut self.sprite_pattern_table_lows)?;
+		reader.read_bytes_into(&mut self.sprite_pattern_table_highs)?;
+		reader.read_register_u8(&mut self.ppuctrl.register)?;
+		reader.read_register_u8(&mut self.ppumask.register)?;
+		reader.read_register_u8(&mut self.ppustatus.register)?;
+		reader.read_register_u8(&mut self.oamaddr)?;
+		reader.read_register_u8(&mut self.oamdata)?;
+		reader.read_register_u8(&mut self.ppuscroll)?;
+		reader.read_register_u8(&mut self.ppuaddr)?;
+		reader.read_register_u8(&mut self.ppudata)?;
+		reader.read_register_u8(&mut self.oamdma)?;
+		self.data_bus = reader.read_u8()?;
+		Ok(())
+	}
 }
 
 // PPU control 8-bit register.
@@ -1250,7 +1496,7 @@ impl PpuStatusRegister {
 		self.register.clear_bit(7);
 	}
 
-	fn is_vblank(&mut self) -> bool {
+	fn is_vblank(&self) -> bool {
 		self.register.is_bit_set(7)
 	}
 
@@ -1365,7 +1611,7 @@ impl Sprite {
 	}
 
 	fn on(&self, y: u8, height: u8) -> bool {
-		(y >= self.get_y()) && (y < self.get_y() + height)
+		(y >= self.get_y()) && ((y as u16) < self.get_y() as u16 + height as u16)
 	}
 
 	fn get_y_in_sprite(&self, y: u8, height: u8) -> u8 {
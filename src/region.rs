@@ -0,0 +1,177 @@
+use rom::{RomHeader, Timing};
+
+/**
+ * Console regions. NTSC for North America and Japan, PAL for Europe
+ * and Australia, and Dendy, a famous Famicom clone with PAL timing
+ * compatible with NTSC games, for Russia.
+ * Refer to https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+	Ntsc,
+	Pal,
+	Dendy
+}
+
+impl Region {
+	/// Detects the region from NES 2.0 header. iNES 1.0 roms and
+	/// roms working on multiple regions run as NTSC.
+	pub fn from_header(header: &RomHeader) -> Self {
+		if !header.is_nes2() {
+			return Region::Ntsc;
+		}
+		match header.timing() {
+			Timing::Pal => Region::Pal,
+			Timing::Dendy => Region::Dendy,
+			_ => Region::Ntsc
+		}
+	}
+
+	pub fn timing(&self) -> RegionTiming {
+		match self {
+			Region::Ntsc => NTSC_TIMING,
+			Region::Pal => PAL_TIMING,
+			Region::Dendy => DENDY_TIMING
+		}
+	}
+}
+
+/**
+ * Timing constants of a console region. CPU and PPU are driven by
+ * the master clock and the dividers decide their ratio.
+ * All the emulator timing should come from here.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegionTiming {
+	// Master clock frequency in Hz
+	pub master_clock_rate: u64,
+
+	// CPU runs at master clock / cpu_clock_divider and
+	// PPU runs at master clock / ppu_clock_divider
+	pub cpu_clock_divider: u64,
+	pub ppu_clock_divider: u64,
+
+	// Master clocks in the first and second halves of a CPU cycle.
+	// The sum is cpu_clock_divider. CPU reads at
+	// (cpu_start_clock_count - 1) and writes at (cpu_start_clock_count + 1)
+	// master clocks from the start of the cycle.
+	pub cpu_start_clock_count: u64,
+	pub cpu_end_clock_count: u64,
+
+	// PPU runs behind CPU by this many master clocks
+	pub ppu_clock_offset: u64,
+
+	// Including post-render, vblank and pre-render scanlines.
+	// The last scanline is pre-render scanline.
+	pub scanlines_per_frame: u16,
+
+	// Vblank flag is set and NMI fires at dot 1 of this scanline
+	pub vblank_scanline: u16,
+
+	// The pre-render scanline is one dot shorter
+	// on odd frames while rendering is enabled
+	pub skips_odd_frame_dot: bool,
+
+	// CPU cycles of the APU frame sequencer steps
+	pub four_step_sequence_cycles: [u32; 6],
+	pub five_step_sequence_cycles: [u32; 6]
+}
+
+impl RegionTiming {
+	// CPU clock frequency in Hz
+	pub fn cpu_clock_rate(&self) -> u64 {
+		self.master_clock_rate / self.cpu_clock_divider
+	}
+
+	pub fn pre_render_scanline(&self) -> u16 {
+		self.scanlines_per_frame - 1
+	}
+}
+
+pub const NTSC_TIMING: RegionTiming = RegionTiming {
+	master_clock_rate: 21477272,
+	cpu_clock_divider: 12,
+	ppu_clock_divider: 4,
+	cpu_start_clock_count: 6,
+	cpu_end_clock_count: 6,
+	ppu_clock_offset: 1,
+	scanlines_per_frame: 262,
+	vblank_scanline: 241,
+	skips_odd_frame_dot: true,
+	four_step_sequence_cycles: [7457, 14913, 22371, 29828, 29829, 29830],
+	five_step_sequence_cycles: [7457, 14913, 22371, 29829, 37281, 37282]
+};
+
+pub const PAL_TIMING: RegionTiming = RegionTiming {
+	master_clock_rate: 26601712,
+	cpu_clock_divider: 16,
+	ppu_clock_divider: 5,
+	cpu_start_clock_count: 8,
+	cpu_end_clock_count: 8,
+	ppu_clock_offset: 1,
+	scanlines_per_frame: 312,
+	vblank_scanline: 241,
+	skips_odd_frame_dot: false,
+	four_step_sequence_cycles: [8313, 16627, 24939, 33252, 33253, 33254],
+	five_step_sequence_cycles: [8313, 16627, 24939, 33253, 41565, 41566]
+};
+
+// Dendy has PAL master clock and PAL length frame but runs CPU faster
+// than PAL and starts vblank 51 scanlines after the visible scanlines
+// so NTSC games work. APU is the same as NTSC.
+pub const DENDY_TIMING: RegionTiming = RegionTiming {
+	master_clock_rate: 26601712,
+	cpu_clock_divider: 15,
+	ppu_clock_divider: 5,
+	cpu_start_clock_count: 7,
+	cpu_end_clock_count: 8,
+	ppu_clock_offset: 1,
+	scanlines_per_frame: 312,
+	vblank_scanline: 291,
+	skips_odd_frame_dot: false,
+	four_step_sequence_cycles: [7457, 14913, 22371, 29828, 29829, 29830],
+	five_step_sequence_cycles: [7457, 14913, 22371, 29829, 37281, 37282]
+};
+
+#[cfg(test)]
+mod tests_region {
+	use super::*;
+	use rom::{HEADER_SIZE, Rom};
+
+	fn detect(nes2: bool, byte12: u8) -> Region {
+		let mut v = vec![0; HEADER_SIZE + 0x4000 + 0x2000];
+		v[0] = 0x4e; // N
+		v[1] = 0x45; // E
+		v[2] = 0x53; // S
+		v[3] = 0x1a; // magic number
+		v[4] = 1;
+		v[5] = 1;
+		if nes2 {
+			v[7] = 0x08;
+		}
+		v[12] = byte12;
+		Region::from_header(Rom::parse(v).unwrap().header())
+	}
+
+	#[test]
+	fn from_header() {
+		assert_eq!(Region::Ntsc, detect(true, 0));
+		assert_eq!(Region::Pal, detect(true, 1));
+		assert_eq!(Region::Ntsc, detect(true, 2));
+		assert_eq!(Region::Dendy, detect(true, 3));
+		assert_eq!(Region::Ntsc, detect(false, 1));
+	}
+
+	#[test]
+	fn timing() {
+		for region in [Region::Ntsc, Region::Pal, Region::Dendy].iter() {
+			let timing = region.timing();
+			assert_eq!(timing.cpu_clock_divider,
+				timing.cpu_start_clock_count + timing.cpu_end_clock_count);
+		}
+		// About 1.79MHz, 1.66MHz and 1.77MHz
+		assert_eq!(1789772, Region::Ntsc.timing().cpu_clock_rate());
+		assert_eq!(1662607, Region::Pal.timing().cpu_clock_rate());
+		assert_eq!(1773447, Region::Dendy.timing().cpu_clock_rate());
+	}
+}
@@ -0,0 +1,334 @@
+use std::collections::VecDeque;
+
+/**
+ * Rewind buffer. Holds snapshots (save states) taken every
+ * `frame_interval` frames in a ring buffer bounded by `memory_budget` bytes.
+ *
+ * Snapshots are grouped. The first snapshot of a group is a keyframe,
+ * a whole save state. The following snapshots are deltas, XOR of the
+ * save state and the keyframe, which are mostly zero because only
+ * a small part of the machine changes in a few seconds.
+ * Both are compressed with run length encoding.
+ * When the buffer exceeds the budget the oldest group is dropped.
+ */
+pub struct Rewind {
+	frame_interval: u32,
+	keyframe_interval: u32,
+	memory_budget: usize,
+
+	// Frames recorded so far. Rewinding moves it back.
+	frame: u64,
+
+	groups: VecDeque<SnapshotGroup>,
+
+	// Uncompressed keyframe of the newest group to encode deltas against
+	keyframe: Vec<u8>,
+	used_memory: usize
+}
+
+struct SnapshotGroup {
+	keyframe: Snapshot,
+	deltas: Vec<Snapshot>
+}
+
+struct Snapshot {
+	frame: u64,
+	data: Vec<u8>
+}
+
+pub const DEFAULT_FRAME_INTERVAL: u32 = 2;
+pub const DEFAULT_MEMORY_BUDGET: usize = 32 * 1024 * 1024; // 32MB
+
+// Snapshots per group including its keyframe
+const DEFAULT_KEYFRAME_INTERVAL: u32 = 60;
+
+impl Rewind {
+	pub fn new(frame_interval: u32, memory_budget: usize) -> Self {
+		Rewind {
+			frame_interval: match frame_interval {
+				0 => 1,
+				_ => frame_interval
+			},
+			keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+			memory_budget: memory_budget,
+			frame: 0,
+			groups: VecDeque::new(),
+			keyframe: Vec::new(),
+			used_memory: 0
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.frame = 0;
+		self.groups.clear();
+		self.keyframe.clear();
+		self.used_memory = 0;
+	}
+
+	pub fn used_memory(&self) -> usize {
+		self.used_memory
+	}
+
+	pub fn frame_interval(&self) -> u32 {
+		self.frame_interval
+	}
+
+	// Counts up a frame and returns true if a snapshot should be taken at this frame
+	pub fn countup_frame(&mut self) -> bool {
+		self.frame += 1;
+		matches!(self.frame % self.frame_interval as u64, 0)
+	}
+
+	pub fn push(&mut self, state: Vec<u8>) {
+		let needs_keyframe = match self.groups.back() {
+			Some(group) => group.deltas.len() + 1 >= self.keyframe_interval as usize ||
+				self.keyframe.len() != state.len(),
+			None => true
+		};
+
+		if needs_keyframe {
+			let snapshot = Snapshot {
+				frame: self.frame,
+				data: compress(&state)
+			};
+			self.used_memory += snapshot.data.len();
+			self.groups.push_back(SnapshotGroup {
+				keyframe: snapshot,
+				deltas: Vec::new()
+			});
+			self.keyframe = state;
+		} else {
+			let snapshot = Snapshot {
+				frame: self.frame,
+				data: compress(&xor(&state, &self.keyframe))
+			};
+			self.used_memory += snapshot.data.len();
+			self.groups.back_mut().unwrap().deltas.push(snapshot);
+		}
+
+		// Drops the oldest groups but always keeps the newest one
+		while self.used_memory > self.memory_budget && self.groups.len() > 1 {
+			let group = self.groups.pop_front().unwrap();
+			self.used_memory -= group.size();
+		}
+	}
+
+	// Discards the snapshots newer than `frames` frames before and
+	// returns the newest remaining save state. If `frames` goes beyond
+	// the oldest snapshot returns the oldest one. The returned snapshot is
+	// kept so that rewinding again goes further back from it.
+	pub fn rewind(&mut self, frames: u32) -> Option<Vec<u8>> {
+		let oldest_frame = match self.groups.front() {
+			Some(group) => group.keyframe.frame,
+			None => return None
+		};
+		let target_frame = match self.frame.checked_sub(frames as u64) {
+			Some(frame) if frame > oldest_frame => frame,
+			_ => oldest_frame
+		};
+
+		loop {
+			let found = match self.groups.back_mut() {
+				Some(group) => {
+					while let Some(snapshot) = group.deltas.pop() {
+						if snapshot.frame <= target_frame {
+							group.deltas.push(snapshot);
+							break;
+						}
+						self.used_memory -= snapshot.data.len();
+					}
+					!group.deltas.is_empty() || group.keyframe.frame <= target_frame
+				},
+				None => unreachable!()
+			};
+
+			if found {
+				break;
+			}
+
+			let group = self.groups.pop_back().unwrap();
+			self.used_memory -= group.size();
+			self.keyframe = match self.groups.back() {
+				Some(group) => decompress(&group.keyframe.data),
+				None => Vec::new()
+			};
+		}
+
+		let group = self.groups.back().unwrap();
+		let (frame, state) = match group.deltas.last() {
+			Some(snapshot) => (snapshot.frame, xor(&decompress(&snapshot.data), &self.keyframe)),
+			None => (group.keyframe.frame, self.keyframe.clone())
+		};
+		self.frame = frame;
+		Some(state)
+	}
+}
+
+impl SnapshotGroup {
+	fn size(&self) -> usize {
+		self.deltas.iter().fold(self.keyframe.data.len(), |sum, s| sum + s.data.len())
+	}
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+	a.iter().zip(b.iter()).map(|(x, y)| x ^ y).collect()
+}
+
+/**
+ * Run length encoding.
+ * 0x00 - 0x7F: (n + 1) literal bytes follow
+ * 0x80 - 0xFF: The following byte repeats (n - 0x7E) times, 2 - 129
+ */
+fn compress(data: &[u8]) -> Vec<u8> {
+	let mut result = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let mut run = 1;
+		while i + run < data.len() && run < 129 && data[i + run] == data[i] {
+			run += 1;
+		}
+
+		if run >= 2 {
+			result.push((run + 0x7E) as u8);
+			result.push(data[i]);
+			i += run;
+			continue;
+		}
+
+		// Collects literal bytes until a run of two or more same bytes appears
+		let start = i;
+		while i < data.len() && i - start < 128 &&
+			!(i + 1 < data.len() && data[i] == data[i + 1]) {
+			i += 1;
+		}
+		result.push((i - start - 1) as u8);
+		result.extend_from_slice(&data[start..i]);
+	}
+	result
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+	let mut result = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let control = data[i] as usize;
+		if control < 0x80 {
+			result.extend_from_slice(&data[i + 1..i + 2 + control]);
+			i += control + 2;
+		} else {
+			for _ in 0..control - 0x7E {
+				result.push(data[i + 1]);
+			}
+			i += 2;
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests_rewind {
+	use super::*;
+	use Nes;
+	use button::Button;
+	use default_input::DefaultInput;
+	use default_audio::DefaultAudio;
+	use default_display::DefaultDisplay;
+	use rom::HEADER_SIZE;
+
+	#[test]
+	fn compress_and_decompress() {
+		let data = vec![0, 0, 0, 1, 2, 3, 3, 4, 5, 5, 5, 5, 6];
+		assert_eq!(data, decompress(&compress(&data)));
+		let long_run = vec![7; 1000];
+		assert_eq!(long_run, decompress(&compress(&long_run)));
+		let literals = (0..1000).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+		assert_eq!(literals, decompress(&compress(&literals)));
+		assert_eq!(Vec::<u8>::new(), decompress(&compress(&[])));
+	}
+
+	#[test]
+	fn countup_frame() {
+		let mut r = Rewind::new(3, 1024);
+		assert_eq!(false, r.countup_frame());
+		assert_eq!(false, r.countup_frame());
+		assert_eq!(true, r.countup_frame());
+	}
+
+	#[test]
+	fn rewind() {
+		let mut r = Rewind::new(1, 1024 * 1024);
+		for i in 1..=100 {
+			r.countup_frame();
+			r.push(vec![i as u8; 64]);
+		}
+		assert_eq!(Some(vec![90; 64]), r.rewind(10));
+		assert_eq!(Some(vec![89; 64]), r.rewind(1));
+		assert_eq!(Some(vec![50; 64]), r.rewind(39));
+		r.countup_frame();
+		r.push(vec![0xFF; 64]);
+		assert_eq!(Some(vec![0xFF; 64]), r.rewind(0));
+		assert_eq!(Some(vec![50; 64]), r.rewind(1));
+		assert_eq!(Some(vec![1; 64]), r.rewind(100));
+		assert_eq!(Some(vec![1; 64]), r.rewind(1));
+		r.clear();
+		assert_eq!(None, r.rewind(1));
+	}
+
+	#[test]
+	fn memory_budget() {
+		let mut r = Rewind::new(1, 256);
+		for i in 1..=1000 {
+			r.countup_frame();
+			r.push((0..64).map(|j| (i * j) as u8).collect());
+		}
+		assert_eq!(true, r.used_memory() <= 256 || r.groups.len() == 1);
+		// Rewinds to the oldest snapshot remaining in the budget
+		let oldest_frame = r.groups.front().unwrap().keyframe.frame;
+		assert_eq!(true, oldest_frame > 1);
+		assert_eq!(Some((0..64).map(|j| (oldest_frame as usize * j) as u8).collect()), r.rewind(999));
+	}
+
+	fn nes() -> Nes {
+		let mut nes = Nes::new(
+			Box::new(DefaultInput::new()),
+			Box::new(DefaultDisplay::new()),
+			Box::new(DefaultAudio::new())
+		);
+		let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1];
+		rom.resize(HEADER_SIZE + 0x4000, 0);
+		nes.set_rom(rom).unwrap();
+		nes.bootup();
+		nes
+	}
+
+	#[test]
+	fn step_frame_rewinding() {
+		let mut nes = nes();
+		nes.enable_rewind(2, DEFAULT_MEMORY_BUDGET);
+		for _ in 0..20 {
+			nes.step_frame();
+		}
+		let frame_offset = nes.rewind.as_ref().unwrap().frame - nes.cpu.get_ppu().frame as u64;
+
+		// Goes back by a snapshot interval per call
+		nes.press_button(Button::Rewind);
+		for _ in 0..3 {
+			nes.step_frame();
+		}
+		nes.release_button(Button::Rewind);
+		assert_eq!(frame_offset, nes.rewind.as_ref().unwrap().frame - nes.cpu.get_ppu().frame as u64);
+		assert_eq!(13, nes.rewind.as_ref().unwrap().frame);
+
+		// Snapshots recorded after rewinding are at the machine frames
+		for _ in 0..10 {
+			nes.step_frame();
+		}
+		assert_eq!(frame_offset, nes.rewind.as_ref().unwrap().frame - nes.cpu.get_ppu().frame as u64);
+		nes.step_frame();
+		let ppu_frame = nes.cpu.get_ppu().frame;
+		nes.step_frame();
+		nes.step_frame();
+		assert_eq!(true, nes.rewind(2));
+		assert_eq!(ppu_frame, nes.cpu.get_ppu().frame);
+	}
+}
@@ -1,83 +1,393 @@
-use memory::Memory;
+use std::error;
+use std::fmt;
+
+use cartridge::Cartridge;
 use mapper::{Mapper, MapperFactory};
+use memory::Memory;
+use state::{StateError, StateReader, StateWriter};
 
 pub struct Rom {
 	header: RomHeader,
-	memory: Memory,
-	mapper: Box<dyn Mapper>
+	cartridge: Cartridge,
+	// 512 bytes placed between the header and PRG-ROM, loaded into 0x7000 - 0x71FF
+	trainer: Option<Vec<u8>>,
+	mapper: Box<dyn Mapper>,
+	// Identifies the rom image a save state is made with
+	checksum: u32
 }
 
 pub static HEADER_SIZE: usize = 16;
+pub static TRAINER_SIZE: usize = 512;
+pub static PRG_ROM_BANK_SIZE: usize = 0x4000;
+pub static CHR_ROM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug, PartialEq)]
+pub enum RomError {
+	// The data is shorter than the iNES header
+	TruncatedHeader,
+	// The data doesn't start with "NES" and 0x1A
+	InvalidMagic,
+	// The data ends before the program rom the header declares
+	PrgRomSizeMismatch { expected: usize, actual: usize },
+	// The data ends before the character rom the header declares
+	ChrRomSizeMismatch { expected: usize, actual: usize },
+	// The header declares a 512-byte trainer but the data ends in it
+	TrainerOverflow,
+	UnsupportedMapper(u16)
+}
+
+impl fmt::Display for RomError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RomError::TruncatedHeader => write!(f, "Rom image is shorter than the header"),
+			RomError::InvalidMagic => write!(f, "Not an iNES rom image"),
+			RomError::PrgRomSizeMismatch { expected, actual } => write!(f,
+				"Program rom is {} bytes but the header declares {} bytes", actual, expected),
+			RomError::ChrRomSizeMismatch { expected, actual } => write!(f,
+				"Character rom is {} bytes but the header declares {} bytes", actual, expected),
+			RomError::TrainerOverflow => write!(f, "Rom image ends in the trainer"),
+			RomError::UnsupportedMapper(num) => write!(f, "Unsupported mapper {}", num)
+		}
+	}
+}
 
-pub enum Mirrorings {
-	SingleScreen,
-	Horizontal,
-	Vertical,
-	FourScreen
+impl error::Error for RomError {}
+
+/**
+ * 1KB memory which a quadrant of the PPU nametable space is mapped to.
+ * 0x2000 - 0x23FF, 0x2400 - 0x27FF, 0x2800 - 0x2BFF and 0x2C00 - 0x2FFF
+ * are the quadrants 0-3.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Nametable {
+	// Page 0 or 1 of the console's internal 2KB VRAM (CIRAM)
+	Ciram(u8),
+	// Page of the nametable RAM on the cartridge
+	CartridgeRam(u8),
+	// MMC5 expansion RAM
+	ExRam,
+	// MMC5 fill mode, a fixed tile and attribute
+	Fill,
+	// 1KB bank of CHR-ROM, Namco 163
+	ChrRom(u16)
+}
+
+pub type NametableMapping = [Nametable; 4];
+
+pub const HORIZONTAL_MIRRORING: NametableMapping =
+	[Nametable::Ciram(0), Nametable::Ciram(0), Nametable::Ciram(1), Nametable::Ciram(1)];
+pub const VERTICAL_MIRRORING: NametableMapping =
+	[Nametable::Ciram(0), Nametable::Ciram(1), Nametable::Ciram(0), Nametable::Ciram(1)];
+pub const SINGLE_SCREEN_A: NametableMapping =
+	[Nametable::Ciram(0), Nametable::Ciram(0), Nametable::Ciram(0), Nametable::Ciram(0)];
+pub const SINGLE_SCREEN_B: NametableMapping =
+	[Nametable::Ciram(1), Nametable::Ciram(1), Nametable::Ciram(1), Nametable::Ciram(1)];
+// The cartridge has 2KB RAM for the other two nametables
+pub const FOUR_SCREEN: NametableMapping =
+	[Nametable::Ciram(0), Nametable::Ciram(1), Nametable::CartridgeRam(0), Nametable::CartridgeRam(1)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+	Ntsc,
+	Pal,
+	// Works on both NTSC and PAL machines
+	MultipleRegion,
+	Dendy
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+	Nes,
+	VsSystem,
+	Playchoice10,
+	// Extended console type in byte 13 of NES 2.0 header
+	Extended(u8)
 }
 
 impl Rom {
+	/**
+	 * Creates a Rom without validating the image.
+	 * Panics if the data is shorter than the header or
+	 * the mapper is unsupported. Use Rom::parse() for
+	 * user-supplied images.
+	 */
 	pub fn new(data: Vec<u8>) -> Self {
 		let header = RomHeader::new(data[0..HEADER_SIZE].to_vec());
-		let mapper = MapperFactory::create(&header);
-		Rom {
-			header: header,
-			memory: Memory::new(data[HEADER_SIZE..].to_vec()),
-			mapper: mapper
+		match Rom::build(header, data) {
+			Ok(rom) => rom,
+			Err(e) => panic!("{}", e)
 		}
 	}
 
 	/**
-	 * CPU memory address:
-	 * 0x0000 - 0x1FFF: Character ROM access
-	 * 0x8000 - 0xFFFF: Program ROM access
+	 * Parses an iNES rom image. Returns an error if the image is
+	 * broken or uses a mapper which isn't supported yet.
+	 */
+	pub fn parse(data: Vec<u8>) -> Result<Self, RomError> {
+		if data.len() < HEADER_SIZE {
+			return Err(RomError::TruncatedHeader);
+		}
+
+		let header = RomHeader::new(data[0..HEADER_SIZE].to_vec());
+		if !header.is_nes() {
+			return Err(RomError::InvalidMagic);
+		}
+
+		let mut offset = HEADER_SIZE;
+		if header.has_trainer() {
+			offset += TRAINER_SIZE;
+			if data.len() < offset {
+				return Err(RomError::TrainerOverflow);
+			}
+		}
+
+		let prg_rom_size = header.prg_rom_size();
+		if data.len() - offset < prg_rom_size {
+			return Err(RomError::PrgRomSizeMismatch {
+				expected: prg_rom_size,
+				actual: data.len() - offset
+			});
+		}
+		offset += prg_rom_size;
+
+		let chr_rom_size = header.chr_rom_size();
+		if data.len() - offset < chr_rom_size {
+			return Err(RomError::ChrRomSizeMismatch {
+				expected: chr_rom_size,
+				actual: data.len() - offset
+			});
+		}
+
+		Rom::build(header, data)
+	}
+
+	fn build(header: RomHeader, data: Vec<u8>) -> Result<Self, RomError> {
+		let mapper = MapperFactory::create(&header)?;
+		let (trainer, prg_rom_offset) = match header.has_trainer() {
+			true => {
+				let end = data.len().min(HEADER_SIZE + TRAINER_SIZE);
+				(Some(data[HEADER_SIZE..end].to_vec()), end)
+			},
+			false => (None, HEADER_SIZE)
+		};
+		// Rom::new() accepts truncated images
+		let chr_rom_offset = data.len().min(prg_rom_offset.saturating_add(header.prg_rom_size()));
+		let chr_rom_end = data.len().min(chr_rom_offset.saturating_add(header.chr_rom_size()));
+		// iNES 1.0 can't tell CHR-RAM size of the boards having
+		// more than 8KB CHR-RAM or both CHR-ROM and CHR-RAM
+		let chr_ram_size = match (header.is_nes2(), header.mapper_num()) {
+			(false, 30) => 0x8000, // UNROM 512
+			(false, 119) => 0x2000, // TQROM
+			_ => header.chr_ram_size() + header.chr_nvram_size()
+		};
+		let nametable_ram_size = match header.four_screen_mirroring() {
+			true => 0x800,
+			false => 0
+		};
+		// iNES 1.0 can't tell PRG-RAM larger than 8KB either. MMC5 boards
+		// have up to 64KB and some Koei games need 32KB.
+		let (prg_ram_size, prg_nvram_size) = match (header.is_nes2(), header.mapper_num()) {
+			(false, 5) => match header.has_battery() {
+				true => (0, 0x10000),
+				false => (0x10000, 0)
+			},
+			_ => (header.prg_ram_size(), header.prg_nvram_size())
+		};
+		// 128 bytes PRG-NVRAM of Namco 163 in NES 2.0 header is the
+		// battery-backed internal RAM of the chip, not at 0x6000
+		let prg_nvram_size = match (header.mapper_num(), prg_nvram_size) {
+			(19, 0x80) => 0,
+			(_, size) => size
+		};
+		let cartridge = Cartridge::new(
+			data[prg_rom_offset..chr_rom_offset].to_vec(),
+			data[chr_rom_offset..chr_rom_end].to_vec(),
+			chr_ram_size,
+			prg_ram_size,
+			prg_nvram_size,
+			nametable_ram_size
+		);
+		Ok(Rom {
+			header: header,
+			cartridge: cartridge,
+			trainer: trainer,
+			mapper: mapper,
+			checksum: calculate_checksum(&data)
+		})
+	}
+
+	/**
+	 * Memory address:
+	 * 0x0000 - 0x1FFF: CHR-ROM or CHR-RAM access from PPU
+	 * 0x4020 - 0x5FFF: Expansion area access from CPU
+	 * 0x6000 - 0x7FFF: PRG-RAM access from CPU
+	 * 0x8000 - 0xFFFF: PRG-ROM access from CPU
 	 *
 	 * To access wide range ROM data with limited CPU memory address space
 	 * Mapper maps CPU memory address to ROM's.
 	 * In general writing control registers in Mapper via .store() switches bank.
 	 */
-	pub fn load(&self, address: u32) -> u8 {
-		let mut address_in_rom = 0 as u32;
+	pub fn load(&mut self, address: u32) -> u8 {
 		if address < 0x2000 {
-			// load from character rom
-			address_in_rom += self.header.prg_rom_bank_num() as u32 * 0x4000;
-			address_in_rom += self.mapper.map_for_chr_rom(address);
-		} else {
-			address_in_rom += self.mapper.map(address);
+			return self.mapper.load_chr(&self.cartridge, address);
 		}
-		self.memory.load(address_in_rom)
+		self.mapper.load_prg(&self.cartridge, address)
 	}
 
 	pub fn load_without_mapping(&self, address: u32) -> u8 {
-		self.memory.load(address)
+		self.cartridge.load_prg_rom(address)
 	}
 
 	/**
 	 * In general writing with ROM address space updates control registers in Mapper.
 	 */
 	pub fn store(&mut self, address: u32, value: u8) {
-		self.mapper.store(address, value);
+		if address < 0x2000 {
+			self.mapper.store_chr(&mut self.cartridge, address, value);
+			return;
+		}
+		self.mapper.store_prg(&mut self.cartridge, address, value);
+	}
+
+	// Access to the nametables on the cartridge. address is the offset in 1KB.
+	pub fn load_nametable(&self, nametable: Nametable, address: u32) -> u8 {
+		self.mapper.load_nametable(&self.cartridge, nametable, address)
+	}
+
+	pub fn store_nametable(&mut self, nametable: Nametable, address: u32, value: u8) {
+		self.mapper.store_nametable(&mut self.cartridge, nametable, address, value);
+	}
+
+	pub fn notify_ppu_address(&mut self, address: u16) {
+		self.mapper.notify_ppu_address(address);
+	}
+
+	pub fn notify_ppu_register_store(&mut self, address: u16, value: u8) {
+		self.mapper.notify_ppu_register_store(address, value);
+	}
+
+	pub fn pattern_ciram_page(&self, address: u16) -> Option<u8> {
+		self.mapper.pattern_ciram_page(address)
+	}
+
+	pub fn set_audio_multiplexing(&mut self, multiplexing: bool) {
+		self.mapper.set_audio_multiplexing(multiplexing);
+	}
+
+	// Expects being called every CPU cycle
+	pub fn step(&mut self) {
+		self.mapper.step();
+	}
+
+	pub fn audio_output(&self) -> f32 {
+		self.mapper.audio_output()
+	}
+
+	/**
+	 * Called at power-on. Places the trainer at 0x7000 - 0x71FF
+	 * where hardware-modified dumps expect it.
+	 */
+	pub fn bootup(&mut self) {
+		let trainer = match self.trainer {
+			Some(ref trainer) => trainer.clone(),
+			None => return
+		};
+		for (i, value) in trainer.into_iter().enumerate() {
+			self.cartridge.store_prg_ram(0x1000 + i as u32, value);
+		}
+	}
+
+	pub fn has_battery(&self) -> bool {
+		self.header.has_battery() && self.battery_memory().is_some()
+	}
+
+	// Battery-backed PRG-RAM, or the mapper chip's internal RAM
+	// if the cartridge has no battery-backed PRG-RAM
+	fn battery_memory(&self) -> Option<&Memory> {
+		match self.cartridge.battery_ram.capacity() {
+			0 => self.mapper.battery_backed_chip_ram(),
+			_ => Some(&self.cartridge.battery_ram)
+		}
+	}
+
+	fn battery_memory_mut(&mut self) -> Option<&mut Memory> {
+		match self.cartridge.battery_ram.capacity() {
+			0 => self.mapper.battery_backed_chip_ram_mut(),
+			_ => Some(&mut self.cartridge.battery_ram)
+		}
+	}
+
+	/**
+	 * Returns battery-backed PRG-RAM contents to persist,
+	 * or None if the cartridge has no battery.
+	 */
+	pub fn battery_ram(&self) -> Option<&[u8]> {
+		match self.has_battery() {
+			true => self.battery_memory().map(|memory| memory.as_slice()),
+			false => None
+		}
+	}
+
+	/**
+	 * Restores battery-backed PRG-RAM contents.
+	 * Copies as much as fits if the size is different
+	 * because some emulators pad save files.
+	 */
+	pub fn load_battery_ram(&mut self, data: &[u8]) {
+		if !self.has_battery() {
+			return;
+		}
+		let ram = self.battery_memory_mut().unwrap().as_mut_slice();
+		let length = ram.len().min(data.len());
+		ram[..length].copy_from_slice(&data[..length]);
 	}
 
 	pub fn valid(&self) -> bool {
 		self.header.is_nes()
 	}
 
+	pub fn header(&self) -> &RomHeader {
+		&self.header
+	}
+
 	pub fn has_chr_rom(&self) -> bool {
-		self.header.has_chr_rom()
+		self.cartridge.has_chr_rom()
+	}
+
+	pub fn nametable_mapping(&self) -> NametableMapping {
+		match self.mapper.nametable_mapping() {
+			Some(mapping) => mapping,
+			None => self.header.nametable_mapping()
+		}
 	}
 
-	pub fn mirroring_type(&self) -> Mirrorings {
-		match self.mapper.has_mirroring_type() {
-			true => self.mapper.mirroring_type(),
-			false => self.header.mirroring_type()
+	pub fn irq_line(&self) -> bool {
+		self.mapper.irq_line()
+	}
+
+	pub fn save_state(&self, writer: &mut StateWriter) {
+		writer.write_u32(self.checksum);
+		self.cartridge.save_state(writer);
+		self.mapper.save_state(writer);
+	}
+
+	pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+		if reader.read_u32()? != self.checksum {
+			return Err(StateError::RomMismatch);
 		}
+		self.cartridge.load_state(reader)?;
+		self.mapper.load_state(reader)
 	}
+}
 
-	// @TODO: MMC3Mapper specific. Should this method be here?
-	pub fn irq_interrupted(&mut self) -> bool {
-		self.mapper.drive_irq_counter()
+// FNV-1a 32-bit hash of the whole rom image
+fn calculate_checksum(data: &[u8]) -> u32 {
+	let mut hash = 0x811C9DC5 as u32;
+	for byte in data {
+		hash ^= *byte as u32;
+		hash = hash.wrapping_mul(0x01000193);
 	}
+	hash
 }
 
 // @TODO: Cache
@@ -96,39 +406,96 @@ impl RomHeader {
 		header
 	}
 
+	/**
+	 * Header for mapper tests. NES 2.0 header with the submapper
+	 * if submapper_num is some, iNES 1.0 header otherwise.
+	 */
+	#[cfg(test)]
+	pub fn new_for_test(prg_rom_bank_num: u8, chr_rom_bank_num: u8, mapper_num: u8, submapper_num: Option<u8>) -> Self {
+		let mut v = vec![0x4E, 0x45, 0x53, 0x1A, prg_rom_bank_num, chr_rom_bank_num, (mapper_num & 0xF) << 4, mapper_num & 0xF0];
+		if let Some(submapper_num) = submapper_num {
+			v[7] |= 0x08;
+			v.push(submapper_num << 4);
+		}
+		v.resize(HEADER_SIZE, 0);
+		RomHeader::new(v)
+	}
+
 	fn load(&self, address: u32) -> u8 {
 		self.data[address as usize]
 	}
 
 	fn is_nes(&self) -> bool {
-		if self.signature() == "NES" && self.magic_number() == 0x1a {
+		if self.signature() == *b"NES" && self.magic_number() == 0x1a {
 			return true;
 		}
 		false
 	}
 
-	fn signature(&self) -> String {
-		let mut vec = Vec::new();
-		for i in 0..3 as u32 {
-			vec.push(self.load(i));
-		}
-		String::from_utf8(vec).unwrap()
+	fn signature(&self) -> [u8; 3] {
+		[self.load(0), self.load(1), self.load(2)]
 	}
 
 	fn magic_number(&self) -> u8 {
 		self.load(3)
 	}
 
-	pub fn prg_rom_bank_num(&self) -> u8 {
-		self.load(4)
+	/**
+	 * NES 2.0 header is identified by 0b10 in bits 2-3 of byte 7.
+	 * NES 2.0 uses bytes 8-15 which iNES 1.0 mostly leaves zero.
+	 */
+	pub fn is_nes2(&self) -> bool {
+		self.extract_bits(self.control_byte2(), 2, 2) == 2
+	}
+
+	// Old dumping tools wrote garbage like "DiskDude!" into bytes 7-15.
+	// If bytes 12-15 of an iNES 1.0 header aren't zero byte 7 isn't reliable.
+	fn has_archaic_header(&self) -> bool {
+		!self.is_nes2() && (12..16).any(|i| self.load(i) != 0)
+	}
+
+	/**
+	 * Size in bytes. NES 2.0 has the size MSB nibble in byte 9 and,
+	 * if the nibble is 0xF, the size is in the exponent-multiplier
+	 * notation 2^E * (M * 2 + 1) where byte 4/5 is EEEEEEMM.
+	 */
+	fn rom_size(&self, lsb: u8, msb_nibble: u8, bank_size: usize) -> usize {
+		if !self.is_nes2() {
+			return lsb as usize * bank_size;
+		}
+		match msb_nibble {
+			0xF => {
+				let exponent = self.extract_bits(lsb, 2, 6) as u32;
+				let multiplier = self.extract_bits(lsb, 0, 2) as usize * 2 + 1;
+				match 1_usize.checked_shl(exponent) {
+					Some(size) => size.saturating_mul(multiplier),
+					None => usize::max_value()
+				}
+			},
+			_ => (((msb_nibble as usize) << 8) | lsb as usize) * bank_size
+		}
+	}
+
+	pub fn prg_rom_size(&self) -> usize {
+		self.rom_size(self.load(4), self.extract_bits(self.load(9), 0, 4), PRG_ROM_BANK_SIZE)
+	}
+
+	pub fn chr_rom_size(&self) -> usize {
+		self.rom_size(self.load(5), self.extract_bits(self.load(9), 4, 4), CHR_ROM_BANK_SIZE)
 	}
 
-	pub fn chr_rom_bank_num(&self) -> u8 {
-		self.load(5)
+	// In 16KB unit
+	pub fn prg_rom_bank_num(&self) -> u16 {
+		(self.prg_rom_size() / PRG_ROM_BANK_SIZE) as u16
+	}
+
+	// In 8KB unit
+	pub fn chr_rom_bank_num(&self) -> u16 {
+		(self.chr_rom_size() / CHR_ROM_BANK_SIZE) as u16
 	}
 
 	fn has_chr_rom(&self) -> bool {
-		self.chr_rom_bank_num() > 0
+		self.chr_rom_size() > 0
 	}
 
 	fn control_byte1(&self) -> u8 {
@@ -139,55 +506,173 @@ impl RomHeader {
 		self.load(7)
 	}
 
-	fn _ram_bank_num(&self) -> u8 {
-		self.load(8)
+	// NES 2.0 RAM sizes are shift counts, 64 << n bytes or 0
+	fn nes2_ram_size(&self, shift_count: u8) -> usize {
+		match shift_count {
+			0 => 0,
+			_ => 64 << shift_count
+		}
+	}
+
+	/**
+	 * Volatile PRG-RAM size in bytes at 0x6000 - 0x7FFF.
+	 * iNES 1.0 has the size in 8KB unit in byte 8 where 0 means 8KB
+	 * for compatibility, and it's battery-backed if the battery bit is set.
+	 */
+	pub fn prg_ram_size(&self) -> usize {
+		match self.is_nes2() {
+			true => self.nes2_ram_size(self.extract_bits(self.load(10), 0, 4)),
+			false => match self.has_battery() {
+				true => 0,
+				false => self.ines_prg_ram_size()
+			}
+		}
 	}
 
-	fn _unused_field(&self) -> u64 {
-		let mut value = 0 as u64;
-		for i in 0..7 as u32 {
-			value = (value << 8) | self.load(9 + i) as u64;
+	// Battery-backed PRG-RAM size in bytes
+	pub fn prg_nvram_size(&self) -> usize {
+		match self.is_nes2() {
+			true => self.nes2_ram_size(self.extract_bits(self.load(10), 4, 4)),
+			false => match self.has_battery() {
+				true => self.ines_prg_ram_size(),
+				false => 0
+			}
+		}
+	}
+
+	fn ines_prg_ram_size(&self) -> usize {
+		match self.has_archaic_header() {
+			true => 0x2000,
+			false => match self.load(8) {
+				0 => 0x2000,
+				n => n as usize * 0x2000
+			}
+		}
+	}
+
+	/**
+	 * Volatile CHR-RAM size in bytes.
+	 * iNES 1.0 implies 8KB CHR-RAM if the rom has no CHR-ROM.
+	 */
+	pub fn chr_ram_size(&self) -> usize {
+		match self.is_nes2() {
+			true => self.nes2_ram_size(self.extract_bits(self.load(11), 0, 4)),
+			false => match self.has_chr_rom() {
+				true => 0,
+				false => 0x2000
+			}
+		}
+	}
+
+	// Battery-backed CHR-RAM size in bytes. Always 0 in iNES 1.0.
+	pub fn chr_nvram_size(&self) -> usize {
+		match self.is_nes2() {
+			true => self.nes2_ram_size(self.extract_bits(self.load(11), 4, 4)),
+			false => 0
+		}
+	}
+
+	/**
+	 * CPU/PPU timing. NES 2.0 has it in bits 0-1 of byte 12.
+	 * iNES 1.0 has the TV system in bit 0 of byte 9
+	 * although few dumps set it.
+	 */
+	pub fn timing(&self) -> Timing {
+		match self.is_nes2() {
+			true => match self.extract_bits(self.load(12), 0, 2) {
+				0 => Timing::Ntsc,
+				1 => Timing::Pal,
+				2 => Timing::MultipleRegion,
+				_ /* 3 */ => Timing::Dendy
+			},
+			false => match !self.has_archaic_header() && self.extract_bits(self.load(9), 0, 1) == 1 {
+				true => Timing::Pal,
+				false => Timing::Ntsc
+			}
+		}
+	}
+
+	pub fn console_type(&self) -> ConsoleType {
+		match self.extract_bits(self.control_byte2(), 0, 2) {
+			0 => ConsoleType::Nes,
+			1 => ConsoleType::VsSystem,
+			2 => ConsoleType::Playchoice10,
+			_ /* 3 */ => match self.is_nes2() {
+				true => ConsoleType::Extended(self.extract_bits(self.load(13), 0, 4)),
+				false => ConsoleType::Nes
+			}
+		}
+	}
+
+	/**
+	 * Default expansion device number in NES 2.0, 0 is unspecified.
+	 * See https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device
+	 */
+	pub fn default_expansion_device(&self) -> u8 {
+		match self.is_nes2() {
+			true => self.extract_bits(self.load(15), 0, 6),
+			false => 0
 		}
-		value
 	}
 
 	fn extract_bits(&self, value: u8, offset: u8, size: u8) -> u8 {
 		(value >> offset) & ((1 << size) - 1)
 	}
 
-	fn mirroring_type(&self) -> Mirrorings {
+	pub fn nametable_mapping(&self) -> NametableMapping {
 		match self.four_screen_mirroring() {
-			true => Mirrorings::FourScreen,
+			true => FOUR_SCREEN,
 			false => match self.extract_bits(self.control_byte1(), 0, 1) {
-				0 => Mirrorings::Horizontal,
-				_ /* 1 */ => Mirrorings::Vertical
+				0 => HORIZONTAL_MIRRORING,
+				_ /* 1 */ => VERTICAL_MIRRORING
 			}
 		}
 	}
 
 	fn _is_horizontal_mirroring(&self) -> bool {
-		match self.mirroring_type() {
-			Mirrorings::Horizontal => true,
-			_ => false
-		}
+		self.nametable_mapping() == HORIZONTAL_MIRRORING
 	}
 
-	fn _battery_backed_ram(&self) -> u8 {
-		self.extract_bits(self.control_byte1(), 1, 1)
+	pub fn has_battery(&self) -> bool {
+		self.extract_bits(self.control_byte1(), 1, 1) == 1
 	}
 
-	fn _trainer_512_bytes(&self) -> u8 {
-		self.extract_bits(self.control_byte1(), 2, 1)
+	fn has_trainer(&self) -> bool {
+		self.extract_bits(self.control_byte1(), 2, 1) == 1
 	}
 
-	fn four_screen_mirroring(&self) -> bool {
+	pub fn four_screen_mirroring(&self) -> bool {
 		self.extract_bits(self.control_byte1(), 3, 1) == 1
 	}
 
-	pub fn mapper_num(&self) -> u8 {
-		let lower_bits = self.extract_bits(self.control_byte1(), 4, 4);
-		let higher_bits = self.extract_bits(self.control_byte2(), 4, 4);
-		(higher_bits << 4) | lower_bits
+	// Some mappers give other meanings to bit 0 and 3 of byte 6
+	pub fn mirroring_bit(&self) -> u8 {
+		self.extract_bits(self.control_byte1(), 0, 1)
+	}
+
+	/**
+	 * 12-bit in NES 2.0 (bits 0-3 of byte 8 are bits 8-11),
+	 * 8-bit in iNES 1.0.
+	 */
+	pub fn mapper_num(&self) -> u16 {
+		let lower_bits = self.extract_bits(self.control_byte1(), 4, 4) as u16;
+		let middle_bits = match self.has_archaic_header() {
+			true => 0,
+			false => self.extract_bits(self.control_byte2(), 4, 4) as u16
+		};
+		let higher_bits = match self.is_nes2() {
+			true => self.extract_bits(self.load(8), 0, 4) as u16,
+			false => 0
+		};
+		(higher_bits << 8) | (middle_bits << 4) | lower_bits
+	}
+
+	// 0 in iNES 1.0
+	pub fn submapper_num(&self) -> u8 {
+		match self.is_nes2() {
+			true => self.extract_bits(self.load(8), 4, 4),
+			false => 0
+		}
 	}
 }
 
@@ -202,7 +687,7 @@ mod tests_rom {
 
 	#[test]
 	fn load() {
-		let r = Rom::new(vec![0; 17]);
+		let mut r = Rom::new(vec![0; 17]);
 		assert_eq!(0, r.load(0));
 	}
 
@@ -224,4 +709,292 @@ mod tests_rom {
 		let r2 = Rom::new(v);
 		assert_eq!(true, r2.valid());
 	}
+
+	fn header(prg_rom_bank_num: u8, chr_rom_bank_num: u8, control_byte1: u8) -> Vec<u8> {
+		let mut v = vec![0; HEADER_SIZE];
+		v[0] = 0x4e; // N
+		v[1] = 0x45; // E
+		v[2] = 0x53; // S
+		v[3] = 0x1a; // magic number
+		v[4] = prg_rom_bank_num;
+		v[5] = chr_rom_bank_num;
+		v[6] = control_byte1;
+		v
+	}
+
+	#[test]
+	fn parse() {
+		let mut v = header(1, 1, 0);
+		v.resize(HEADER_SIZE + 0x4000 + 0x2000, 0);
+		assert_eq!(true, Rom::parse(v).is_ok());
+		assert_eq!(Some(RomError::TruncatedHeader), Rom::parse(vec![0x4e, 0x45]).err());
+		assert_eq!(Some(RomError::InvalidMagic), Rom::parse(vec![0; 64]).err());
+	}
+
+	#[test]
+	fn parse_size_mismatch() {
+		let mut v = header(2, 1, 0);
+		v.resize(HEADER_SIZE + 0x4000, 0);
+		assert_eq!(Some(RomError::PrgRomSizeMismatch { expected: 0x8000, actual: 0x4000 }),
+			Rom::parse(v).err());
+		let mut v2 = header(1, 1, 0);
+		v2.resize(HEADER_SIZE + 0x4000 + 0x1000, 0);
+		assert_eq!(Some(RomError::ChrRomSizeMismatch { expected: 0x2000, actual: 0x1000 }),
+			Rom::parse(v2).err());
+	}
+
+	#[test]
+	fn parse_trainer_overflow() {
+		let mut v = header(1, 1, 0x04);
+		v.resize(HEADER_SIZE + 0x100, 0);
+		assert_eq!(Some(RomError::TrainerOverflow), Rom::parse(v).err());
+	}
+
+	#[test]
+	fn parse_unsupported_mapper() {
+		let mut v = header(1, 1, 0xF0);
+		v.resize(HEADER_SIZE + 0x4000 + 0x2000, 0);
+		assert_eq!(Some(RomError::UnsupportedMapper(15)), Rom::parse(v).err());
+	}
+
+	#[test]
+	fn ines_header() {
+		let mut v = header(2, 1, 0x12);
+		v[7] = 0x40;
+		let h = RomHeader::new(v);
+		assert_eq!(false, h.is_nes2());
+		assert_eq!(0x41, h.mapper_num());
+		assert_eq!(0, h.submapper_num());
+		assert_eq!(0x8000, h.prg_rom_size());
+		assert_eq!(0x2000, h.chr_rom_size());
+		assert_eq!(0, h.prg_ram_size());
+		assert_eq!(0x2000, h.prg_nvram_size());
+		assert_eq!(0, h.chr_ram_size());
+		assert_eq!(Timing::Ntsc, h.timing());
+		assert_eq!(ConsoleType::Nes, h.console_type());
+	}
+
+	#[test]
+	fn ines_archaic_header() {
+		let mut v = header(1, 0, 0x10);
+		v[7..16].copy_from_slice(b"DiskDude!");
+		let h = RomHeader::new(v);
+		assert_eq!(1, h.mapper_num());
+		assert_eq!(0x2000, h.prg_ram_size());
+		assert_eq!(0x2000, h.chr_ram_size());
+	}
+
+	#[test]
+	fn nes2_header() {
+		let mut v = header(0x02, 0x00, 0x40);
+		v[7] = 0x58; // NES 2.0, mapper bits 4-7 = 5
+		v[8] = 0x31; // submapper 3, mapper bits 8-11 = 1
+		v[9] = 0x01; // PRG-ROM size MSB
+		v[10] = 0x70; // PRG-NVRAM 8KB
+		v[11] = 0x07; // CHR-RAM 8KB
+		v[12] = 0x03; // Dendy
+		v[15] = 0x01; // Standard controllers
+		let h = RomHeader::new(v);
+		assert_eq!(true, h.is_nes2());
+		assert_eq!(0x154, h.mapper_num());
+		assert_eq!(3, h.submapper_num());
+		assert_eq!(0x102 * 0x4000, h.prg_rom_size());
+		assert_eq!(0, h.chr_rom_size());
+		assert_eq!(0, h.prg_ram_size());
+		assert_eq!(0x2000, h.prg_nvram_size());
+		assert_eq!(0x2000, h.chr_ram_size());
+		assert_eq!(0, h.chr_nvram_size());
+		assert_eq!(Timing::Dendy, h.timing());
+		assert_eq!(1, h.default_expansion_device());
+	}
+
+	#[test]
+	fn nes2_exponent_multiplier_size() {
+		let mut v = header(0x4D, 0x00, 0);
+		v[7] = 0x0B; // NES 2.0, extended console type
+		v[9] = 0x0F;
+		v[13] = 0x04;
+		let h = RomHeader::new(v);
+		assert_eq!((1 << 19) * 3, h.prg_rom_size());
+		assert_eq!(ConsoleType::Extended(4), h.console_type());
+	}
+
+	#[test]
+	fn prg_ram() {
+		let mut r = Rom::new(header(1, 1, 0));
+		r.store(0x6000, 1);
+		r.store(0x7FFF, 2);
+		assert_eq!(1, r.load(0x6000));
+		assert_eq!(2, r.load(0x7FFF));
+		assert_eq!(None, r.battery_ram());
+	}
+
+	#[test]
+	fn battery_ram() {
+		let mut r = Rom::new(header(1, 1, 0x02));
+		r.store(0x6001, 3);
+		assert_eq!(0x2000, r.battery_ram().unwrap().len());
+		assert_eq!(3, r.battery_ram().unwrap()[1]);
+		r.load_battery_ram(&[4, 5, 6]);
+		assert_eq!(4, r.load(0x6000));
+		assert_eq!(6, r.load(0x6002));
+	}
+
+	#[test]
+	fn trainer() {
+		let mut v = header(1, 1, 0x04);
+		v.extend((0..TRAINER_SIZE).map(|i| i as u8));
+		v.resize(HEADER_SIZE + TRAINER_SIZE + 0x4000, 0x11);
+		v.resize(HEADER_SIZE + TRAINER_SIZE + 0x4000 + 0x2000, 0x22);
+		let mut r = Rom::parse(v).ok().unwrap();
+		assert_eq!(0x11, r.load(0x8000));
+		assert_eq!(0x22, r.load(0x0000));
+		assert_eq!(0, r.load(0x7001));
+		r.bootup();
+		assert_eq!(0, r.load(0x7000));
+		assert_eq!(1, r.load(0x7001));
+		assert_eq!(0xFF, r.load(0x71FF));
+		assert_eq!(0, r.load(0x7200));
+	}
+
+	#[test]
+	fn expansion_area() {
+		// NROM has nothing in 0x4020 - 0x5FFF
+		let mut r = Rom::new(header(1, 1, 0));
+		r.store(0x5000, 1);
+		assert_eq!(0, r.load(0x5000));
+		r.store(0x6000, 1);
+		assert_eq!(1, r.load(0x6000));
+	}
+
+	#[test]
+	fn chr_ram_bank() {
+		// UNROM 512, mapper 30
+		let mut v = header(2, 0, 0xE0);
+		v[7] = 0x10;
+		v.resize(HEADER_SIZE + 0x8000, 0);
+		let mut r = Rom::parse(v).ok().unwrap();
+		r.store(0x0000, 1);
+		r.store(0x8000, 0x20);
+		assert_eq!(0, r.load(0x0000));
+		r.store(0x0000, 2);
+		r.store(0x8000, 0x60);
+		r.store(0x0000, 4);
+		r.store(0x8000, 0x00);
+		assert_eq!(1, r.load(0x0000));
+		r.store(0x8000, 0x20);
+		assert_eq!(2, r.load(0x0000));
+		r.store(0x8000, 0x60);
+		assert_eq!(4, r.load(0x0000));
+	}
+
+	#[test]
+	fn tqrom_chr_ram() {
+		// TQROM, mapper 119
+		let mut v = header(2, 8, 0x70);
+		v[7] = 0x70;
+		v.resize(HEADER_SIZE + 0x8000, 0);
+		v.resize(HEADER_SIZE + 0x8000 + 0x10000, 0x11);
+		let mut r = Rom::parse(v).ok().unwrap();
+		// R0 = 0x40: CHR-RAM at 0x0000 - 0x07FF
+		r.store(0x8000, 0);
+		r.store(0x8001, 0x40);
+		r.store(0x0001, 1);
+		assert_eq!(1, r.load(0x0001));
+		// R0 = 0x02: CHR-ROM
+		r.store(0x8001, 0x02);
+		r.store(0x0001, 2);
+		assert_eq!(0x11, r.load(0x0001));
+	}
+
+	#[test]
+	fn nametable_mapping() {
+		let mut v = header(1, 1, 0);
+		v.resize(HEADER_SIZE + 0x4000 + 0x2000, 0);
+		assert_eq!(HORIZONTAL_MIRRORING, Rom::parse(v).ok().unwrap().nametable_mapping());
+		let mut v2 = header(1, 1, 1);
+		v2.resize(HEADER_SIZE + 0x4000 + 0x2000, 0);
+		assert_eq!(VERTICAL_MIRRORING, Rom::parse(v2).ok().unwrap().nametable_mapping());
+
+		// MMC1 control register 1: one screen, upper bank
+		let mut v3 = header(2, 1, 0x10);
+		v3.resize(HEADER_SIZE + 0x8000 + 0x2000, 0);
+		let mut r = Rom::parse(v3).ok().unwrap();
+		for i in 0..5 {
+			r.store(0x8000, match i { 0 => 1, _ => 0 });
+			r.step();
+			r.step();
+		}
+		assert_eq!(SINGLE_SCREEN_B, r.nametable_mapping());
+	}
+
+	#[test]
+	fn bus_conflict() {
+		// UNROM, NES 2.0 submapper 1 without bus conflicts and 2 with them
+		for submapper in 1..3 {
+			let mut v = header(4, 0, 0x20);
+			v[7] = 0x08;
+			v[8] = submapper << 4;
+			for bank in 0..4 {
+				v.resize(HEADER_SIZE + 0x4000 * (bank + 1), 0x10 | bank as u8);
+			}
+			let mut r = Rom::parse(v).ok().unwrap();
+			r.store(0x8000, 0x03);
+			assert_eq!(match submapper {
+				1 => 0x13,
+				_ => 0x10
+			}, r.load(0x8000));
+		}
+	}
+
+	#[test]
+	fn four_screen_nametable_ram() {
+		let mut v = header(1, 1, 0x08);
+		v.resize(HEADER_SIZE + 0x4000 + 0x2000, 0);
+		let mut r = Rom::parse(v).ok().unwrap();
+		assert_eq!(FOUR_SCREEN, r.nametable_mapping());
+		r.store_nametable(Nametable::CartridgeRam(0), 0x3FF, 1);
+		r.store_nametable(Nametable::CartridgeRam(1), 0x000, 2);
+		assert_eq!(1, r.load_nametable(Nametable::CartridgeRam(0), 0x3FF));
+		assert_eq!(2, r.load_nametable(Nametable::CartridgeRam(1), 0x000));
+		assert_eq!(0, r.load_nametable(Nametable::CartridgeRam(1), 0x3FF));
+	}
+
+	#[test]
+	fn mmc5_prg_ram() {
+		// iNES 1.0 MMC5 rom gets 64KB PRG-RAM
+		let mut v = header(2, 1, 0x50);
+		v.resize(HEADER_SIZE + 0x8000 + 0x2000, 0);
+		let mut r = Rom::parse(v).ok().unwrap();
+		r.store(0x5102, 2);
+		r.store(0x5103, 1);
+		r.store(0x5113, 0);
+		r.store(0x6000, 1);
+		r.store(0x5113, 7);
+		r.store(0x6000, 2);
+		assert_eq!(2, r.load(0x6000));
+		r.store(0x5113, 0);
+		assert_eq!(1, r.load(0x6000));
+	}
+
+	#[test]
+	fn chip_battery_ram() {
+		// Namco 163 with 128 bytes PRG-NVRAM in NES 2.0 header
+		let mut v = header(1, 1, 0x32);
+		v[7] = 0x18;
+		v[10] = 0x10;
+		v.resize(HEADER_SIZE + 0x4000 + 0x2000, 0);
+		let mut r = Rom::parse(v).ok().unwrap();
+		r.store(0x6000, 1);
+		assert_eq!(0, r.load(0x6000));
+		// Internal RAM with auto increment
+		r.store(0xF800, 0x81);
+		r.store(0x4800, 3);
+		r.store(0x4800, 4);
+		assert_eq!(0x80, r.battery_ram().unwrap().len());
+		assert_eq!(&[0, 3, 4], &r.battery_ram().unwrap()[..3]);
+		r.load_battery_ram(&[5, 6]);
+		r.store(0xF800, 0x01);
+		assert_eq!(6, r.load(0x4800));
+	}
 }
@@ -0,0 +1,269 @@
+use std::error;
+use std::fmt;
+
+use memory::Memory;
+use register::Register;
+
+/**
+ * Save state binary format.
+ *
+ * 0x00 - 0x03: Magic number "NESS"
+ * 0x04 - 0x07: Format version, little endian
+ * 0x08 -     : Component states serialized in the fixed order
+ *              Cpu (registers, RAM), Ppu, Apu, Joypads, Rom (CHR-RAM, PRG-RAM, Mapper)
+ *
+ * Every multi-byte value is little endian. Byte arrays are
+ * prefixed with their length in 4 bytes.
+ * Bump STATE_VERSION whenever the layout of any component changes
+ * so that states saved by older builds are rejected.
+ */
+pub const STATE_MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x53]; // NESS
+pub const STATE_VERSION: u32 = 13;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+	// The data doesn't start with STATE_MAGIC
+	InvalidHeader,
+	// Saved by a build whose format version is different from STATE_VERSION
+	UnsupportedVersion(u32),
+	// The data ends before all the components are restored
+	UnexpectedEnd,
+	// A byte array length or an enum value which can't be restored
+	InvalidData,
+	// Saved with a different rom image
+	RomMismatch
+}
+
+impl fmt::Display for StateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StateError::InvalidHeader => write!(f, "Not a save state"),
+			StateError::UnsupportedVersion(version) => write!(f,
+				"Save state version {} is not supported (expected {})", version, STATE_VERSION),
+			StateError::UnexpectedEnd => write!(f, "Save state is truncated"),
+			StateError::InvalidData => write!(f, "Save state is corrupted"),
+			StateError::RomMismatch => write!(f, "Save state was made with a different rom")
+		}
+	}
+}
+
+impl error::Error for StateError {}
+
+pub struct StateWriter {
+	data: Vec<u8>
+}
+
+impl StateWriter {
+	pub fn new() -> Self {
+		StateWriter {
+			data: Vec::new()
+		}
+	}
+
+	pub fn write_header(&mut self) {
+		self.data.extend_from_slice(&STATE_MAGIC);
+		self.write_u32(STATE_VERSION);
+	}
+
+	pub fn write_u8(&mut self, value: u8) {
+		self.data.push(value);
+	}
+
+	pub fn write_bool(&mut self, value: bool) {
+		self.write_u8(value as u8);
+	}
+
+	pub fn write_u16(&mut self, value: u16) {
+		self.data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	pub fn write_u32(&mut self, value: u32) {
+		self.data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	pub fn write_u64(&mut self, value: u64) {
+		self.data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		self.write_u32(bytes.len() as u32);
+		self.data.extend_from_slice(bytes);
+	}
+
+	pub fn write_register_u8(&mut self, register: &Register<u8>) {
+		self.write_u8(register.load());
+	}
+
+	pub fn write_register_u16(&mut self, register: &Register<u16>) {
+		self.write_u16(register.load());
+	}
+
+	pub fn write_memory(&mut self, memory: &Memory) {
+		self.write_bytes(memory.as_slice());
+	}
+
+	pub fn into_vec(self) -> Vec<u8> {
+		self.data
+	}
+}
+
+pub struct StateReader<'a> {
+	data: &'a [u8],
+	position: usize
+}
+
+impl<'a> StateReader<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		StateReader {
+			data: data,
+			position: 0
+		}
+	}
+
+	pub fn read_header(&mut self) -> Result<(), StateError> {
+		if self.data.len() < STATE_MAGIC.len() || self.data[0..STATE_MAGIC.len()] != STATE_MAGIC {
+			return Err(StateError::InvalidHeader);
+		}
+		self.position = STATE_MAGIC.len();
+		match self.read_u32() {
+			Ok(STATE_VERSION) => Ok(()),
+			Ok(version) => Err(StateError::UnsupportedVersion(version)),
+			Err(_) => Err(StateError::InvalidHeader)
+		}
+	}
+
+	fn read_slice(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+		if self.data.len() - self.position < length {
+			return Err(StateError::UnexpectedEnd);
+		}
+		let slice = &self.data[self.position..self.position + length];
+		self.position += length;
+		Ok(slice)
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, StateError> {
+		Ok(self.read_slice(1)?[0])
+	}
+
+	pub fn read_bool(&mut self) -> Result<bool, StateError> {
+		match self.read_u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			_ => Err(StateError::InvalidData)
+		}
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16, StateError> {
+		let slice = self.read_slice(2)?;
+		Ok(u16::from_le_bytes([slice[0], slice[1]]))
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32, StateError> {
+		let slice = self.read_slice(4)?;
+		Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64, StateError> {
+		let slice = self.read_slice(8)?;
+		let mut bytes = [0; 8];
+		bytes.copy_from_slice(slice);
+		Ok(u64::from_le_bytes(bytes))
+	}
+
+	pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+		let length = self.read_u32()? as usize;
+		self.read_slice(length)
+	}
+
+	// Reads a byte array into a fixed size buffer.
+	// The length must match because the buffer size is defined by
+	// the emulator, not by the save state.
+	pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+		let bytes = self.read_bytes()?;
+		if bytes.len() != buffer.len() {
+			return Err(StateError::InvalidData);
+		}
+		buffer.copy_from_slice(bytes);
+		Ok(())
+	}
+
+	pub fn read_register_u8(&mut self, register: &mut Register<u8>) -> Result<(), StateError> {
+		register.store(self.read_u8()?);
+		Ok(())
+	}
+
+	pub fn read_register_u16(&mut self, register: &mut Register<u16>) -> Result<(), StateError> {
+		register.store(self.read_u16()?);
+		Ok(())
+	}
+
+	pub fn read_memory(&mut self, memory: &mut Memory) -> Result<(), StateError> {
+		self.read_bytes_into(memory.as_mut_slice())
+	}
+
+	pub fn is_end(&self) -> bool {
+		self.position == self.data.len()
+	}
+}
+
+#[cfg(test)]
+mod tests_state {
+	use super::*;
+
+	#[test]
+	fn write_and_read() {
+		let mut w = StateWriter::new();
+		w.write_header();
+		w.write_u8(0x12);
+		w.write_bool(true);
+		w.write_u16(0x3456);
+		w.write_u32(0x789ABCDE);
+		w.write_u64(0x0123456789ABCDEF);
+		w.write_bytes(&[1, 2, 3]);
+		let data = w.into_vec();
+
+		let mut r = StateReader::new(&data);
+		assert_eq!(Ok(()), r.read_header());
+		assert_eq!(Ok(0x12), r.read_u8());
+		assert_eq!(Ok(true), r.read_bool());
+		assert_eq!(Ok(0x3456), r.read_u16());
+		assert_eq!(Ok(0x789ABCDE), r.read_u32());
+		assert_eq!(Ok(0x0123456789ABCDEF), r.read_u64());
+		assert_eq!(Ok(&[1, 2, 3][..]), r.read_bytes());
+		assert_eq!(true, r.is_end());
+	}
+
+	#[test]
+	fn invalid_header() {
+		let mut r = StateReader::new(&[0x4E, 0x45, 0x53]);
+		assert_eq!(Err(StateError::InvalidHeader), r.read_header());
+		let mut r2 = StateReader::new(&[0, 0, 0, 0, 1, 0, 0, 0]);
+		assert_eq!(Err(StateError::InvalidHeader), r2.read_header());
+	}
+
+	#[test]
+	fn unsupported_version() {
+		let mut data = STATE_MAGIC.to_vec();
+		data.extend_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+		let mut r = StateReader::new(&data);
+		assert_eq!(Err(StateError::UnsupportedVersion(STATE_VERSION + 1)), r.read_header());
+	}
+
+	#[test]
+	fn unexpected_end() {
+		let mut r = StateReader::new(&[1]);
+		assert_eq!(Err(StateError::UnexpectedEnd), r.read_u16());
+	}
+
+	#[test]
+	fn read_bytes_into() {
+		let mut w = StateWriter::new();
+		w.write_bytes(&[1, 2]);
+		let data = w.into_vec();
+		let mut buffer = [0; 3];
+		assert_eq!(Err(StateError::InvalidData), StateReader::new(&data).read_bytes_into(&mut buffer));
+		let mut buffer2 = [0; 2];
+		assert_eq!(Ok(()), StateReader::new(&data).read_bytes_into(&mut buffer2));
+		assert_eq!([1, 2], buffer2);
+	}
+}
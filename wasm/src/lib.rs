@@ -4,8 +4,8 @@ extern crate nes_rust;
 use wasm_bindgen::prelude::*;
 
 use nes_rust::Nes;
-use nes_rust::rom::Rom;
 use nes_rust::button;
+use nes_rust::region;
 use nes_rust::default_input::DefaultInput;
 use nes_rust::default_audio::DefaultAudio;
 use nes_rust::default_display::DefaultDisplay;
@@ -16,6 +16,7 @@ use nes_rust::default_display::DefaultDisplay;
 pub enum Button {
 	Poweroff,
 	Reset,
+	Rewind,
 	Select,
 	Start,
 	Joypad1A,
@@ -36,6 +37,7 @@ fn to_button_internal(button: Button) -> button::Button {
 	match button {
 		Button::Poweroff => button::Button::Poweroff,
 		Button::Reset => button::Button::Reset,
+		Button::Rewind => button::Button::Rewind,
 		Button::Select => button::Button::Select,
 		Button::Start => button::Button::Start,
 		Button::Joypad1A => button::Button::Joypad1A,
@@ -53,6 +55,21 @@ fn to_button_internal(button: Button) -> button::Button {
 	}
 }
 
+#[wasm_bindgen]
+pub enum Region {
+	Ntsc,
+	Pal,
+	Dendy
+}
+
+fn to_region_internal(region: Region) -> region::Region {
+	match region {
+		Region::Ntsc => region::Region::Ntsc,
+		Region::Pal => region::Region::Pal,
+		Region::Dendy => region::Region::Dendy
+	}
+}
+
 /// `WasmNes` is an interface between user JavaScript code and
 /// WebAssembly NES emulator. The following code is example
 /// JavaScript user code.
@@ -115,12 +132,44 @@ impl WasmNes {
 		}
 	}
 
-	/// Sets up NES rom
+	/// Sets up NES rom. Throws an error message string
+	/// if the rom image is broken or unsupported.
 	///
 	/// # Arguments
 	/// * `rom` Rom image binary `Uint8Array`
-	pub fn set_rom(&mut self, contents: Vec<u8>) {
-		self.nes.set_rom(Rom::new(contents));
+	pub fn set_rom(&mut self, contents: Vec<u8>) -> Result<(), JsValue> {
+		self.nes.set_rom(contents).map_err(|e| JsValue::from_str(&e.to_string()))
+	}
+
+	/// Overrides the console region detected from NES 2.0 header
+	/// in `set_rom`. Call after `set_rom` and before `bootup`.
+	///
+	/// # Arguments
+	/// * `region` `Region.Ntsc`, `Region.Pal` or `Region.Dendy`
+	pub fn set_region(&mut self, region: Region) {
+		self.nes.set_region(to_region_internal(region));
+	}
+
+	/// Returns the latest abnormal event message like
+	/// "CPU jammed at $C123 (opcode $02)" since the last reset,
+	/// or `undefined` if nothing happened.
+	pub fn last_fault(&self) -> Option<String> {
+		self.nes.last_fault().map(|fault| fault.to_string())
+	}
+
+	/// Returns battery-backed RAM contents `Uint8Array` for the page
+	/// to persist game saves, or `undefined` if the cartridge has no battery.
+	pub fn battery_ram(&self) -> Option<Vec<u8>> {
+		self.nes.battery_ram().map(|ram| ram.to_vec())
+	}
+
+	/// Restores battery-backed RAM contents. Call after `set_rom`
+	/// and before `bootup`.
+	///
+	/// # Arguments
+	/// * `data` `Uint8Array` returned by `battery_ram`
+	pub fn load_battery_ram(&mut self, data: Vec<u8>) {
+		self.nes.load_battery_ram(&data);
 	}
 
 	/// Boots up
@@ -133,6 +182,28 @@ impl WasmNes {
 		self.nes.reset();
 	}
 
+	/// Enables rewind. Holding `Button::Rewind` plays backwards.
+	///
+	/// # Arguments
+	/// * `frame_interval` Frames between snapshots
+	/// * `memory_budget` Upper limit of memory for snapshots in bytes
+	pub fn enable_rewind(&mut self, frame_interval: u32, memory_budget: usize) {
+		self.nes.enable_rewind(frame_interval, memory_budget);
+	}
+
+	/// Disables rewind
+	pub fn disable_rewind(&mut self) {
+		self.nes.disable_rewind();
+	}
+
+	/// Rewinds `frames` frames. Returns false if nothing to rewind.
+	///
+	/// # Arguments
+	/// * `frames`
+	pub fn rewind(&mut self, frames: u32) -> bool {
+		self.nes.rewind(frames)
+	}
+
 	/// Executes a CPU cycle
 	pub fn step(&mut self) {
 		self.nes.step();